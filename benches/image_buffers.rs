@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use bytes::Bytes;
+use criterion::{BatchSize, BenchmarkId, Criterion, criterion_group, criterion_main, Throughput};
+use flightcode::application::data_manage::{ContentType, DataSource, DataSourceSpec, IncomingData};
+use flightcode::application::data_manage::data_bus::DataBus;
+
+// Roughly a full resolution PiCam PNG
+const FRAME_BYTES: usize = 8 * 1024 * 1024;
+
+// REST clients polling the latest image, such as the frontend and a ground station
+const REST_REQUESTS: usize = 4;
+
+const PICAM_IMAGE: DataSourceSpec = DataSourceSpec { source: DataSource::new("picam_image"), content_type: ContentType::Png, units: &[] };
+
+// How image frames were handed on before, copied into the current data map and again for every REST response
+fn copied_frame(frame: Vec<u8>, current_data: &Mutex<HashMap<&'static str, Vec<u8>>>) -> usize {
+    current_data.lock().unwrap().insert("picam_image", frame.clone());
+
+    let mut served = 0;
+    for _ in 0..REST_REQUESTS {
+        let response = current_data.lock().unwrap().get("picam_image").unwrap().clone();
+        served += response.len();
+    }
+    return served;
+}
+
+// Frames as captured into an IncomingData sample, published by the data manager and served by the REST server from the
+// latest sample of the bus
+fn published_frame(frame: Vec<u8>, data_bus: &DataBus) -> usize {
+    data_bus.publish(Arc::new(IncomingData::new(PICAM_IMAGE.source, None, Some(Bytes::from(frame)))));
+
+    let mut served = 0;
+    for _ in 0..REST_REQUESTS {
+        let response = data_bus.get_latest(PICAM_IMAGE.source).and_then(|latest| latest.file.clone()).unwrap();
+        served += response.len();
+    }
+    return served;
+}
+
+fn image_buffers(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("image_frame_handoff");
+    group.throughput(Throughput::Bytes(FRAME_BYTES as u64));
+
+    let current_data = Mutex::new(HashMap::new());
+    group.bench_with_input(BenchmarkId::new("copied", REST_REQUESTS), &REST_REQUESTS, |bencher, _| {
+        bencher.iter_batched(|| vec![0x5Au8; FRAME_BYTES], |frame| copied_frame(frame, &current_data), BatchSize::LargeInput);
+    });
+
+    let data_bus = DataBus::new(&[&PICAM_IMAGE]);
+    group.bench_with_input(BenchmarkId::new("data_bus", REST_REQUESTS), &REST_REQUESTS, |bencher, _| {
+        bencher.iter_batched(|| vec![0x5Au8; FRAME_BYTES], |frame| published_frame(frame, &data_bus), BatchSize::LargeInput);
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, image_buffers);
+criterion_main!(benches);
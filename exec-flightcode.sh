@@ -1 +1 @@
-source /home/firedrone/FlightCode/config.env && /home/firedrone/FlightCode/target/debug/flightcode
+source /home/firedrone/FlightCode/config.env && exec /home/firedrone/FlightCode/target/debug/flightcode
\ No newline at end of file
@@ -4,6 +4,14 @@ import time
 import io
 import sys
 import os
+import argparse
+
+# Image formats by the extension FlightCode passes, see CapturePiCamImages
+FORMATS = {"png": "PNG", "jpg": "JPEG", "tiff": "TIFF"}
+
+parser = argparse.ArgumentParser()
+parser.add_argument("--format", choices=FORMATS.keys(), default="png")
+args = parser.parse_args()
 
 class FakeStdOut:
     def write(self, text):
@@ -26,7 +34,7 @@ time.sleep(2)
 
 image = picam2.capture_image("main").convert('RGB')
 img_byte_arr = io.BytesIO()
-image.save(img_byte_arr, format='PNG')
+image.save(img_byte_arr, format=FORMATS[args.format])
 
 # Use original stdout in order to dump image data
 with os.fdopen(original_stdout.fileno(), "wb", closefd=False) as stdout:
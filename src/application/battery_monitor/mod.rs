@@ -1,8 +1,72 @@
-use std::sync::{Arc, Mutex};
-use std::sync::mpsc::SyncSender;
-use mavlink::common::MavMessage;
-use crate::application::data_manage::{DataSource, IncomingData};
+use std::sync::Arc;
+use std::time::Duration;
+use envconfig::Envconfig;
+use log::{error, warn};
+use crate::application::data_manage::Telemetry;
+use crate::application::data_manage::data_bus::DataBus;
+use crate::application::task_config::{BuiltTask, TaskConfig, TaskContext, TaskSpec};
+use crate::application::tasks::pib_adapter::POWER;
+use crate::application::task_registry::Dependency;
+use crate::application::timer::{TimedTask, Timer};
 
-pub fn spawn_battery_monitor(current_data_storage: Arc<Mutex<Box<[Option<IncomingData>; DataSource::COUNT]>>>, mavlink_commander: SyncSender<MavMessage>) {
+const POWER_MAX_AGE: Duration = Duration::from_secs(5);
 
-}
\ No newline at end of file
+pub const TASK_SPEC: TaskSpec = TaskSpec { name: "battery_monitor", enabled: true, interval_ms: 1000, params: &[], sources: &[], build };
+
+#[derive(Envconfig, Debug)]
+pub struct BatteryMonitorConfig {
+    #[envconfig(from = "BATTERY_WARNING_VOLTAGE", default = "21.0")]
+    pub warning_voltage: f32,
+    #[envconfig(from = "BATTERY_CRITICAL_VOLTAGE", default = "19.8")]
+    pub critical_voltage: f32
+}
+
+fn build(context: &TaskContext, config: &TaskConfig) -> Option<BuiltTask> {
+    let voltages = BatteryMonitorConfig::init_from_env().unwrap();
+
+    let power_changes = context.data_bus.subscribe(POWER.source);
+    let battery_monitor = BatteryMonitor {
+        data_bus: context.data_bus.clone(),
+        warning_voltage: voltages.warning_voltage,
+        critical_voltage: voltages.critical_voltage
+    };
+
+    // Voltages are only evaluated once the PIB is reporting power telemetry
+    let battery_monitor_timer = Timer::on_data("BatteryMonitor".to_string(), power_changes)
+        .rate_limit(config.interval())
+        .depends_on(Dependency::FreshData(POWER.source, POWER_MAX_AGE));
+
+    return Some((battery_monitor_timer, Box::from(battery_monitor)));
+}
+
+struct BatteryMonitor {
+    data_bus: Arc<DataBus>,
+    warning_voltage: f32,
+    critical_voltage: f32
+}
+
+impl BatteryMonitor {
+    fn get_voltage(&self) -> Option<f32> {
+        match self.data_bus.get_latest(POWER.source).as_ref().and_then(|current_power| current_power.telemetry.as_ref()) {
+            Some(Telemetry::Power(power)) => {Some(power.average_voltage)}
+            _ => {None}
+        }
+    }
+}
+
+impl TimedTask for BatteryMonitor {
+    fn execute(&mut self) -> () {
+        let voltage = match self.get_voltage() {
+            Some(voltage) => voltage,
+            None => {
+                return;
+            }
+        };
+
+        if voltage <= self.critical_voltage {
+            error!("Battery voltage at {:.2} V, at or below critical threshold of {:.2} V!", voltage, self.critical_voltage);
+        } else if voltage <= self.warning_voltage {
+            warn!("Battery voltage at {:.2} V, at or below warning threshold of {:.2} V", voltage, self.warning_voltage);
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use std::fs;
+use std::sync::{Mutex, OnceLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, TimeZone, Utc};
+use envconfig::Envconfig;
+use log::{info, warn};
+use serde::Serialize;
+
+// Autopilots report a time close to zero until their GPS has a fix, anything before 2020 is taken as no fix
+const GPS_TIME_MIN_US: u64 = 1_577_836_800_000_000;
+
+// Share of the difference to each new GPS time that is applied, so jitter in MAVLink delivery does not make timestamps jump
+const SLEW_GAIN: f64 = 0.1;
+
+#[derive(Envconfig, Debug)]
+pub struct ClockConfig {
+    // Timestamps step to a new GPS time instead of slewing towards it when they are further off than this
+    #[envconfig(from = "FLIGHTCODE_CLOCK_STEP_THRESHOLD_MS", default = "1000")]
+    pub step_threshold_ms: u64
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeSource {
+    // The system clock, which is wildly off on a Pi without RTC that booted without network time
+    System,
+    // GPS time of the autopilot, carried forward on the monotonic clock between updates
+    Gps
+}
+
+// When a sample was taken
+#[derive(Clone, Copy, Debug)]
+pub struct Timestamp {
+    pub utc: DateTime<Utc>,
+    // Since boot of the OBC, never affected by the wall clock being set
+    pub monotonic_ms: u64,
+    // Counts up across every source for the run
+    pub sequence: u64
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ClockQuality {
+    pub source: TimeSource,
+    pub utc: DateTime<Utc>,
+    pub monotonic_ms: u64,
+    // GPS time minus system time as of the last GPS time received
+    pub system_offset_ms: Option<i64>,
+    pub last_sync_age_ms: Option<u64>,
+    // Times the GPS time was too far off to slew towards
+    pub steps: u64
+}
+
+struct GpsDiscipline {
+    // UTC in microseconds at the start of the monotonic clock
+    epoch_us: f64,
+    system_offset_ms: i64,
+    last_sync: Instant
+}
+
+struct DisciplineState {
+    gps: Option<GpsDiscipline>,
+    steps: u64
+}
+
+pub struct Clock {
+    start: Instant,
+    // Uptime of the OBC when the clock was started
+    boot_offset: Duration,
+    step_threshold_us: f64,
+    sequence: AtomicU64,
+    state: Mutex<DisciplineState>
+}
+
+impl Clock {
+    fn new(config: &ClockConfig) -> Self {
+        Self {
+            start: Instant::now(),
+            boot_offset: get_uptime().unwrap_or_default(),
+            step_threshold_us: config.step_threshold_ms as f64 * 1000.0,
+            sequence: AtomicU64::new(0),
+            state: Mutex::new(DisciplineState { gps: None, steps: 0 })
+        }
+    }
+
+    fn get_elapsed_us(&self, instant: Instant) -> f64 {
+        return instant.saturating_duration_since(self.start).as_micros() as f64;
+    }
+
+    fn get_utc(&self, gps: Option<&GpsDiscipline>) -> DateTime<Utc> {
+        return gps.and_then(|gps| Utc.timestamp_micros((gps.epoch_us + self.get_elapsed_us(Instant::now())) as i64).single())
+            .unwrap_or_else(Utc::now);
+    }
+
+    // Best estimate of the current time in UTC
+    pub fn now(&self) -> DateTime<Utc> {
+        return self.get_utc(self.state.lock().unwrap().gps.as_ref());
+    }
+
+    pub fn get_monotonic_ms(&self) -> u64 {
+        return (self.boot_offset + self.start.elapsed()).as_millis() as u64;
+    }
+
+    pub fn stamp(&self) -> Timestamp {
+        return Timestamp {
+            utc: self.now(),
+            monotonic_ms: self.get_monotonic_ms(),
+            sequence: self.sequence.fetch_add(1, Ordering::Relaxed)
+        };
+    }
+
+    // GPS time in microseconds since the Unix epoch, as received at the given instant
+    pub fn sync_gps(&self, gps_time_us: u64, received: Instant) {
+        if gps_time_us < GPS_TIME_MIN_US {
+            return;
+        }
+
+        let estimate_us = gps_time_us as f64 - self.get_elapsed_us(received);
+        let system_offset_ms = (gps_time_us as i64 - Utc::now().timestamp_micros()) / 1000;
+
+        // Logged once the lock is released, as log lines are stamped by this clock
+        let (first_sync, step_ms) = {
+            let mut state = self.state.lock().unwrap();
+
+            let step_ms = match state.gps.as_mut() {
+                Some(gps) if (estimate_us - gps.epoch_us).abs() <= self.step_threshold_us => {
+                    gps.epoch_us += (estimate_us - gps.epoch_us) * SLEW_GAIN;
+                    gps.system_offset_ms = system_offset_ms;
+                    gps.last_sync = received;
+                    None
+                }
+                gps => Some(gps.map(|gps| ((estimate_us - gps.epoch_us) / 1000.0) as i64))
+            };
+
+            match step_ms {
+                Some(step) => {
+                    state.gps = Some(GpsDiscipline { epoch_us: estimate_us, system_offset_ms, last_sync: received });
+                    if step.is_some() {
+                        state.steps += 1;
+                    }
+                    (step.is_none(), step)
+                }
+                None => (false, None)
+            }
+        };
+
+        if first_sync {
+            info!("Clock synced to GPS time, the system clock is {} ms off", system_offset_ms);
+        }
+        if let Some(step_ms) = step_ms {
+            warn!("Clock stepped by {} ms to GPS time", step_ms);
+        }
+    }
+
+    pub fn get_quality(&self) -> ClockQuality {
+        let state = self.state.lock().unwrap();
+
+        return ClockQuality {
+            source: if state.gps.is_some() {TimeSource::Gps} else {TimeSource::System},
+            utc: self.get_utc(state.gps.as_ref()),
+            monotonic_ms: self.get_monotonic_ms(),
+            system_offset_ms: state.gps.as_ref().map(|gps| gps.system_offset_ms),
+            last_sync_age_ms: state.gps.as_ref().map(|gps| gps.last_sync.elapsed().as_millis() as u64),
+            steps: state.steps
+        };
+    }
+}
+
+fn get_uptime() -> Option<Duration> {
+    return fs::read_to_string("/proc/uptime").ok()
+        .and_then(|uptime| uptime.split_whitespace().next().and_then(|seconds| seconds.parse::<f64>().ok()))
+        .map(Duration::from_secs_f64);
+}
+
+static CLOCK: OnceLock<Clock> = OnceLock::new();
+
+// Clock shared by everything that stamps data, started on first use
+pub fn clock() -> &'static Clock {
+    return CLOCK.get_or_init(|| Clock::new(&ClockConfig::init_from_env().unwrap()));
+}
+
+#[cfg(test)]
+mod clock_tests {
+    use std::time::Instant;
+    use chrono::Utc;
+    use crate::application::clock::{Clock, ClockConfig, SLEW_GAIN, TimeSource};
+
+    #[test]
+    fn disciplined_by_gps_time() {
+        let clock = Clock::new(&ClockConfig { step_threshold_ms: 1000 });
+        assert_eq!(clock.get_quality().source, TimeSource::System);
+
+        let first = clock.stamp();
+        assert!(clock.stamp().sequence > first.sequence);
+        assert!(clock.stamp().monotonic_ms >= first.monotonic_ms);
+
+        // System clock an hour behind, GPS times without a fix are ignored
+        clock.sync_gps(1000, Instant::now());
+        assert_eq!(clock.get_quality().source, TimeSource::System);
+
+        let gps_time_us = (Utc::now().timestamp_micros() + 3_600_000_000) as u64;
+        let received = Instant::now();
+        clock.sync_gps(gps_time_us, received);
+        let quality = clock.get_quality();
+        assert_eq!(quality.source, TimeSource::Gps);
+        assert!((quality.system_offset_ms.unwrap() - 3_600_000).abs() < 100);
+        assert!(((clock.now() - Utc::now()).num_milliseconds() - 3_600_000).abs() < 100);
+
+        // Small differences are slewed towards, large ones stepped to. Received at the same instant, the GPS times differ
+        // from the current estimate by exactly the amount added
+        let get_epoch_us = || clock.state.lock().unwrap().gps.as_ref().unwrap().epoch_us;
+        let before = get_epoch_us();
+        clock.sync_gps(gps_time_us + 100_000, received);
+        assert!((get_epoch_us() - before - 100_000.0 * SLEW_GAIN).abs() < 1.0);
+        assert_eq!(clock.get_quality().steps, 0);
+
+        let before = get_epoch_us();
+        clock.sync_gps(gps_time_us + 10_000_000, received);
+        assert!((get_epoch_us() - before - (10_000_000.0 - 100_000.0 * SLEW_GAIN)).abs() < 1.0);
+        assert_eq!(clock.get_quality().steps, 1);
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::sync::{Arc, mpsc, Mutex};
+use std::sync::mpsc::{Receiver, SyncSender, TrySendError};
+use arc_swap::ArcSwapOption;
+use crate::application::data_manage::{DataSource, DataSourceSpec, IncomingData};
+
+enum Subscriber {
+    // Pending notifications coalesce into one, so a slow subscriber only learns that something changed
+    Changes(SyncSender<()>),
+    // Every sample, those arriving while the subscriber's buffer is full are skipped for it
+    Samples(SyncSender<Arc<IncomingData>>)
+}
+
+impl Subscriber {
+    // Subscribers that have gone away are dropped
+    fn send(&self, incoming_data: &Arc<IncomingData>) -> bool {
+        let result = match self {
+            Subscriber::Changes(sender) => sender.try_send(()).map_err(|err| matches!(err, TrySendError::Disconnected(_))),
+            Subscriber::Samples(sender) => sender.try_send(incoming_data.clone()).map_err(|err| matches!(err, TrySendError::Disconnected(_)))
+        };
+        return result != Err(true);
+    }
+}
+
+struct SourceChannel {
+    latest: ArcSwapOption<IncomingData>,
+    subscribers: Mutex<Vec<Subscriber>>
+}
+
+// Latest sample of every source along with subscriptions to new samples. The data manager publishes everything
+// it receives, readers of the latest sample never wait on a lock and share the sample instead of copying it
+pub struct DataBus {
+    channels: HashMap<DataSource, SourceChannel>
+}
+
+impl DataBus {
+    pub fn new(sources: &[&'static DataSourceSpec]) -> Self {
+        let channels = sources.iter()
+            .map(|spec| (spec.source, SourceChannel { latest: ArcSwapOption::empty(), subscribers: Mutex::new(Vec::new()) }))
+            .collect();
+
+        return Self { channels };
+    }
+
+    pub fn publish(&self, incoming_data: Arc<IncomingData>) {
+        let channel = match self.channels.get(&incoming_data.source) {
+            Some(channel) => channel,
+            None => {
+                return;
+            }
+        };
+
+        channel.latest.store(Some(incoming_data.clone()));
+        channel.subscribers.lock().unwrap().retain(|subscriber| subscriber.send(&incoming_data));
+    }
+
+    pub fn get_latest(&self, source: DataSource) -> Option<Arc<IncomingData>> {
+        return self.channels.get(&source).and_then(|channel| channel.latest.load_full());
+    }
+
+    // Notified whenever a new sample of the source has been published
+    pub fn subscribe(&self, source: DataSource) -> Receiver<()> {
+        let (change_sender, change_recv) = mpsc::sync_channel(1);
+        self.add_subscriber(source, Subscriber::Changes(change_sender));
+        return change_recv;
+    }
+
+    // Receives every sample of the source published from now on, buffering up to the given number
+    pub fn subscribe_samples(&self, source: DataSource, buffer: usize) -> Receiver<Arc<IncomingData>> {
+        let (sample_sender, sample_recv) = mpsc::sync_channel(buffer);
+        self.add_subscriber(source, Subscriber::Samples(sample_sender));
+        return sample_recv;
+    }
+
+    // Subscriptions to unregistered sources are never notified
+    fn add_subscriber(&self, source: DataSource, subscriber: Subscriber) {
+        if let Some(channel) = self.channels.get(&source) {
+            channel.subscribers.lock().unwrap().push(subscriber);
+        }
+    }
+}
+
+#[cfg(test)]
+mod data_bus_tests {
+    use std::sync::Arc;
+    use crate::application::data_manage::{ContentType, DataSource, DataSourceSpec, IncomingData, Telemetry};
+    use crate::application::data_manage::data_bus::DataBus;
+    use crate::application::tasks::example_task::Example;
+
+    const TELEMETRY: DataSourceSpec = DataSourceSpec { source: DataSource::new("telemetry"), content_type: ContentType::Json, units: &[] };
+    const IMAGE: DataSourceSpec = DataSourceSpec { source: DataSource::new("image"), content_type: ContentType::Png, units: &[] };
+
+    fn sample(value: &str) -> Arc<IncomingData> {
+        return Arc::new(IncomingData::new(TELEMETRY.source, Some(Telemetry::Example(Example { test: value.to_string() })), None));
+    }
+
+    #[test]
+    fn latest_values_and_subscriptions() {
+        let data_bus = DataBus::new(&[&TELEMETRY, &IMAGE]);
+        assert!(data_bus.get_latest(TELEMETRY.source).is_none());
+
+        let changes = data_bus.subscribe(TELEMETRY.source);
+        let samples = data_bus.subscribe_samples(TELEMETRY.source, 2);
+        let image_changes = data_bus.subscribe(IMAGE.source);
+
+        for value in ["a", "b", "c"] {
+            data_bus.publish(sample(value));
+        }
+
+        // Readers share the published sample
+        let latest = data_bus.get_latest(TELEMETRY.source).unwrap();
+        assert_eq!(Arc::strong_count(&latest), 2);
+        assert!(matches!(&latest.telemetry, Some(Telemetry::Example(example)) if example.test == "c"));
+
+        // Notifications coalesce, samples beyond the buffer are skipped
+        assert!(changes.try_recv().is_ok() && changes.try_recv().is_err());
+        let received: Vec<String> = samples.try_iter().map(|sample| match &sample.telemetry {
+            Some(Telemetry::Example(example)) => example.test.clone(),
+            _ => panic!("Unexpected sample")
+        }).collect();
+        assert_eq!(received, ["a", "b"]);
+        assert!(image_changes.try_recv().is_err());
+
+        // Subscribers that have gone away are dropped on the next publish
+        drop(samples);
+        data_bus.publish(sample("d"));
+        assert_eq!(data_bus.channels[&TELEMETRY.source].subscribers.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,227 @@
+use std::process::Command;
+use std::time::{Duration, Instant};
+use envconfig::Envconfig;
+use log::{error, info, warn};
+use serde::Serialize;
+use crate::application::data_manage::{ContentType, DataSource, DataSourceSpec, IncomingData, Telemetry};
+use crate::application::mission::MissionManager;
+
+const BYTES_PER_MB: u64 = 1024 * 1024;
+
+pub const STORAGE: DataSourceSpec = DataSourceSpec {
+    source: DataSource::new("storage"),
+    content_type: ContentType::Json,
+    units: &[("free_bytes", "B"), ("total_bytes", "B"), ("shed_samples", "count")]
+};
+
+#[derive(Envconfig, Debug)]
+pub struct DiskGuardConfig {
+    #[envconfig(from = "FLIGHTCODE_DISK_GUARD_ENABLED", default = "true")]
+    pub enabled: bool,
+    // Time between free space checks of the storage volume
+    #[envconfig(from = "FLIGHTCODE_DISK_CHECK_INTERVAL_MS", default = "5000")]
+    pub check_interval_ms: u64,
+    // Images are no longer stored below this much free space
+    #[envconfig(from = "FLIGHTCODE_DISK_LOW_MB", default = "1024")]
+    pub low_mb: u64,
+    // Nothing is stored below this much free space
+    #[envconfig(from = "FLIGHTCODE_DISK_CRITICAL_MB", default = "256")]
+    pub critical_mb: u64,
+    // Deletes the oldest offloaded missions while free space is low
+    #[envconfig(from = "FLIGHTCODE_DISK_PRUNE_OFFLOADED", default = "false")]
+    pub prune_offloaded: bool
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct StorageStatus {
+    pub free_bytes: u64,
+    pub total_bytes: u64,
+    pub level: &'static str,
+    pub shed_samples: u64
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StorageLevel {
+    Normal,
+    Low,
+    Critical
+}
+
+impl StorageLevel {
+    fn from_free_bytes(free_bytes: u64, config: &DiskGuardConfig) -> Self {
+        if free_bytes < config.critical_mb * BYTES_PER_MB {
+            return StorageLevel::Critical;
+        }
+        if free_bytes < config.low_mb * BYTES_PER_MB {
+            return StorageLevel::Low;
+        }
+        return StorageLevel::Normal;
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            StorageLevel::Normal => {"normal"}
+            StorageLevel::Low => {"low"}
+            StorageLevel::Critical => {"critical"}
+        }
+    }
+
+    // Images are shed first as they fill the card fastest, telemetry is kept until space is critical
+    fn allows(&self, content_type: ContentType) -> bool {
+        match self {
+            StorageLevel::Normal => {true}
+            StorageLevel::Low => {content_type == ContentType::Json}
+            StorageLevel::Critical => {false}
+        }
+    }
+}
+
+pub struct DiskGuard {
+    config: DiskGuardConfig,
+    check_interval: Duration,
+    last_check: Option<Instant>,
+    level: StorageLevel,
+    shed_samples: u64
+}
+
+impl DiskGuard {
+    pub fn new(config: DiskGuardConfig) -> Self {
+        Self {
+            check_interval: Duration::from_millis(config.check_interval_ms),
+            config,
+            last_check: None,
+            level: StorageLevel::Normal,
+            shed_samples: 0
+        }
+    }
+
+    // Samples that are not allowed at the current level are still kept as current data, only their storage is skipped
+    pub fn allows(&mut self, content_type: ContentType) -> bool {
+        if self.level.allows(content_type) {
+            return true;
+        }
+
+        self.shed_samples += 1;
+        return false;
+    }
+
+    // Measures free space once the check interval has passed, returning the storage status to be stored like any other sample
+    pub fn check(&mut self, storage_dir: &str, mission_manager: &MissionManager) -> Option<IncomingData> {
+        if self.last_check.is_some_and(|last_check| last_check.elapsed().lt(&self.check_interval)) {
+            return None;
+        }
+        self.last_check = Option::from(Instant::now());
+
+        let (mut free_bytes, mut total_bytes) = match get_free_space(storage_dir) {
+            Ok(space) => space,
+            Err(err) => {
+                warn!("Failed to check free space of {}: {}", storage_dir, err);
+                return None;
+            }
+        };
+
+        if self.config.prune_offloaded {
+            while free_bytes < self.config.low_mb * BYTES_PER_MB {
+                match mission_manager.prune_oldest_offloaded() {
+                    Some(mission_dir) => {
+                        info!("Pruned offloaded mission {} to free storage space", mission_dir);
+                    }
+                    None => {
+                        break;
+                    }
+                }
+
+                match get_free_space(storage_dir) {
+                    Ok(space) => (free_bytes, total_bytes) = space,
+                    Err(_) => {
+                        break;
+                    }
+                }
+            }
+        }
+
+        self.set_level(StorageLevel::from_free_bytes(free_bytes, &self.config), free_bytes);
+
+        let status = StorageStatus {
+            free_bytes,
+            total_bytes,
+            level: self.level.name(),
+            shed_samples: self.shed_samples
+        };
+
+        return Option::from(IncomingData::new(STORAGE.source, Option::from(Telemetry::Storage(status)), None));
+    }
+
+    fn set_level(&mut self, level: StorageLevel, free_bytes: u64) {
+        if level == self.level {
+            return;
+        }
+
+        let free_mb = free_bytes / BYTES_PER_MB;
+        match level {
+            StorageLevel::Normal => {
+                info!("Storage space recovered, {} MB free, storing all data again", free_mb);
+            }
+            StorageLevel::Low => {
+                warn!("Storage space low, {} MB free, images are no longer stored", free_mb);
+            }
+            StorageLevel::Critical => {
+                error!("Storage space critical, {} MB free, no data is stored", free_mb);
+            }
+        }
+
+        self.level = level;
+    }
+}
+
+// Available and total bytes of the volume holding the given directory
+fn get_free_space(path: &str) -> Result<(u64, u64), String> {
+    let output = Command::new("df")
+        .arg("-B1")
+        .arg("--output=avail,size")
+        .arg(path)
+        .output()
+        .map_err(|err| err.to_string())?;
+
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+
+    return parse_df_output(&String::from_utf8_lossy(&output.stdout));
+}
+
+fn parse_df_output(output: &str) -> Result<(u64, u64), String> {
+    let values: Vec<u64> = output.lines().nth(1)
+        .ok_or("Missing df output")?
+        .split_whitespace()
+        .map(|value| value.parse::<u64>().map_err(|err| err.to_string()))
+        .collect::<Result<Vec<u64>, String>>()?;
+
+    return match values[..] {
+        [free_bytes, total_bytes] => Ok((free_bytes, total_bytes)),
+        _ => Err(format!("Unexpected df output: {}", output.trim()))
+    };
+}
+
+#[cfg(test)]
+mod disk_guard_tests {
+    use crate::application::data_manage::ContentType;
+    use crate::application::data_manage::disk_guard::{DiskGuardConfig, parse_df_output, StorageLevel};
+
+    #[test]
+    fn images_are_shed_before_telemetry() {
+        let config = DiskGuardConfig { enabled: true, check_interval_ms: 5000, low_mb: 1024, critical_mb: 256, prune_offloaded: false };
+
+        let normal = StorageLevel::from_free_bytes(2048 * 1024 * 1024, &config);
+        let low = StorageLevel::from_free_bytes(512 * 1024 * 1024, &config);
+        let critical = StorageLevel::from_free_bytes(100 * 1024 * 1024, &config);
+
+        assert_eq!((normal, low, critical), (StorageLevel::Normal, StorageLevel::Low, StorageLevel::Critical));
+        assert!(normal.allows(ContentType::Png) && normal.allows(ContentType::Json));
+        assert!(!low.allows(ContentType::Png) && low.allows(ContentType::Json));
+        assert!(!critical.allows(ContentType::Png) && !critical.allows(ContentType::Json));
+
+        assert_eq!(parse_df_output("   Avail     1B-blocks\n1073741824 31268536320\n"), Ok((1073741824, 31268536320)));
+        assert!(parse_df_output("Avail 1B-blocks\n").is_err());
+    }
+}
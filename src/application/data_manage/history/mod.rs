@@ -0,0 +1,119 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use chrono::{DateTime, FixedOffset};
+use json::{JsonValue, object};
+use crate::mission_data::get_segments;
+
+pub const LIMIT_DEFAULT: usize = 1000;
+pub const LIMIT_MAX: usize = 10000;
+
+// Records within [from, to], of which every decimate-th is kept, paginated by offset and limit
+pub struct HistoryQuery {
+    pub from: Option<DateTime<FixedOffset>>,
+    pub to: Option<DateTime<FixedOffset>>,
+    pub decimate: usize,
+    pub offset: usize,
+    pub limit: usize
+}
+
+impl HistoryQuery {
+    // Accepts RFC 3339 timestamps, a + in the offset that was decoded to a space in the query string is restored
+    pub fn parse_time(time: &str) -> Result<DateTime<FixedOffset>, String> {
+        return DateTime::parse_from_rfc3339(time)
+            .or_else(|_| DateTime::parse_from_rfc3339(&time.replace(' ', "+")))
+            .map_err(|_| format!("Invalid time {}, expected RFC 3339 such as 2024-05-01T14:30:00Z", time));
+    }
+}
+
+// Reads back the stored telemetry of a source, returning the matching records along with the offset of the next page
+pub fn query_history(source_dir: &Path, source_name: &str, query: &HistoryQuery) -> Result<JsonValue, String> {
+    let mut records = JsonValue::new_array();
+    let mut matched = 0;
+    let mut more = false;
+
+    'segments: for (_, segment) in get_segments(source_dir, source_name) {
+        let file = File::open(&segment).map_err(|err| format!("Failed to read {}: {}", segment.display(), err))?;
+
+        for line in BufReader::new(file).lines() {
+            // A torn record at the end of a segment still being written is skipped
+            let record = match line.ok().and_then(|line| json::parse(&line).ok()) {
+                Some(record) => record,
+                None => continue
+            };
+            let time_stamp = match record["timestamp"].as_str().and_then(|time| DateTime::parse_from_rfc3339(time).ok()) {
+                Some(time_stamp) => time_stamp,
+                None => continue
+            };
+
+            // Timestamps go back when the wall clock is stepped, so records past the range do not end the search
+            if query.from.is_some_and(|from| time_stamp < from) || query.to.is_some_and(|to| time_stamp > to) {
+                continue;
+            }
+
+            let index = matched;
+            matched += 1;
+            if index % query.decimate != 0 || index / query.decimate < query.offset {
+                continue;
+            }
+
+            if records.len() == query.limit {
+                more = true;
+                break 'segments;
+            }
+            records.push(record).unwrap();
+        }
+    }
+
+    return Ok(object!{
+        records: records,
+        next_offset: if more {Some(query.offset + query.limit)} else {None}
+    });
+}
+
+#[cfg(test)]
+mod history_tests {
+    use chrono::{DateTime, Duration, SecondsFormat};
+    use json::object;
+    use crate::application::data_manage::history::{HistoryQuery, query_history};
+    use crate::application::data_manage::jsonl_stream::{JsonlStream, TelemetryStorageConfig};
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn ranges_pages_and_decimation() {
+        let source_dir = TempDir::new("history");
+
+        let config = TelemetryStorageConfig { sync_interval_ms: 1000, segment_max_bytes: 200, segment_max_seconds: 900 };
+        let mut stream = JsonlStream::open(&source_dir, "power", &config).unwrap();
+        let start = DateTime::parse_from_rfc3339("2024-05-01T14:30:00+02:00").unwrap();
+        for sample in 0..20 {
+            stream.write(&object!{
+                timestamp: (start + Duration::seconds(sample)).to_rfc3339_opts(SecondsFormat::Millis, false),
+                data: object!{ sample: sample }
+            }.dump()).unwrap();
+        }
+        // Wall clock stepped back by a GPS fix, the record is still found after the later ones
+        stream.write(&object!{
+            timestamp: (start + Duration::seconds(10)).to_rfc3339_opts(SecondsFormat::Millis, false),
+            data: object!{ sample: 20 }
+        }.dump()).unwrap();
+        drop(stream);
+
+        let samples = |query: &HistoryQuery| {
+            let history = query_history(&source_dir, "power", query).unwrap();
+            let samples: Vec<i64> = history["records"].members().map(|record| record["data"]["sample"].as_i64().unwrap()).collect();
+            (samples, history["next_offset"].as_usize())
+        };
+
+        let mut query = HistoryQuery { from: Some(start + Duration::seconds(5)), to: Some(start + Duration::seconds(14)), decimate: 1, offset: 0, limit: 4 };
+        assert_eq!(samples(&query), (vec![5, 6, 7, 8], Some(4)));
+        query.offset = 8;
+        assert_eq!(samples(&query), (vec![13, 14, 20], None));
+
+        let query = HistoryQuery { from: None, to: None, decimate: 5, offset: 1, limit: 100 };
+        assert_eq!(samples(&query), (vec![5, 10, 15, 20], None));
+
+        assert!(HistoryQuery::parse_time("2024-05-01T14:30:00 02:00").is_ok());
+        assert!(HistoryQuery::parse_time("yesterday").is_err());
+    }
+}
@@ -0,0 +1,317 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use envconfig::Envconfig;
+use log::{error, warn};
+use serde::Serialize;
+use crate::application::data_manage::{ContentType, DataSource, DataSourceSpec, IncomingData, Telemetry};
+use crate::mission_data::integrity::{get_digest, IntegrityDigests};
+
+// Recently written images that are fsynced when moving on to another directory and on shutdown, anything older has long
+// been written back by the kernel
+const UNSYNCED_IMAGES_MAX: usize = 32;
+
+pub const IMAGE_WRITER: DataSourceSpec = DataSourceSpec {
+    source: DataSource::new("image_writer"),
+    content_type: ContentType::Json,
+    units: &[("written", "count"), ("dropped", "count"), ("failed", "count"), ("queued", "count"), ("written_bytes", "B"),
+             ("mean_write_ms", "ms"), ("max_write_ms", "ms")]
+};
+
+#[derive(Envconfig, Debug)]
+pub struct ImageWriterConfig {
+    // Images waiting to be written, further images are dropped while it is full
+    #[envconfig(from = "FLIGHTCODE_IMAGE_WRITE_QUEUE", default = "4")]
+    pub queue_capacity: usize,
+    // Time between samples of the write counts and latencies, 0 disables them
+    #[envconfig(from = "FLIGHTCODE_IMAGE_WRITE_STATS_INTERVAL_MS", default = "5000")]
+    pub stats_interval_ms: u64
+}
+
+// Counts since startup, latencies of the images written since the previous sample
+#[derive(Clone, Debug, Serialize)]
+pub struct ImageWriteStats {
+    pub written: u64,
+    pub dropped: u64,
+    pub failed: u64,
+    pub queued: usize,
+    pub written_bytes: u64,
+    pub mean_write_ms: Option<f64>,
+    pub max_write_ms: Option<f64>
+}
+
+// Image file of a sample, written to the path without extension within the storage directory
+pub struct ImageWrite {
+    pub storage_dir: String,
+    pub file: String,
+    pub extension: String,
+    pub content_type: ContentType,
+    pub incoming_data: Arc<IncomingData>
+}
+
+#[derive(Default)]
+struct WriteMetrics {
+    written: u64,
+    dropped: u64,
+    failed: u64,
+    queued: usize,
+    written_bytes: u64,
+    interval_writes: u64,
+    interval_write_ms: f64,
+    interval_max_write_ms: f64
+}
+
+// Writes image files on a thread of its own, so telemetry records are never stored behind a slow image write.
+// Images arriving while the queue is full are dropped rather than holding up the data manager
+pub struct ImageWriter {
+    sender: SyncSender<ImageWrite>,
+    metrics: Arc<Mutex<WriteMetrics>>,
+    integrity: Arc<IntegrityDigests>,
+    join_handle: JoinHandle<Result<(), String>>,
+    stats_interval: Option<Duration>,
+    last_stats: Instant
+}
+
+impl ImageWriter {
+    // Written images are recorded in the integrity digests of their storage directory
+    pub fn spawn(config: &ImageWriterConfig, integrity: Arc<IntegrityDigests>) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(config.queue_capacity.max(1));
+        let metrics = Arc::new(Mutex::new(WriteMetrics::default()));
+
+        let metrics_in_thread = metrics.clone();
+        let integrity_in_thread = integrity.clone();
+        let join_handle = thread::spawn(move || {
+            return image_writer_loop(receiver, metrics_in_thread, integrity_in_thread);
+        });
+
+        return Self {
+            sender,
+            metrics,
+            integrity,
+            join_handle,
+            stats_interval: (config.stats_interval_ms > 0).then(|| Duration::from_millis(config.stats_interval_ms)),
+            last_stats: Instant::now()
+        };
+    }
+
+    pub fn queue(&self, image_write: ImageWrite) {
+        // Counted ahead of sending, the writer may be done with it before this returns
+        self.metrics.lock().unwrap().queued += 1;
+        self.integrity.begin_write(&image_write.storage_dir);
+
+        match self.sender.try_send(image_write) {
+            Ok(()) => {}
+            Err(TrySendError::Full(image_write)) => {
+                self.integrity.end_write(&image_write.storage_dir);
+                let mut metrics = self.metrics.lock().unwrap();
+                metrics.queued -= 1;
+                metrics.dropped += 1;
+                warn!("Image writer is behind, dropped {} image", image_write.incoming_data.source);
+            }
+            Err(TrySendError::Disconnected(image_write)) => {
+                self.integrity.end_write(&image_write.storage_dir);
+                let mut metrics = self.metrics.lock().unwrap();
+                metrics.queued -= 1;
+                metrics.failed += 1;
+                error!("Image writer has stopped, failed to store {} image", image_write.incoming_data.source);
+            }
+        }
+    }
+
+    // Write counts and latencies, once the stats interval has passed
+    pub fn get_stats_if_due(&mut self) -> Option<IncomingData> {
+        if self.stats_interval.is_none() || self.last_stats.elapsed().lt(&self.stats_interval.unwrap()) {
+            return None;
+        }
+        self.last_stats = Instant::now();
+
+        let mut metrics = self.metrics.lock().unwrap();
+        let stats = ImageWriteStats {
+            written: metrics.written,
+            dropped: metrics.dropped,
+            failed: metrics.failed,
+            queued: metrics.queued,
+            written_bytes: metrics.written_bytes,
+            mean_write_ms: (metrics.interval_writes > 0).then(|| metrics.interval_write_ms / metrics.interval_writes as f64),
+            max_write_ms: (metrics.interval_writes > 0).then_some(metrics.interval_max_write_ms)
+        };
+        metrics.interval_writes = 0;
+        metrics.interval_write_ms = 0.0;
+        metrics.interval_max_write_ms = 0.0;
+
+        return Option::from(IncomingData::new(IMAGE_WRITER.source, Option::from(Telemetry::ImageWriter(stats)), None));
+    }
+
+    // Writes whatever is still queued and syncs the images written last
+    pub fn stop(self) -> Result<(), String> {
+        drop(self.sender);
+
+        return match self.join_handle.join() {
+            Ok(result) => result,
+            Err(_) => Err("Image writer panicked".to_string())
+        };
+    }
+}
+
+fn image_writer_loop(receiver: Receiver<ImageWrite>, metrics: Arc<Mutex<WriteMetrics>>, integrity: Arc<IntegrityDigests>) -> Result<(), String> {
+    let mut unsynced_images: VecDeque<String> = VecDeque::new();
+    let mut current_dir: Option<String> = None;
+
+    for image_write in receiver {
+        // Images of a mission are synced once images start going to the next mission or the idle area
+        if current_dir.as_ref() != Some(&image_write.storage_dir) {
+            if let Err(err) = sync_images(&mut unsynced_images) {
+                warn!("{}", err);
+            }
+            current_dir = Some(image_write.storage_dir.clone());
+        }
+
+        let result = write_image(&image_write, &integrity);
+        integrity.end_write(&image_write.storage_dir);
+
+        let mut metrics = metrics.lock().unwrap();
+        metrics.queued -= 1;
+        match result {
+            Ok((path, bytes, write_ms)) => {
+                metrics.written += 1;
+                metrics.written_bytes += bytes as u64;
+                metrics.interval_writes += 1;
+                metrics.interval_write_ms += write_ms;
+                metrics.interval_max_write_ms = metrics.interval_max_write_ms.max(write_ms);
+
+                unsynced_images.push_back(path);
+                if unsynced_images.len() > UNSYNCED_IMAGES_MAX {
+                    unsynced_images.pop_front();
+                }
+            }
+            Err(err) => {
+                metrics.failed += 1;
+                error!("{}", err);
+            }
+        }
+    }
+
+    return sync_images(&mut unsynced_images);
+}
+
+// JPEGs carry their geotag as embedded EXIF and XMP and TIFFs as XMP, other files get a sidecar JSON file.
+// Returns the path and size of the written image along with the time taken to write it, geotagging is not included
+fn write_image(image_write: &ImageWrite, integrity: &IntegrityDigests) -> Result<(String, usize, f64), String> {
+    let incoming_data = &image_write.incoming_data;
+    let image = incoming_data.file.as_ref()
+        .ok_or(format!("No image to write for {}", incoming_data.source))?;
+    let mut sidecar = None;
+
+    let tagged = match (incoming_data.geotag.as_ref(), image_write.content_type) {
+        (Some(geotag), ContentType::Jpeg) => Some(geotag.embed_in_jpeg(image)),
+        (Some(geotag), ContentType::Tiff) => Some(geotag.embed_in_tiff(image)),
+        (Some(geotag), _) => {
+            sidecar = serde_json::to_string(geotag).ok();
+            None
+        }
+        (None, _) => None
+    };
+    let tagged = match tagged {
+        Some(Ok(tagged)) => Some(tagged),
+        Some(Err(err)) => {
+            warn!("Failed to geotag {} image: {}", incoming_data.source, err);
+            None
+        }
+        None => None
+    };
+    let image = tagged.as_deref().unwrap_or(image);
+
+    let path = format!("{}.{}", image_write.file, image_write.extension);
+    let started = Instant::now();
+    fs::write(&path, image)
+        .map_err(|err| format!("Failed to write to file {}: {}", path, err))?;
+    let write_ms = started.elapsed().as_secs_f64() * 1000.0;
+    integrity.record(&image_write.storage_dir, Path::new(&path), get_digest(image));
+
+    if let Some(sidecar) = sidecar {
+        let sidecar_path = format!("{}.json", image_write.file);
+        match fs::write(&sidecar_path, &sidecar) {
+            Ok(()) => integrity.record(&image_write.storage_dir, Path::new(&sidecar_path), get_digest(sidecar.as_bytes())),
+            Err(err) => error!("Failed to write geotag of {}: {}", image_write.file, err)
+        }
+    }
+
+    return Ok((path, image.len(), write_ms));
+}
+
+fn sync_images(unsynced_images: &mut VecDeque<String>) -> Result<(), String> {
+    let failures: Vec<String> = unsynced_images.drain(..)
+        .filter_map(|image| File::open(&image).and_then(|file| file.sync_all()).err().map(|err| format!("Failed to sync {}: {}", image, err)))
+        .collect();
+
+    if failures.is_empty() {
+        return Ok(());
+    }
+    return Err(failures.join(", "));
+}
+
+#[cfg(test)]
+mod image_writer_tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+    use bytes::Bytes;
+    use crate::application::data_manage::{ContentType, IncomingData, Telemetry};
+    use crate::application::data_manage::image_writer::{ImageWrite, ImageWriter, ImageWriterConfig};
+    use crate::mission_data::integrity::IntegrityDigests;
+    use crate::application::geotag::GeoTag;
+    use crate::application::tasks::capture_ircam_images::THERMAL_IMAGE;
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn writes_images_and_reports_latency() {
+        let storage_dir = TempDir::new("image-writer");
+
+        let mut image_writer = ImageWriter::spawn(&ImageWriterConfig { queue_capacity: 8, stats_interval_ms: 1 }, Arc::new(IntegrityDigests::new()));
+        for index in 0..3 {
+            let mut incoming_data = IncomingData::new(THERMAL_IMAGE.source, None, Some(Bytes::from(vec![index as u8; 100])));
+            if index == 0 {
+                incoming_data = incoming_data.with_geotag(GeoTag { latitude: Some(-33.7), ..Default::default() });
+            }
+            image_writer.queue(ImageWrite {
+                storage_dir: storage_dir.display().to_string(),
+                file: format!("{}/{}", storage_dir.display(), index),
+                extension: "png".to_string(),
+                content_type: ContentType::Png,
+                incoming_data: Arc::new(incoming_data)
+            });
+        }
+
+        // Missing directories are counted as failed writes
+        image_writer.queue(ImageWrite {
+            storage_dir: storage_dir.display().to_string(),
+            file: format!("{}/missing/3", storage_dir.display()),
+            extension: "png".to_string(),
+            content_type: ContentType::Png,
+            incoming_data: Arc::new(IncomingData::new(THERMAL_IMAGE.source, None, Some(Bytes::from(vec![0u8; 100]))))
+        });
+
+        while image_writer.metrics.lock().unwrap().queued > 0 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        std::thread::sleep(Duration::from_millis(2));
+
+        let stats = match image_writer.get_stats_if_due().and_then(|stats| stats.telemetry) {
+            Some(Telemetry::ImageWriter(stats)) => stats,
+            _ => panic!("Expected image writer stats")
+        };
+        assert_eq!((stats.written, stats.failed, stats.dropped, stats.written_bytes), (3, 1, 0, 300));
+        assert!(stats.max_write_ms.unwrap() >= stats.mean_write_ms.unwrap());
+        image_writer.stop().unwrap();
+
+        assert_eq!(std::fs::read(storage_dir.join("2.png")).unwrap(), vec![2u8; 100]);
+        assert!(std::fs::read_to_string(storage_dir.join("0.json")).unwrap().contains("-33.7"));
+        assert!(!storage_dir.join("1.json").exists());
+    }
+}
@@ -0,0 +1,250 @@
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use envconfig::Envconfig;
+use crate::mission_data::{get_segments, SEGMENT_EXTENSION};
+use crate::mission_data::integrity::{FileDigest, FileHasher};
+
+#[derive(Envconfig, Debug)]
+pub struct TelemetryStorageConfig {
+    // Time between fsyncs of each stream, 0 syncs every record
+    #[envconfig(from = "FLIGHTCODE_TELEMETRY_SYNC_INTERVAL_MS", default = "1000")]
+    pub sync_interval_ms: u64,
+    #[envconfig(from = "FLIGHTCODE_TELEMETRY_SEGMENT_MAX_BYTES", default = "16777216")]
+    pub segment_max_bytes: u64,
+    #[envconfig(from = "FLIGHTCODE_TELEMETRY_SEGMENT_MAX_SECONDS", default = "900")]
+    pub segment_max_seconds: u64
+}
+
+// Appends one JSON record per line to <source>-<index>.jsonl segments within the source directory.
+// Records are never rewritten, so a power cut can at most leave a torn last line, see recover_segment
+pub struct JsonlStream {
+    source_dir: PathBuf,
+    source_name: String,
+    sync_interval: Duration,
+    segment_max_bytes: u64,
+    segment_max_age: Duration,
+    segment_index: u32,
+    file: File,
+    size: u64,
+    opened: Instant,
+    last_sync: Instant,
+    unsynced: bool,
+    // Running digest of the current segment and final digests of the segments written before it
+    hasher: FileHasher,
+    completed: Vec<(PathBuf, FileDigest)>
+}
+
+impl JsonlStream {
+    // Continues numbering after the segments already in the directory, existing segments are never appended to
+    pub fn open(source_dir: &Path, source_name: &str, config: &TelemetryStorageConfig) -> io::Result<Self> {
+        let segment_index = get_segments(source_dir, source_name).last().map_or(0, |(index, _)| index + 1);
+        let file = create_segment(source_dir, source_name, segment_index)?;
+
+        return Ok(Self {
+            source_dir: source_dir.to_path_buf(),
+            source_name: source_name.to_string(),
+            sync_interval: Duration::from_millis(config.sync_interval_ms),
+            segment_max_bytes: config.segment_max_bytes,
+            segment_max_age: Duration::from_secs(config.segment_max_seconds),
+            segment_index,
+            file,
+            size: 0,
+            opened: Instant::now(),
+            last_sync: Instant::now(),
+            unsynced: false,
+            hasher: FileHasher::default(),
+            completed: Vec::new()
+        });
+    }
+
+    // Takes a record serialized as a single line of JSON
+    pub fn write(&mut self, record: &str) -> io::Result<()> {
+        if self.size > 0 && (self.size >= self.segment_max_bytes || self.opened.elapsed().ge(&self.segment_max_age)) {
+            self.rotate()?;
+        }
+
+        // Written with a single call so records of a healthy run are never split
+        let line = format!("{}\n", record);
+        self.file.write_all(line.as_bytes())?;
+        self.hasher.update(line.as_bytes());
+        self.size += line.len() as u64;
+        self.unsynced = true;
+
+        return self.sync_if_due();
+    }
+
+    pub fn sync_if_due(&mut self) -> io::Result<()> {
+        if self.unsynced && self.last_sync.elapsed().ge(&self.sync_interval) {
+            return self.sync();
+        }
+        return Ok(());
+    }
+
+    // Flush everything written so far down to the storage device
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.file.sync_data()?;
+        self.last_sync = Instant::now();
+        self.unsynced = false;
+        return Ok(());
+    }
+
+    // Every segment written by this stream along with its contents so far
+    pub fn get_digests(&self) -> Vec<(PathBuf, FileDigest)> {
+        let current = (get_segment_path(&self.source_dir, &self.source_name, self.segment_index), self.hasher.get_digest());
+        return self.completed.iter().cloned().chain([current]).collect();
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.sync()?;
+
+        self.file = create_segment(&self.source_dir, &self.source_name, self.segment_index + 1)?;
+        self.completed.push((get_segment_path(&self.source_dir, &self.source_name, self.segment_index), self.hasher.get_digest()));
+        self.hasher = FileHasher::default();
+        self.segment_index += 1;
+        self.size = 0;
+        self.opened = Instant::now();
+
+        return Ok(());
+    }
+}
+
+fn get_segment_path(source_dir: &Path, source_name: &str, index: u32) -> PathBuf {
+    return source_dir.join(format!("{}-{:05}.{}", source_name, index, SEGMENT_EXTENSION));
+}
+
+fn create_segment(source_dir: &Path, source_name: &str, index: u32) -> io::Result<File> {
+    return OpenOptions::new().create_new(true).append(true).open(get_segment_path(source_dir, source_name, index));
+}
+
+// Truncates a segment after its last intact record, returning the number of bytes removed.
+// Segments ending in a newline are left alone without being read, anything else was interrupted mid write
+pub fn recover_segment(path: &Path) -> io::Result<u64> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let size = file.metadata()?.len();
+    if size == 0 {
+        return Ok(0);
+    }
+
+    let mut last_byte = [0u8; 1];
+    file.seek(SeekFrom::End(-1))?;
+    file.read_exact(&mut last_byte)?;
+    if last_byte[0] == b'\n' {
+        return Ok(0);
+    }
+
+    let mut contents = Vec::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_end(&mut contents)?;
+
+    // Blocks of a file being written during a power cut can also come back zero filled, so every record up to the
+    // first one that fails to parse is kept rather than just cutting the unterminated tail
+    let mut intact_len = 0;
+    for line in contents.split_inclusive(|byte| *byte == b'\n') {
+        let record = std::str::from_utf8(line).ok()
+            .filter(|line| line.ends_with('\n'))
+            .and_then(|line| json::parse(line).ok());
+        if record.is_none() {
+            break;
+        }
+        intact_len += line.len();
+    }
+
+    file.set_len(intact_len as u64)?;
+    file.sync_all()?;
+
+    return Ok(size - intact_len as u64);
+}
+
+// Recovers every segment found below the given directory, returning the segments that had torn records removed
+pub fn recover_segments(dir: &Path) -> Vec<(PathBuf, io::Result<u64>)> {
+    let mut recovered = Vec::new();
+
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            recovered.extend(recover_segments(&path));
+        } else if path.extension().is_some_and(|extension| extension == SEGMENT_EXTENSION) {
+            let result = recover_segment(&path);
+            if !matches!(result, Ok(0)) {
+                recovered.push((path, result));
+            }
+        }
+    }
+
+    return recovered;
+}
+
+// Writes the segments of a source as a single {"data_stream": [...]} document, as stored before JSON Lines segments.
+// Returns the number of records converted
+pub fn convert_to_legacy(source_dir: &Path, source_name: &str, output: &Path) -> io::Result<usize> {
+    let mut writer = io::BufWriter::new(File::create(output)?);
+    let mut records = 0;
+
+    writer.write_all(b"{\"data_stream\": [")?;
+    for (_, segment) in get_segments(source_dir, source_name) {
+        for line in fs::read_to_string(&segment)?.lines().filter(|line| !line.trim().is_empty()) {
+            let delimiter = if records == 0 {"\n"} else {",\n"};
+            writer.write_all(delimiter.as_bytes())?;
+            writer.write_all(line.as_bytes())?;
+            records += 1;
+        }
+    }
+    writer.write_all(b"\n]}")?;
+    writer.flush()?;
+
+    return Ok(records);
+}
+
+#[cfg(test)]
+mod jsonl_stream_tests {
+    use std::fs;
+    use std::io::Write;
+    use json::object;
+    use crate::mission_data::get_segments;
+    use crate::mission_data::integrity::get_digest;
+    use crate::application::data_manage::jsonl_stream::{convert_to_legacy, JsonlStream, recover_segment, TelemetryStorageConfig};
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn segments_rotate_and_recover() {
+        let source_dir = TempDir::new("jsonl");
+
+        let config = TelemetryStorageConfig { sync_interval_ms: 0, segment_max_bytes: 50, segment_max_seconds: 900 };
+        let mut stream = JsonlStream::open(&source_dir, "power", &config).unwrap();
+        for sample in 0..6 {
+            stream.write(&object!{ sample: sample, voltage: 22.5 }.dump()).unwrap();
+        }
+        let digests = stream.get_digests();
+        drop(stream);
+
+        let segments = get_segments(&source_dir, "power");
+        assert_eq!(segments.iter().map(|(index, _)| *index).collect::<Vec<u32>>(), vec![0, 1, 2]);
+
+        // Digests are kept up to date with the segments as written
+        assert_eq!(digests.len(), 3);
+        for ((digest_path, digest), (_, segment_path)) in digests.iter().zip(segments.iter()) {
+            assert_eq!(digest_path, segment_path);
+            assert_eq!(*digest, get_digest(&fs::read(segment_path).unwrap()));
+        }
+        assert_eq!(recover_segment(&segments[2].1).unwrap(), 0);
+
+        // Torn record followed by zero filled blocks
+        let mut last_segment = fs::OpenOptions::new().append(true).open(&segments[2].1).unwrap();
+        last_segment.write_all(b"{\"sample\":6,\"volt\0\0\0\0").unwrap();
+        assert_eq!(recover_segment(&segments[2].1).unwrap(), 21);
+        assert!(fs::read_to_string(&segments[2].1).unwrap().ends_with("}\n"));
+
+        // Reopening continues with a new segment
+        JsonlStream::open(&source_dir, "power", &config).unwrap().write(&object!{ sample: 6, voltage: 22.4 }.dump()).unwrap();
+
+        let legacy = source_dir.join("power.json");
+        assert_eq!(convert_to_legacy(&source_dir, "power", &legacy).unwrap(), 7);
+        let legacy = json::parse(&fs::read_to_string(&legacy).unwrap()).unwrap();
+        assert_eq!(legacy["data_stream"].len(), 7);
+        assert_eq!(legacy["data_stream"][6]["sample"], 6);
+    }
+}
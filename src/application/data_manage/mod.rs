@@ -1,185 +1,492 @@
-mod live_json_stream;
+pub mod data_bus;
+pub mod disk_guard;
+pub mod history;
+pub mod image_writer;
+pub mod jsonl_stream;
+pub mod recent_samples;
+pub mod storage_queue;
+pub mod storage_sampling;
 
-use std::sync::mpsc::Receiver;
-use std::{fs, thread};
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread;
+use std::collections::HashMap;
+use std::fmt;
 use std::path::Path;
-use std::slice::Iter;
-use std::sync::{Arc, Mutex};
-use chrono::{DateTime, Local};
+use std::time::{Duration, Instant};
+use std::sync::Arc;
+use bytes::Bytes;
+use chrono::{DateTime, Local, Utc};
 use envconfig::Envconfig;
 use json::{JsonValue, object};
-use crate::application::data_manage::live_json_stream::LiveJsonStream;
+use log::{error, info, warn};
+use mavlink::common::{ATTITUDE_DATA, GLOBAL_POSITION_INT_DATA};
+use serde::Serialize;
+use crate::application::clock::clock;
+use crate::application::data_manage::data_bus::DataBus;
+use crate::application::data_manage::disk_guard::{DiskGuard, StorageStatus};
+use crate::application::data_manage::image_writer::{ImageWrite, ImageWriter, ImageWriteStats};
+use crate::application::data_manage::jsonl_stream::{JsonlStream, TelemetryStorageConfig};
+use crate::application::data_manage::recent_samples::RecentSamples;
+use crate::application::data_manage::storage_queue::{QueueStats, StorageReceiver};
+use crate::application::data_manage::storage_sampling::StorageSampling;
+use crate::application::geotag::GeoTag;
+use crate::application::mission::MissionManager;
+use crate::application::shutdown::ShutdownHandle;
+use crate::application::tasks::example_task::Example;
+use crate::application::tasks::mavlink_adapter::Heartbeat;
+use crate::application::tasks::obc_telem::ObcTelemetry;
+use crate::application::tasks::pib_adapter::{Environmental, Power, Temperature};
+use crate::mission_data::integrity::IntegrityDigests;
 
-#[derive(Envconfig)]
-struct DataStorageConfig {
+// Time between writes of the running integrity manifest of the directory data is stored in
+const INTEGRITY_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Envconfig, Debug)]
+pub struct DataStorageConfig {
     #[envconfig(from = "FLIGHTCODE_DATA_STORAGE_DIR", default = "./collected_data/")]
     pub target_path: String
 }
 
-#[derive(PartialEq, Clone, Copy)]
-pub enum DataSource {
-    Example = 0,
-    GoproImage,
-    GlobalPosition,
-    IrCamImage,
-    Power,
-    Temperature,
-    Environmental,
-    ObcTelemetry,
-    PiCamImage,
-    Attitude,
-    Count,
-    Invalid
-}
+// Identifies a data source by name, which is also its storage directory and REST API path
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct DataSource(&'static str);
 
 impl DataSource {
-    pub const COUNT: usize = DataSource::Count as usize;
-
-    pub fn iter() -> Iter<'static, DataSource> {
-        static SOURCES: [DataSource; DataSource::COUNT] = [DataSource::Example,
-            DataSource::GoproImage,
-            DataSource::GlobalPosition,
-            DataSource::IrCamImage,
-            DataSource::Power,
-            DataSource::Temperature,
-            DataSource::Environmental,
-            DataSource::ObcTelemetry,
-            DataSource::PiCamImage,
-            DataSource::Attitude];
-        return SOURCES.iter()
-    }
-}
-
-pub fn get_data_source_string(source: &DataSource) -> String {
-    match source {
-        DataSource::Example => {"example".to_string()}
-        DataSource::GoproImage => {"gopro_image".to_string()}
-        DataSource::GlobalPosition => {"global_position".to_string()}
-        DataSource::IrCamImage => {"thermal_img".to_string()}
-        DataSource::Power => {"power".to_string()}
-        DataSource::Temperature => {"temperature".to_string()}
-        DataSource::Environmental => {"environmental".to_string()}
-        DataSource::ObcTelemetry => {"obc_telemetry".to_string()}
-        DataSource::PiCamImage => {"picam_image".to_string()}
-        DataSource::Attitude => {"attitude".to_string()}
-        _ => {"unsupported".to_string()}
-    }
-}
-
-pub fn get_data_source_by_name(source_name: String) -> DataSource {
-    match source_name.as_str() {
-        "example" => {DataSource::Example}
-        "gopro_image" => {DataSource::GoproImage}
-        "global_position" => {DataSource::GlobalPosition}
-        "thermal_img" => {DataSource::IrCamImage}
-        "power" => {DataSource::Power}
-        "temperature" => {DataSource::Temperature}
-        "environmental" => {DataSource::Environmental}
-        "obc_telemetry" => {DataSource::ObcTelemetry}
-        "picam_image" => {DataSource::PiCamImage}
-        "attitude" => {DataSource::Attitude}
-        _ => {DataSource::Invalid}
-    }
-}
-
-// TODO: associate data with mission
+    pub const fn new(name: &'static str) -> Self {
+        return Self(name);
+    }
+
+    pub fn name(&self) -> &'static str {
+        return self.0;
+    }
+}
+
+impl fmt::Display for DataSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ContentType {
+    Json,
+    Png,
+    Jpeg,
+    // Also holds 16 bit radiometric thermal frames
+    Tiff,
+    // Raw blobs stored as they are
+    Binary
+}
+
+impl ContentType {
+    // Names accepted in configuration, such as the image_format of camera tasks
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "json" => {Some(ContentType::Json)}
+            "png" => {Some(ContentType::Png)}
+            "jpeg" | "jpg" => {Some(ContentType::Jpeg)}
+            "tiff" | "tif" => {Some(ContentType::Tiff)}
+            "binary" | "bin" => {Some(ContentType::Binary)}
+            _ => {None}
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            ContentType::Json => {"application/json"}
+            ContentType::Png => {"image/png"}
+            ContentType::Jpeg => {"image/jpeg"}
+            ContentType::Tiff => {"image/tiff"}
+            ContentType::Binary => {"application/octet-stream"}
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ContentType::Json => {"json"}
+            ContentType::Png => {"png"}
+            ContentType::Jpeg => {"jpg"}
+            ContentType::Tiff => {"tiff"}
+            ContentType::Binary => {"bin"}
+        }
+    }
+}
+
+// Declared by the collector producing the data source, see TaskSpec
+pub struct DataSourceSpec {
+    pub source: DataSource,
+    pub content_type: ContentType,
+    // Unit of each field of the serialized data, empty for images
+    pub units: &'static [(&'static str, &'static str)]
+}
+
+impl DataSourceSpec {
+    pub fn to_json(&self) -> JsonValue {
+        let mut units = JsonValue::new_object();
+        for (field, unit) in self.units {
+            units[*field] = JsonValue::from(*unit);
+        }
+
+        return object!{
+            name: self.source.name(),
+            content_type: self.content_type.mime_type(),
+            units: units
+        };
+    }
+}
+
+// Typed payload of a telemetry source, serialized as just the fields of the payload for storage and REST
+#[derive(Clone, Debug, Serialize)]
+#[serde(untagged)]
+pub enum Telemetry {
+    GlobalPosition(GLOBAL_POSITION_INT_DATA),
+    Attitude(ATTITUDE_DATA),
+    Heartbeat(Heartbeat),
+    Power(Power),
+    Temperature(Temperature),
+    Environmental(Environmental),
+    ObcTelemetry(ObcTelemetry),
+    Storage(StorageStatus),
+    StorageQueue(QueueStats),
+    ImageWriter(ImageWriteStats),
+    Example(Example)
+}
+
+// One line of a telemetry stream
+#[derive(Serialize)]
+struct TelemetryRecord<'a> {
+    timestamp: String,
+    monotonic_ms: u64,
+    sequence: u64,
+    data: &'a Telemetry
+}
+
 #[derive(Clone)]
 pub struct IncomingData {
     source: DataSource,
     pub time_stamp: DateTime<Local>,
-    pub serialized: Option<JsonValue>,
-    pub file: Option<Vec<u8>>
+    // Monotonic time since boot and sequence number, which keep their order when the wall clock is stepped
+    pub monotonic_ms: u64,
+    pub sequence: u64,
+    pub telemetry: Option<Telemetry>,
+    // Shared rather than copied on its way to storage, the data bus and REST clients
+    pub file: Option<Bytes>,
+    // Media type of the file when it differs from the content type declared by the source
+    pub content_type: Option<ContentType>,
+    // Appended to the timestamp the file is named by, keeping the extension of the hint if it has one
+    pub file_name: Option<String>,
+    pub geotag: Option<GeoTag>
 }
 
 impl IncomingData {
-    pub fn new(source: DataSource, serialized: Option<JsonValue>, file: Option<Vec<u8>>) -> Self {
+    pub fn new(source: DataSource, telemetry: Option<Telemetry>, file: Option<Bytes>) -> Self {
+        let time_stamp = clock().stamp();
+
         Self {
             source,
-            time_stamp: Local::now(),
-            serialized,
-            file
+            time_stamp: time_stamp.utc.with_timezone(&Local),
+            monotonic_ms: time_stamp.monotonic_ms,
+            sequence: time_stamp.sequence,
+            telemetry,
+            file,
+            content_type: None,
+            file_name: None,
+            geotag: None
         }
     }
-}
 
-fn create_serialized_file(parent_dir: String, source: DataSource) -> fs::File {
-    let source = get_data_source_string(&source);
+    pub fn with_content_type(mut self, content_type: ContentType) -> Self {
+        self.content_type = Some(content_type);
+        return self;
+    }
 
-    return fs::File::create(format!("{}/{}/{}.json", &parent_dir, &source, &source))
-        .expect(&*format!("Creation of {}/{}.json failed!", parent_dir, source));
-}
+    pub fn with_file_name(mut self, file_name: String) -> Self {
+        self.file_name = Some(file_name);
+        return self;
+    }
 
-fn create_source_directories(storage_dir: String) {
-    for source in DataSource::iter() {
-        let source_dir = format!("{}/{}", &storage_dir, get_data_source_string(source));
-        if !Path::new(&source_dir).exists() {
-            fs::create_dir_all(&source_dir).expect(&*format!("Failed to create directory: {}", &storage_dir));
-        }
+    pub fn get_content_type(&self, spec: &DataSourceSpec) -> ContentType {
+        return self.content_type.unwrap_or(spec.content_type);
     }
+
+    // Path of the file within the source directory without extension, along with the extension
+    fn get_file_name(&self, content_type: ContentType) -> (String, String) {
+        let time_stamp = self.time_stamp.to_rfc3339();
+
+        // Only the last component of the hint is used so it cannot point outside of the source directory
+        let hint = self.file_name.as_ref()
+            .and_then(|file_name| Path::new(file_name).file_name())
+            .map(Path::new);
+
+        return match hint {
+            Some(hint) => {
+                let stem = sanitize_file_name(&hint.file_stem().unwrap_or_default().to_string_lossy());
+                let extension = hint.extension()
+                    .map(|extension| sanitize_file_name(&extension.to_string_lossy()))
+                    .filter(|extension| !extension.is_empty())
+                    .unwrap_or(content_type.extension().to_string());
+                (format!("{}_{}", time_stamp, stem), extension)
+            }
+            None => (time_stamp, content_type.extension().to_string())
+        };
+    }
+
+    pub fn with_geotag(mut self, geotag: GeoTag) -> Self {
+        self.geotag = Some(geotag);
+        return self;
+    }
+}
+
+// Hints come from the producer of a sample, so anything that could be special in a path or a shell is replaced
+fn sanitize_file_name(name: &str) -> String {
+    return name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' {c} else {'_'})
+        .collect();
 }
 
 struct DataStreams {
-    json_streams: [Option<LiveJsonStream>; DataSource::COUNT]
+    storage_dir: String,
+    telemetry_config: TelemetryStorageConfig,
+    json_streams: HashMap<DataSource, JsonlStream>,
+    integrity: Arc<IntegrityDigests>,
+    last_integrity_snapshot: Instant
 }
 
 impl DataStreams {
-    pub fn write_json_stream(&mut self, source_dir: String, source: DataSource, serialized: JsonValue) {
-        if self.json_streams[source as usize].is_none() {
-            let file = create_serialized_file(source_dir.clone(), source);
-            self.json_streams[source as usize] = Option::from(LiveJsonStream::new(file))
+    // Streams of the previous directory are synced and closed when switching between missions and the idle area
+    pub fn set_storage_dir(&mut self, storage_dir: String) {
+        if storage_dir == self.storage_dir {
+            return;
         }
 
-        self.json_streams[source as usize].as_mut().unwrap().write(serialized);
+        if let Err(err) = self.sync_all() {
+            warn!("Failed to sync data of {}: {}", self.storage_dir, err);
+        }
+        self.close();
+        self.storage_dir = storage_dir;
+        self.integrity.open(&self.storage_dir);
+    }
+
+    // Closed streams are left out of the integrity manifest until their final digests are recorded
+    fn close(&mut self) {
+        self.record_digests();
+        self.json_streams.clear();
+        self.integrity.close(&self.storage_dir);
+    }
+
+    fn record_digests(&self) {
+        for json_stream in self.json_streams.values() {
+            for (segment, digest) in json_stream.get_digests() {
+                self.integrity.record(&self.storage_dir, &segment, digest);
+            }
+        }
+    }
+
+    pub fn write_integrity_snapshot_if_due(&mut self) {
+        if self.last_integrity_snapshot.elapsed().lt(&INTEGRITY_SNAPSHOT_INTERVAL) {
+            return;
+        }
+        self.last_integrity_snapshot = Instant::now();
+
+        self.record_digests();
+        self.integrity.write_snapshot(&self.storage_dir);
+    }
+
+    pub fn write_json_stream(&mut self, source: DataSource, record: &str) -> Result<(), String> {
+        if !self.json_streams.contains_key(&source) {
+            let source_dir = Path::new(&self.storage_dir).join(source.name());
+            let json_stream = JsonlStream::open(&source_dir, source.name(), &self.telemetry_config)
+                .map_err(|err| format!("Failed to open {} stream in {}: {}", source, source_dir.display(), err))?;
+            self.json_streams.insert(source, json_stream);
+        }
+
+        return self.json_streams.get_mut(&source).unwrap().write(record)
+            .map_err(|err| format!("Failed to write {} data: {}", source, err));
+    }
+
+    // Streams that have gone quiet are still synced once their sync interval has passed
+    pub fn sync_due(&mut self) {
+        for (source, json_stream) in self.json_streams.iter_mut() {
+            if let Err(err) = json_stream.sync_if_due() {
+                warn!("Failed to sync {} stream: {}", source, err);
+            }
+        }
+    }
+
+    pub fn sync_all(&mut self) -> Result<(), String> {
+        let mut failures: Vec<String> = Vec::new();
+
+        for json_stream in self.json_streams.values_mut() {
+            if let Err(err) = json_stream.sync() {
+                failures.push(format!("Failed to sync json stream: {}", err));
+            }
+        }
+
+        if failures.is_empty() {
+            return Ok(());
+        }
+        return Err(failures.join(", "));
     }
 }
 
-pub fn spawn_data_manager(data_receiver: Receiver<IncomingData>) -> Arc<Mutex<Box<[Option<IncomingData>; DataSource::COUNT]>>> {
-    const ARRAY_REPEAT_VALUE: Option<IncomingData> = None;
-    let current_storage = [ARRAY_REPEAT_VALUE; DataSource::COUNT];
-    let current_data_storage = Arc::new(Mutex::new(Box::new(current_storage)));
-    let data_use_in_thread = current_data_storage.clone();
+// Free space is only checked, and data shed when it runs low, with a disk guard
+pub fn spawn_data_manager(mission_manager: Arc<MissionManager>, sources: Vec<&'static DataSourceSpec>, data_receiver: StorageReceiver,
+                          data_bus: Arc<DataBus>, recent_samples: Arc<RecentSamples>, storage_sampling: Arc<StorageSampling>,
+                          disk_guard: Option<DiskGuard>, image_writer: ImageWriter, integrity: Arc<IntegrityDigests>) -> ShutdownHandle {
+    let sources = sources.into_iter().map(|spec| (spec.source, spec)).collect();
 
-    thread::spawn(move || {
-        data_manager_loop(data_receiver, data_use_in_thread);
+    let (kill_sender, kill_recv) = mpsc::channel();
+    let join_handle = thread::spawn(move || {
+        return data_manager_loop(mission_manager, sources, data_receiver, data_bus, recent_samples, storage_sampling, disk_guard, image_writer,
+                                 integrity, kill_recv);
     });
 
-    return current_data_storage;
+    return ShutdownHandle::new("DataManager".to_string(), kill_sender, join_handle);
 }
 
-fn data_manager_loop(data_receiver: Receiver<IncomingData>, current_data_storage: Arc<Mutex<Box<[Option<IncomingData>; DataSource::COUNT]>>>) {
-    let data_storage_config = DataStorageConfig::init_from_env().unwrap();
+fn data_manager_loop(mission_manager: Arc<MissionManager>, sources: HashMap<DataSource, &'static DataSourceSpec>, mut data_receiver: StorageReceiver,
+                     data_bus: Arc<DataBus>, recent_samples: Arc<RecentSamples>, storage_sampling: Arc<StorageSampling>, mut disk_guard: Option<DiskGuard>, mut image_writer: ImageWriter,
+                     integrity: Arc<IntegrityDigests>, kill_recv: Receiver<bool>) -> Result<(), String> {
+    let mut data_streams = DataStreams {
+        storage_dir: mission_manager.get_storage_dir(),
+        telemetry_config: TelemetryStorageConfig::init_from_env().unwrap(),
+        json_streams: HashMap::new(),
+        integrity,
+        last_integrity_snapshot: Instant::now()
+    };
+    data_streams.integrity.open(&data_streams.storage_dir);
 
-    let storage_dir = format!("{}/{}", data_storage_config.target_path, Local::now().to_rfc3339());
-    create_source_directories(storage_dir.clone());
+    loop {
+        // Each sample switches to the directory it was counted for, this only closes the streams of a stopped mission
+        // while no samples arrive
+        data_streams.set_storage_dir(mission_manager.get_storage_dir());
+        data_streams.write_integrity_snapshot_if_due();
 
-    const ARRAY_REPEAT_VALUE: Option<LiveJsonStream> = None;
-    let mut data_streams = DataStreams { json_streams: [ARRAY_REPEAT_VALUE; DataSource::COUNT] };
+        if let Some(image_write_stats) = image_writer.get_stats_if_due() {
+            store_incoming_data(image_write_stats, &mission_manager, &sources, &mut data_streams, &mut disk_guard, &image_writer, &data_bus, &recent_samples, &storage_sampling);
+        }
 
-    loop {
-        let data_result = data_receiver.recv();
-        if data_result.is_ok() {
-            let incoming_data = data_result.unwrap();
-
-            current_data_storage.lock().unwrap()[incoming_data.source as usize] = Option::from(incoming_data.clone());
-
-            /**
-                TODO: Check remaining space on storage drive of destination directory to ensure sufficient storage space
-                      and warn on running low
-            */
-            if incoming_data.serialized.is_some() {
-                let serialized = object!{
-                    timestamp: incoming_data.time_stamp.to_rfc3339(),
-                    data: incoming_data.serialized.unwrap()
-                };
-
-                data_streams.write_json_stream(storage_dir.clone(), incoming_data.source, serialized);
+        if let Some(queue_stats) = data_receiver.get_stats_if_due() {
+            store_incoming_data(queue_stats, &mission_manager, &sources, &mut data_streams, &mut disk_guard, &image_writer, &data_bus, &recent_samples, &storage_sampling);
+        }
+
+        let storage_status = disk_guard.as_mut()
+            .and_then(|disk_guard| disk_guard.check(&data_streams.storage_dir, &mission_manager));
+        if let Some(storage_status) = storage_status {
+            store_incoming_data(storage_status, &mission_manager, &sources, &mut data_streams, &mut disk_guard, &image_writer, &data_bus, &recent_samples, &storage_sampling);
+        }
+
+        match data_receiver.recv_timeout(Duration::from_millis(100)) {
+            Ok(incoming_data) => {
+                store_incoming_data(incoming_data, &mission_manager, &sources, &mut data_streams, &mut disk_guard, &image_writer, &data_bus, &recent_samples, &storage_sampling);
             }
+            Err(RecvTimeoutError::Timeout) => {
+                data_streams.sync_due();
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                break;
+            }
+        }
 
-            if incoming_data.file.is_some() {
-                let file = format!("{}/{}/{}.png", storage_dir.clone(), get_data_source_string(&incoming_data.source), incoming_data.time_stamp.to_rfc3339());
-                fs::write(file.clone(), incoming_data.file.unwrap())
-                    .expect(&*format!("Failed to write to file: {}", file));
+        if kill_recv.try_recv() == Ok(true) {
+            // Drain whatever producers queued before they were stopped
+            while let Some(incoming_data) = data_receiver.try_recv() {
+                store_incoming_data(incoming_data, &mission_manager, &sources, &mut data_streams, &mut disk_guard, &image_writer, &data_bus, &recent_samples, &storage_sampling);
             }
+            break;
+        }
+    }
+
+    info!("Data manager stopping, syncing stored data");
+    let telemetry_result = data_streams.sync_all();
+    data_streams.close();
+    let image_result = image_writer.stop();
+    return match (telemetry_result, image_result) {
+        (Err(telemetry_err), Err(image_err)) => Err(format!("{}, {}", telemetry_err, image_err)),
+        (telemetry_result, image_result) => telemetry_result.and(image_result)
+    };
+}
+
+fn store_incoming_data(incoming_data: IncomingData, mission_manager: &Arc<MissionManager>, sources: &HashMap<DataSource, &'static DataSourceSpec>,
+                       data_streams: &mut DataStreams, disk_guard: &mut Option<DiskGuard>, image_writer: &ImageWriter, data_bus: &DataBus,
+                       recent_samples: &RecentSamples, storage_sampling: &StorageSampling) {
+    let spec = match sources.get(&incoming_data.source) {
+        Some(spec) => spec,
+        None => {
+            warn!("Dropping data from unregistered source {}", incoming_data.source);
+            return;
+        }
+    };
+
+    // Shared with the bus and the recent samples rather than copied, as it may hold a whole image
+    let incoming_data = Arc::new(incoming_data);
+    data_bus.publish(incoming_data.clone());
+    recent_samples.push(incoming_data.clone());
+
+    // Samples left out by the source's sampling rule are only kept in memory
+    if !storage_sampling.should_store(&incoming_data) {
+        return;
+    }
+
+    let content_type = incoming_data.get_content_type(spec);
+    if disk_guard.as_mut().is_some_and(|disk_guard| !disk_guard.allows(content_type)) {
+        return;
+    }
+
+    // Only stored samples count towards the mission
+    data_streams.set_storage_dir(mission_manager.record_sample(incoming_data.source));
+
+    // Failed writes, such as on a full or removed card, are logged and the sample dropped so collection carries on
+    if let Some(telemetry) = incoming_data.telemetry.as_ref() {
+        let record = TelemetryRecord {
+            timestamp: incoming_data.time_stamp.with_timezone(&Utc).to_rfc3339(),
+            monotonic_ms: incoming_data.monotonic_ms,
+            sequence: incoming_data.sequence,
+            data: telemetry
+        };
+
+        let result = serde_json::to_string(&record)
+            .map_err(|err| format!("Failed to serialize {} data: {}", incoming_data.source, err))
+            .and_then(|record| data_streams.write_json_stream(incoming_data.source, &record));
+        if let Err(err) = result {
+            error!("{}", err);
         }
     }
+
+    // Written by the image writer, so telemetry never waits on image writes
+    if incoming_data.file.is_some() {
+        let (file_name, extension) = incoming_data.get_file_name(content_type);
+        image_writer.queue(ImageWrite {
+            storage_dir: data_streams.storage_dir.clone(),
+            file: format!("{}/{}/{}", data_streams.storage_dir, incoming_data.source, file_name),
+            extension,
+            content_type,
+            incoming_data: incoming_data.clone()
+        });
+    }
+}
+
+#[cfg(test)]
+mod data_manage_tests {
+    use bytes::Bytes;
+    use crate::application::data_manage::{ContentType, DataSource, IncomingData};
+
+    #[test]
+    fn names_files_by_content_type_and_hint() {
+        let data = IncomingData::new(DataSource::new("ir_cam"), None, Some(Bytes::new())).with_content_type(ContentType::Tiff);
+        let time_stamp = data.time_stamp.to_rfc3339();
+        assert_eq!(data.get_file_name(ContentType::Tiff), (time_stamp.clone(), "tiff".to_string()));
+
+        let data = data.with_file_name("../../etc/frame.raw".to_string());
+        assert_eq!(data.get_file_name(ContentType::Binary), (format!("{}_frame", time_stamp), "raw".to_string()));
+
+        let data = data.with_file_name("frame".to_string());
+        assert_eq!(data.get_file_name(ContentType::Binary), (format!("{}_frame", time_stamp), "bin".to_string()));
+
+        let data = data.with_file_name("gopro clip #2.mp4".to_string());
+        assert_eq!(data.get_file_name(ContentType::Binary), (format!("{}_gopro_clip__2", time_stamp), "mp4".to_string()));
+        assert_eq!(ContentType::from_name("tif"), Some(ContentType::Tiff));
+        assert_eq!(ContentType::from_name("gif"), None);
+    }
 }
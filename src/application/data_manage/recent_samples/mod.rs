@@ -0,0 +1,157 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Local};
+use envconfig::Envconfig;
+use crate::application::data_manage::{ContentType, DataSource, DataSourceSpec, IncomingData};
+
+const SAMPLES_DEFAULT: usize = 600;
+
+#[derive(Envconfig, Debug)]
+pub struct RecentSamplesConfig {
+    // Samples kept of each source followed by per source overrides, such as "600,attitude=1200"
+    #[envconfig(from = "FLIGHTCODE_RECENT_SAMPLES", default = "600")]
+    pub samples: String,
+    // Images are additionally limited by their total size per source
+    #[envconfig(from = "FLIGHTCODE_RECENT_IMAGE_BYTES", default = "33554432")]
+    pub image_bytes: usize
+}
+
+fn parse_sample_counts(directives: &str) -> Result<(usize, HashMap<String, usize>), String> {
+    let mut default_count = SAMPLES_DEFAULT;
+    let mut counts = HashMap::new();
+
+    for directive in directives.split(',').map(|directive| directive.trim()).filter(|directive| !directive.is_empty()) {
+        match directive.split_once('=') {
+            Some((source, count)) => {
+                let count = count.trim().parse::<usize>()
+                    .map_err(|_| format!("Invalid recent sample count {} for {}", count, source))?;
+                counts.insert(source.trim().to_string(), count);
+            }
+            None => {
+                default_count = directive.parse::<usize>()
+                    .map_err(|_| format!("Invalid recent sample count {}", directive))?;
+            }
+        }
+    }
+
+    return Ok((default_count, counts));
+}
+
+struct SourceWindow {
+    samples: VecDeque<Arc<IncomingData>>,
+    bytes: usize,
+    max_samples: usize,
+    max_bytes: Option<usize>
+}
+
+impl SourceWindow {
+    fn push(&mut self, incoming_data: Arc<IncomingData>) {
+        self.bytes += incoming_data.file.as_ref().map_or(0, |file| file.len());
+        self.samples.push_back(incoming_data);
+
+        while self.samples.len() > self.max_samples || self.max_bytes.is_some_and(|max_bytes| self.bytes > max_bytes && self.samples.len() > 1) {
+            let evicted = self.samples.pop_front().unwrap();
+            self.bytes -= evicted.file.as_ref().map_or(0, |file| file.len());
+        }
+    }
+}
+
+// Last samples of each source, oldest first. Every source has its own lock that is only held to push or to clone
+// the shared samples out, so readers never hold up the data manager for long
+pub struct RecentSamples {
+    windows: HashMap<DataSource, Mutex<SourceWindow>>
+}
+
+impl RecentSamples {
+    pub fn new(sources: &[&'static DataSourceSpec], config: &RecentSamplesConfig) -> Result<Self, String> {
+        let (default_count, mut counts) = parse_sample_counts(&config.samples)?;
+
+        let windows = sources.iter().map(|spec| {
+            let window = SourceWindow {
+                samples: VecDeque::new(),
+                bytes: 0,
+                max_samples: counts.remove(spec.source.name()).unwrap_or(default_count),
+                max_bytes: if spec.content_type == ContentType::Json {None} else {Some(config.image_bytes)}
+            };
+            (spec.source, Mutex::new(window))
+        }).collect();
+
+        if let Some(source) = counts.keys().next() {
+            return Err(format!("Recent samples configured for unknown data source {}", source));
+        }
+
+        return Ok(Self { windows });
+    }
+
+    pub(super) fn push(&self, incoming_data: Arc<IncomingData>) {
+        if let Some(window) = self.windows.get(&incoming_data.source) {
+            window.lock().unwrap().push(incoming_data);
+        }
+    }
+
+    // Samples of the source taken at or after the given time, or all that are kept. Filtered rather than searched,
+    // as samples stamped before a wall clock step can be newer than ones stamped after it
+    pub fn get_window(&self, source: DataSource, since: Option<DateTime<Local>>) -> Vec<Arc<IncomingData>> {
+        let window = match self.windows.get(&source) {
+            Some(window) => window.lock().unwrap(),
+            None => {
+                return Vec::new();
+            }
+        };
+
+        return window.samples.iter()
+            .filter(|sample| since.is_none_or(|since| sample.time_stamp >= since))
+            .cloned()
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod recent_samples_tests {
+    use std::sync::Arc;
+    use bytes::Bytes;
+    use chrono::Local;
+    use crate::application::data_manage::{ContentType, DataSource, DataSourceSpec, IncomingData, Telemetry};
+    use crate::application::data_manage::recent_samples::{RecentSamples, RecentSamplesConfig};
+    use crate::application::tasks::example_task::Example;
+
+    const TELEMETRY: DataSourceSpec = DataSourceSpec { source: DataSource::new("telemetry"), content_type: ContentType::Json, units: &[] };
+    const IMAGE: DataSourceSpec = DataSourceSpec { source: DataSource::new("image"), content_type: ContentType::Png, units: &[] };
+
+    #[test]
+    fn windows_are_bounded() {
+        let config = RecentSamplesConfig { samples: "3, image=10".to_string(), image_bytes: 250 };
+        let recent_samples = RecentSamples::new(&[&TELEMETRY, &IMAGE], &config).unwrap();
+
+        for value in 0..5 {
+            recent_samples.push(Arc::new(IncomingData::new(TELEMETRY.source, Some(Telemetry::Example(Example { test: value.to_string() })), None)));
+            recent_samples.push(Arc::new(IncomingData::new(IMAGE.source, None, Some(Bytes::from(vec![0u8; 100])))));
+        }
+
+        let telemetry: Vec<String> = recent_samples.get_window(TELEMETRY.source, None).iter()
+            .map(|sample| match sample.telemetry.as_ref() {
+                Some(Telemetry::Example(example)) => example.test.clone(),
+                _ => panic!("Unexpected sample")
+            })
+            .collect();
+        assert_eq!(telemetry, vec!["2", "3", "4"]);
+        assert_eq!(recent_samples.get_window(IMAGE.source, None).len(), 2);
+        assert!(recent_samples.get_window(TELEMETRY.source, Some(Local::now())).is_empty());
+
+        // Samples stamped before a wall clock step back do not hide the newer ones in the window
+        let recent_samples = RecentSamples::new(&[&TELEMETRY], &RecentSamplesConfig { samples: "3".to_string(), image_bytes: 0 }).unwrap();
+        let since = Local::now();
+        let first = Arc::new(IncomingData::new(TELEMETRY.source, None, None));
+        let mut stepped_back = IncomingData::new(TELEMETRY.source, None, None);
+        stepped_back.time_stamp = since - chrono::Duration::hours(1);
+        let last = Arc::new(IncomingData::new(TELEMETRY.source, None, None));
+        for sample in [first.clone(), Arc::new(stepped_back), last.clone()] {
+            recent_samples.push(sample);
+        }
+        let sequences: Vec<u64> = recent_samples.get_window(TELEMETRY.source, Some(since)).iter().map(|sample| sample.sequence).collect();
+        assert_eq!(sequences, vec![first.sequence, last.sequence]);
+
+        assert!(RecentSamples::new(&[&TELEMETRY], &RecentSamplesConfig { samples: "many".to_string(), image_bytes: 0 }).is_err());
+        assert!(RecentSamples::new(&[&TELEMETRY], &RecentSamplesConfig { samples: "other=5".to_string(), image_bytes: 0 }).is_err());
+    }
+}
@@ -0,0 +1,381 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
+use envconfig::Envconfig;
+use serde::Serialize;
+use crate::application::data_manage::{ContentType, DataSource, DataSourceSpec, IncomingData, Telemetry};
+
+const TELEMETRY_CAPACITY: usize = 256;
+const IMAGE_CAPACITY: usize = 1;
+
+pub const STORAGE_QUEUE: DataSourceSpec = DataSourceSpec {
+    source: DataSource::new("storage_queue"),
+    content_type: ContentType::Json,
+    units: &[("dropped", "count"), ("queued", "count")]
+};
+
+// Counts of each source by name
+#[derive(Clone, Debug, Serialize)]
+pub struct QueueStats {
+    pub dropped: BTreeMap<&'static str, u64>,
+    pub queued: BTreeMap<&'static str, usize>
+}
+
+#[derive(Envconfig, Debug)]
+pub struct StorageQueueConfig {
+    // Per source overrides of the queue policy and capacity, such as "thermal_img=drop_oldest:4,power=block:64"
+    #[envconfig(from = "FLIGHTCODE_STORAGE_QUEUES", default = "")]
+    pub queues: String,
+    // Time between samples of the queue statistics, 0 disables them
+    #[envconfig(from = "FLIGHTCODE_STORAGE_QUEUE_STATS_INTERVAL_MS", default = "5000")]
+    pub stats_interval_ms: u64
+}
+
+// What happens to a sample queued for storage while its source's queue is full
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum QueuePolicy {
+    // Producer waits for the data manager to catch up
+    Block,
+    DropOldest,
+    DropNewest,
+    // Only the latest sample is kept
+    Coalesce
+}
+
+impl QueuePolicy {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "block" => Some(QueuePolicy::Block),
+            "drop_oldest" => Some(QueuePolicy::DropOldest),
+            "drop_newest" => Some(QueuePolicy::DropNewest),
+            "coalesce" => Some(QueuePolicy::Coalesce),
+            _ => None
+        }
+    }
+}
+
+// Telemetry is stored before images, as it is small and should never wait behind a slow image write.
+// Producers draining serial ports must not be blocked, so telemetry drops the oldest sample by default
+fn get_default_queue(content_type: ContentType) -> (QueuePolicy, usize, u8) {
+    match content_type {
+        ContentType::Json => {(QueuePolicy::DropOldest, TELEMETRY_CAPACITY, 0)}
+        ContentType::Png | ContentType::Jpeg | ContentType::Tiff | ContentType::Binary => {(QueuePolicy::Coalesce, IMAGE_CAPACITY, 1)}
+    }
+}
+
+fn parse_queue_overrides(directives: &str) -> Result<HashMap<String, (QueuePolicy, Option<usize>)>, String> {
+    let mut overrides = HashMap::new();
+
+    for directive in directives.split(',').map(|directive| directive.trim()).filter(|directive| !directive.is_empty()) {
+        let (source, settings) = directive.split_once('=')
+            .ok_or(format!("Invalid storage queue {}, expected <source>=<policy>[:<capacity>]", directive))?;
+        let (policy, capacity) = match settings.split_once(':') {
+            Some((policy, capacity)) => (policy, Some(capacity)),
+            None => (settings, None)
+        };
+
+        let policy = QueuePolicy::parse(policy.trim())
+            .ok_or(format!("Invalid storage queue policy {} for {}, expected block, drop_oldest, drop_newest or coalesce", policy, source))?;
+        let capacity = match capacity {
+            Some(capacity) => Some(capacity.trim().parse::<usize>().ok().filter(|capacity| *capacity > 0)
+                .ok_or(format!("Invalid storage queue capacity {} for {}", capacity, source))?),
+            None => None
+        };
+
+        overrides.insert(source.trim().to_string(), (policy, capacity));
+    }
+
+    return Ok(overrides);
+}
+
+struct SourceQueue {
+    source: DataSource,
+    policy: QueuePolicy,
+    capacity: usize,
+    // Lower is stored first
+    priority: u8,
+    samples: VecDeque<IncomingData>,
+    dropped: u64
+}
+
+struct QueueState {
+    queues: Vec<SourceQueue>,
+    senders: usize,
+    receiver_closed: bool
+}
+
+impl QueueState {
+    // Oldest sample of the highest priority with anything queued, by sequence so a wall clock step does not reorder sources
+    fn pop(&mut self) -> Option<IncomingData> {
+        return self.queues.iter_mut()
+            .filter(|queue| !queue.samples.is_empty())
+            .min_by_key(|queue| (queue.priority, queue.samples.front().unwrap().sequence))
+            .and_then(|queue| queue.samples.pop_front());
+    }
+}
+
+struct SharedQueues {
+    state: Mutex<QueueState>,
+    available: Condvar,
+    space: Condvar
+}
+
+impl SharedQueues {
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        return self.state.lock().unwrap();
+    }
+}
+
+// Creates a bounded queue for each source, with the policy and capacity of its content type unless overridden.
+// Queue statistics are sampled every stats interval when given
+pub fn storage_queue(sources: &[&'static DataSourceSpec], config: &StorageQueueConfig) -> Result<(StorageSender, StorageReceiver), String> {
+    let mut overrides = parse_queue_overrides(&config.queues)?;
+    let stats_interval = (config.stats_interval_ms > 0).then(|| Duration::from_millis(config.stats_interval_ms));
+
+    let queues = sources.iter().map(|spec| {
+        let (default_policy, default_capacity, priority) = get_default_queue(spec.content_type);
+        let (policy, capacity) = match overrides.remove(spec.source.name()) {
+            Some((policy, capacity)) => (policy, capacity.unwrap_or(default_capacity)),
+            None => (default_policy, default_capacity)
+        };
+
+        SourceQueue {
+            source: spec.source,
+            policy,
+            capacity: if policy == QueuePolicy::Coalesce {1} else {capacity},
+            priority,
+            samples: VecDeque::new(),
+            dropped: 0
+        }
+    }).collect();
+
+    if let Some(source) = overrides.keys().next() {
+        return Err(format!("Storage queue configured for unknown data source {}", source));
+    }
+
+    let shared = Arc::new(SharedQueues {
+        state: Mutex::new(QueueState { queues, senders: 1, receiver_closed: false }),
+        available: Condvar::new(),
+        space: Condvar::new()
+    });
+
+    return Ok((
+        StorageSender { shared: shared.clone() },
+        StorageReceiver { shared, stats_interval, last_stats: Instant::now() }
+    ));
+}
+
+// Queues samples for storage by the data manager, cloned for every producer
+pub struct StorageSender {
+    shared: Arc<SharedQueues>
+}
+
+impl StorageSender {
+    // Only waits for the data manager when the source's policy is to block, samples dropped by the other policies are counted
+    pub fn send(&self, incoming_data: IncomingData) -> Result<(), String> {
+        let mut state = self.shared.lock();
+
+        loop {
+            if state.receiver_closed {
+                return Err("Data manager is no longer storing data".to_string());
+            }
+
+            let queue = state.queues.iter_mut().find(|queue| queue.source == incoming_data.source)
+                .ok_or(format!("Unregistered data source {}", incoming_data.source))?;
+
+            if queue.samples.len() < queue.capacity {
+                queue.samples.push_back(incoming_data);
+                break;
+            }
+
+            match queue.policy {
+                QueuePolicy::Block => {
+                    state = self.shared.space.wait(state).unwrap();
+                }
+                QueuePolicy::DropOldest | QueuePolicy::Coalesce => {
+                    queue.samples.pop_front();
+                    queue.samples.push_back(incoming_data);
+                    queue.dropped += 1;
+                    break;
+                }
+                QueuePolicy::DropNewest => {
+                    queue.dropped += 1;
+                    return Ok(());
+                }
+            }
+        }
+
+        self.shared.available.notify_one();
+        return Ok(());
+    }
+}
+
+impl Clone for StorageSender {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        return Self { shared: self.shared.clone() };
+    }
+}
+
+impl Drop for StorageSender {
+    fn drop(&mut self) {
+        self.shared.lock().senders -= 1;
+        self.shared.available.notify_all();
+    }
+}
+
+pub struct StorageReceiver {
+    shared: Arc<SharedQueues>,
+    stats_interval: Option<Duration>,
+    last_stats: Instant
+}
+
+impl StorageReceiver {
+    // Disconnected once every sender has been dropped and everything queued has been received
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<IncomingData, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.lock();
+
+        loop {
+            if let Some(incoming_data) = state.pop() {
+                self.shared.space.notify_all();
+                return Ok(incoming_data);
+            }
+
+            if state.senders == 0 {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            state = self.shared.available.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+
+    pub fn try_recv(&self) -> Option<IncomingData> {
+        let incoming_data = self.shared.lock().pop();
+        if incoming_data.is_some() {
+            self.shared.space.notify_all();
+        }
+        return incoming_data;
+    }
+
+    // Samples dropped since startup and currently queued for each source, once the stats interval has passed
+    pub fn get_stats_if_due(&mut self) -> Option<IncomingData> {
+        if self.stats_interval.is_none() || self.last_stats.elapsed().lt(&self.stats_interval.unwrap()) {
+            return None;
+        }
+        self.last_stats = Instant::now();
+
+        let state = self.shared.lock();
+        let stats = QueueStats {
+            dropped: state.queues.iter().map(|queue| (queue.source.name(), queue.dropped)).collect(),
+            queued: state.queues.iter().map(|queue| (queue.source.name(), queue.samples.len())).collect()
+        };
+
+        return Option::from(IncomingData::new(STORAGE_QUEUE.source, Option::from(Telemetry::StorageQueue(stats)), None));
+    }
+}
+
+// Blocked producers are released once the data manager stops
+impl Drop for StorageReceiver {
+    fn drop(&mut self) {
+        self.shared.lock().receiver_closed = true;
+        self.shared.space.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod storage_queue_tests {
+    use std::sync::mpsc::RecvTimeoutError;
+    use std::thread;
+    use std::time::Duration;
+    use crate::application::data_manage::{ContentType, DataSource, DataSourceSpec, IncomingData, Telemetry};
+    use crate::application::data_manage::storage_queue::{storage_queue, StorageQueueConfig};
+    use crate::application::tasks::example_task::Example;
+
+    const TELEMETRY: DataSourceSpec = DataSourceSpec { source: DataSource::new("telemetry"), content_type: ContentType::Json, units: &[] };
+    const IMAGE: DataSourceSpec = DataSourceSpec { source: DataSource::new("image"), content_type: ContentType::Png, units: &[] };
+
+    fn sample(spec: &DataSourceSpec, value: u32) -> IncomingData {
+        return IncomingData::new(spec.source, Option::from(Telemetry::Example(Example { test: value.to_string() })), None);
+    }
+
+    fn value(incoming_data: Option<IncomingData>) -> Option<(DataSource, u32)> {
+        return incoming_data.map(|data| match data.telemetry {
+            Some(Telemetry::Example(example)) => (data.source, example.test.parse().unwrap()),
+            _ => panic!("Unexpected sample")
+        });
+    }
+
+    #[test]
+    fn policies_and_priorities() {
+        let config = StorageQueueConfig { queues: "telemetry=drop_newest:2".to_string(), stats_interval_ms: 1 };
+        let (sender, mut receiver) = storage_queue(&[&TELEMETRY, &IMAGE], &config).unwrap();
+
+        // Images coalesce to the latest, telemetry beyond its capacity of 2 is dropped
+        for index in 0..3 {
+            sender.send(sample(&IMAGE, index)).unwrap();
+            sender.send(sample(&TELEMETRY, index)).unwrap();
+        }
+        assert!(sender.send(sample(&DataSourceSpec { source: DataSource::new("unknown"), ..IMAGE }, 0)).is_err());
+        thread::sleep(Duration::from_millis(2));
+
+        let stats = match receiver.get_stats_if_due().unwrap().telemetry {
+            Some(Telemetry::StorageQueue(stats)) => stats,
+            _ => panic!("Unexpected queue statistics")
+        };
+        assert_eq!((stats.dropped["telemetry"], stats.dropped["image"]), (1, 2));
+
+        assert_eq!(value(receiver.try_recv()), Some((TELEMETRY.source, 0)));
+        assert_eq!(value(receiver.try_recv()), Some((TELEMETRY.source, 1)));
+        assert_eq!(value(receiver.try_recv()), Some((IMAGE.source, 2)));
+        assert_eq!(value(receiver.try_recv()), None);
+
+        drop(sender);
+        assert_eq!(receiver.recv_timeout(Duration::from_millis(10)).err(), Some(RecvTimeoutError::Disconnected));
+
+        assert!(storage_queue(&[&TELEMETRY], &StorageQueueConfig { queues: "telemetry=hold".to_string(), stats_interval_ms: 0 }).is_err());
+        assert!(storage_queue(&[&TELEMETRY], &StorageQueueConfig { queues: "other=block".to_string(), stats_interval_ms: 0 }).is_err());
+    }
+
+    #[test]
+    fn drains_in_sequence_order_across_clock_steps() {
+        const POSITION: DataSourceSpec = DataSourceSpec { source: DataSource::new("position"), ..TELEMETRY };
+        let config = StorageQueueConfig { queues: String::new(), stats_interval_ms: 0 };
+        let (sender, receiver) = storage_queue(&[&TELEMETRY, &POSITION], &config).unwrap();
+
+        // Wall clock stepped back by a GPS fix between the two samples
+        sender.send(sample(&TELEMETRY, 0)).unwrap();
+        let mut stepped_back = sample(&POSITION, 1);
+        stepped_back.time_stamp -= chrono::Duration::hours(1);
+        sender.send(stepped_back).unwrap();
+
+        assert_eq!(value(receiver.try_recv()), Some((TELEMETRY.source, 0)));
+        assert_eq!(value(receiver.try_recv()), Some((POSITION.source, 1)));
+    }
+
+    #[test]
+    fn blocked_producers_wait_for_space() {
+        let config = StorageQueueConfig { queues: "telemetry=block:1".to_string(), stats_interval_ms: 0 };
+        let (sender, receiver) = storage_queue(&[&TELEMETRY], &config).unwrap();
+
+        sender.send(sample(&TELEMETRY, 0)).unwrap();
+        let producer = thread::spawn(move || sender.send(sample(&TELEMETRY, 1)));
+
+        assert_eq!(value(receiver.recv_timeout(Duration::from_secs(1)).ok()), Some((TELEMETRY.source, 0)));
+        assert_eq!(value(receiver.recv_timeout(Duration::from_secs(1)).ok()), Some((TELEMETRY.source, 1)));
+        assert!(producer.join().unwrap().is_ok());
+
+        // Dropping the receiver releases producers instead of leaving them blocked
+        let (sender, receiver) = storage_queue(&[&TELEMETRY], &config).unwrap();
+        sender.send(sample(&TELEMETRY, 0)).unwrap();
+        let producer = thread::spawn(move || sender.send(sample(&TELEMETRY, 1)));
+        thread::sleep(Duration::from_millis(20));
+        drop(receiver);
+        assert!(producer.join().unwrap().is_err());
+    }
+}
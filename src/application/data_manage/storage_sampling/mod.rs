@@ -0,0 +1,283 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Mutex;
+use envconfig::Envconfig;
+use serde::Serialize;
+use serde_json::Value;
+use crate::application::data_manage::{DataSource, DataSourceSpec, IncomingData};
+
+#[derive(Envconfig, Debug)]
+pub struct StorageSamplingConfig {
+    // Per source storage sampling, such as "attitude=every:5,global_position=max_hz:2,power=deadband:average_voltage=0.05;average_current=0.1"
+    #[envconfig(from = "FLIGHTCODE_STORAGE_SAMPLING", default = "")]
+    pub sampling: String
+}
+
+// Which samples of a source are stored, the latest sample is kept in memory either way
+#[derive(Clone, PartialEq, Debug)]
+pub enum SamplingRule {
+    All,
+    EveryNth(u32),
+    MaxRate(f64),
+    // Stored once one of the fields has changed by more than its deadband since the last stored sample, fields are named
+    // as in the units of the source and fields without a deadband are not compared
+    Deadband(Vec<(String, f64)>),
+    Off
+}
+
+impl SamplingRule {
+    pub fn parse(rule: &str) -> Result<Self, String> {
+        let (name, value) = match rule.trim().split_once(':') {
+            Some((name, value)) => (name, Some(value.trim())),
+            None => (rule.trim(), None)
+        };
+
+        let sampling_rule = match (name, value) {
+            ("all", None) => Some(SamplingRule::All),
+            ("off", None) => Some(SamplingRule::Off),
+            ("every", Some(value)) => value.parse::<u32>().ok().filter(|nth| *nth > 0).map(SamplingRule::EveryNth),
+            ("max_hz", Some(value)) => value.parse::<f64>().ok().filter(|hz| *hz > 0.0 && hz.is_finite()).map(SamplingRule::MaxRate),
+            ("deadband", Some(value)) => parse_deadbands(value).map(SamplingRule::Deadband),
+            _ => None
+        };
+
+        return sampling_rule.ok_or(format!("Invalid storage sampling {}, expected all, every:<n>, max_hz:<hz>, \
+                                            deadband:<field>=<value>[;<field>=<value>...] or off", rule));
+    }
+
+    // Deadbands can only be checked against the fields of a source once it is known which source the rule is for
+    fn check_fields(&self, spec: &DataSourceSpec) -> Result<(), String> {
+        if let SamplingRule::Deadband(deadbands) = self {
+            if let Some((field, _)) = deadbands.iter().find(|(field, _)| !spec.units.iter().any(|(unit_field, _)| unit_field == field)) {
+                let known: Vec<&str> = spec.units.iter().map(|(unit_field, _)| *unit_field).collect();
+                return Err(format!("Unknown deadband field {} of {}, expected one of: {}", field, spec.source, known.join(", ")));
+            }
+        }
+        return Ok(());
+    }
+}
+
+fn parse_deadbands(deadbands: &str) -> Option<Vec<(String, f64)>> {
+    return deadbands.split(';').map(|deadband| {
+        let (field, value) = deadband.split_once('=')?;
+        let value = value.trim().parse::<f64>().ok().filter(|value| *value >= 0.0 && value.is_finite())?;
+        return Some((field.trim().to_string(), value));
+    }).collect();
+}
+
+impl fmt::Display for SamplingRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SamplingRule::All => write!(f, "all"),
+            SamplingRule::EveryNth(nth) => write!(f, "every:{}", nth),
+            SamplingRule::MaxRate(hz) => write!(f, "max_hz:{}", hz),
+            SamplingRule::Deadband(deadbands) => {
+                let deadbands: Vec<String> = deadbands.iter().map(|(field, value)| format!("{}={}", field, value)).collect();
+                write!(f, "deadband:{}", deadbands.join(";"))
+            }
+            SamplingRule::Off => write!(f, "off")
+        }
+    }
+}
+
+fn parse_sampling_rules(directives: &str) -> Result<HashMap<String, SamplingRule>, String> {
+    let mut rules = HashMap::new();
+
+    for directive in directives.split(',').map(|directive| directive.trim()).filter(|directive| !directive.is_empty()) {
+        let (source, rule) = directive.split_once('=')
+            .ok_or(format!("Invalid storage sampling {}, expected <source>=<rule>", directive))?;
+        rules.insert(source.trim().to_string(), SamplingRule::parse(rule)?);
+    }
+
+    return Ok(rules);
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SamplingStatus {
+    pub rule: String,
+    // Samples not stored since the rule was set
+    pub skipped: u64
+}
+
+struct SourceSampling {
+    spec: &'static DataSourceSpec,
+    rule: SamplingRule,
+    received: u64,
+    skipped: u64,
+    last_stored_monotonic_ms: Option<u64>,
+    last_stored_value: Option<Value>
+}
+
+impl SourceSampling {
+    fn new(spec: &'static DataSourceSpec, rule: SamplingRule) -> Self {
+        Self {
+            spec,
+            rule,
+            received: 0,
+            skipped: 0,
+            last_stored_monotonic_ms: None,
+            last_stored_value: None
+        }
+    }
+
+    fn should_store(&mut self, incoming_data: &IncomingData) -> bool {
+        self.received += 1;
+
+        let store = match &self.rule {
+            SamplingRule::All => {true}
+            SamplingRule::Off => {false}
+            SamplingRule::EveryNth(nth) => {(self.received - 1) % *nth as u64 == 0}
+            SamplingRule::MaxRate(hz) => {
+                self.last_stored_monotonic_ms.map_or(true, |last_stored_monotonic_ms| {
+                    incoming_data.monotonic_ms.saturating_sub(last_stored_monotonic_ms) as f64 >= 1000.0 / hz
+                })
+            }
+            // Files are not compared, so sources without telemetry are stored in full
+            SamplingRule::Deadband(deadbands) => {
+                match incoming_data.telemetry.as_ref().and_then(|telemetry| serde_json::to_value(telemetry).ok()) {
+                    Some(value) => {
+                        let changed = self.last_stored_value.as_ref()
+                            .map_or(true, |last_stored_value| exceeds_deadbands(last_stored_value, &value, deadbands));
+                        if changed {
+                            self.last_stored_value = Some(value);
+                        }
+                        changed
+                    }
+                    None => {true}
+                }
+            }
+        };
+
+        if store {
+            self.last_stored_monotonic_ms = Some(incoming_data.monotonic_ms);
+        } else {
+            self.skipped += 1;
+        }
+        return store;
+    }
+
+}
+
+fn exceeds_deadbands(previous: &Value, current: &Value, deadbands: &[(String, f64)]) -> bool {
+    return deadbands.iter().any(|(field, deadband)| match (previous.get(field), current.get(field)) {
+        (Some(previous), Some(current)) => differs(previous, current, *deadband),
+        (previous, current) => previous.is_some() != current.is_some()
+    });
+}
+
+// Numbers differ once further apart than the deadband, anything else once it is not equal
+fn differs(previous: &Value, current: &Value, deadband: f64) -> bool {
+    return match (previous, current) {
+        (Value::Number(previous), Value::Number(current)) => {
+            (previous.as_f64().unwrap_or_default() - current.as_f64().unwrap_or_default()).abs() > deadband
+        }
+        (Value::Object(previous_fields), Value::Object(current_fields)) => {
+            previous_fields.len() != current_fields.len() || current_fields.iter()
+                .any(|(field, value)| previous_fields.get(field).map_or(true, |previous| differs(previous, value, deadband)))
+        }
+        (Value::Array(previous_items), Value::Array(current_items)) => {
+            previous_items.len() != current_items.len() || previous_items.iter().zip(current_items)
+                .any(|(previous, current)| differs(previous, current, deadband))
+        }
+        _ => previous != current
+    };
+}
+
+// Decides which received samples the data manager writes to disk. Rules are set at startup and can be changed
+// while running, which restarts the sampling of the source
+pub struct StorageSampling {
+    sources: Mutex<HashMap<DataSource, SourceSampling>>
+}
+
+impl StorageSampling {
+    pub fn new(sources: &[&'static DataSourceSpec], config: &StorageSamplingConfig) -> Result<Self, String> {
+        let mut rules = parse_sampling_rules(&config.sampling)?;
+
+        let sampling = sources.iter()
+            .map(|spec| {
+                let rule = rules.remove(spec.source.name()).unwrap_or(SamplingRule::All);
+                rule.check_fields(spec)?;
+                return Ok((spec.source, SourceSampling::new(spec, rule)));
+            })
+            .collect::<Result<_, String>>()?;
+
+        if let Some(source) = rules.keys().next() {
+            return Err(format!("Storage sampling configured for unknown data source {}", source));
+        }
+
+        return Ok(Self { sources: Mutex::new(sampling) });
+    }
+
+    pub fn set_rule(&self, source: DataSource, rule: SamplingRule) -> Result<(), String> {
+        let mut sources = self.sources.lock().unwrap();
+        let sampling = sources.get_mut(&source)
+            .ok_or(format!("Unregistered data source {}", source))?;
+        rule.check_fields(sampling.spec)?;
+
+        *sampling = SourceSampling::new(sampling.spec, rule);
+        return Ok(());
+    }
+
+    pub fn get_status(&self) -> BTreeMap<&'static str, SamplingStatus> {
+        return self.sources.lock().unwrap().iter()
+            .map(|(source, sampling)| (source.name(), SamplingStatus { rule: sampling.rule.to_string(), skipped: sampling.skipped }))
+            .collect();
+    }
+
+    // Samples of unregistered sources are left for the data manager to drop
+    pub(super) fn should_store(&self, incoming_data: &IncomingData) -> bool {
+        return self.sources.lock().unwrap().get_mut(&incoming_data.source)
+            .map_or(true, |sampling| sampling.should_store(incoming_data));
+    }
+}
+
+#[cfg(test)]
+mod storage_sampling_tests {
+    use mavlink::common::ATTITUDE_DATA;
+    use crate::application::data_manage::{DataSource, IncomingData, Telemetry};
+    use crate::application::data_manage::storage_sampling::{SamplingRule, StorageSampling, StorageSamplingConfig};
+    use crate::application::tasks::mavlink_adapter::ATTITUDE;
+
+    fn attitude(time_boot_ms: u32, pitch: f32, monotonic_ms: u64) -> IncomingData {
+        let attitude = ATTITUDE_DATA { time_boot_ms, pitch, ..Default::default() };
+        let mut incoming_data = IncomingData::new(ATTITUDE.source, Some(Telemetry::Attitude(attitude)), None);
+        incoming_data.monotonic_ms = monotonic_ms;
+        return incoming_data;
+    }
+
+    fn stored(sampling: &StorageSampling, samples: &[IncomingData]) -> Vec<bool> {
+        return samples.iter().map(|sample| sampling.should_store(sample)).collect();
+    }
+
+    #[test]
+    fn samples_by_rule() {
+        let config = StorageSamplingConfig { sampling: "attitude=every:3".to_string() };
+        let sampling = StorageSampling::new(&[&ATTITUDE], &config).unwrap();
+        let samples: Vec<IncomingData> = (0..5).map(|index| attitude(index * 20, 0.0, index as u64 * 20)).collect();
+        assert_eq!(stored(&sampling, &samples), [true, false, false, true, false]);
+        assert_eq!(sampling.get_status()["attitude"].skipped, 3);
+
+        // 10 Hz keeps one sample in every 100 ms
+        sampling.set_rule(ATTITUDE.source, SamplingRule::parse("max_hz:10").unwrap()).unwrap();
+        let samples: Vec<IncomingData> = [0, 40, 90, 100, 150, 210].iter().map(|offset| attitude(0, 0.0, *offset)).collect();
+        assert_eq!(stored(&sampling, &samples), [true, false, false, true, false, true]);
+
+        // Boot time always changes, only the fields given a deadband are compared
+        sampling.set_rule(ATTITUDE.source, SamplingRule::parse("deadband:pitch=0.1;roll=0.5").unwrap()).unwrap();
+        let samples: Vec<IncomingData> = [0.0, 0.05, 0.09, 0.15, 0.2, 0.3].iter().enumerate()
+            .map(|(index, pitch)| attitude(index as u32 * 1000, *pitch, 0)).collect();
+        assert_eq!(stored(&sampling, &samples), [true, false, false, true, false, true]);
+        assert_eq!(sampling.get_status()["attitude"].rule, "deadband:pitch=0.1;roll=0.5");
+        assert!(sampling.set_rule(ATTITUDE.source, SamplingRule::parse("deadband:altitude=1").unwrap()).is_err());
+        assert!(SamplingRule::parse("deadband:0.1").is_err());
+
+        sampling.set_rule(ATTITUDE.source, SamplingRule::Off).unwrap();
+        assert_eq!(stored(&sampling, &[attitude(0, 0.0, 0)]), [false]);
+        assert_eq!(sampling.get_status()["attitude"].rule, "off");
+        assert!(sampling.set_rule(DataSource::new("unknown"), SamplingRule::All).is_err());
+
+        assert!(SamplingRule::parse("every:0").is_err());
+        assert!(SamplingRule::parse("sometimes").is_err());
+        assert!(StorageSampling::new(&[&ATTITUDE], &StorageSamplingConfig { sampling: "other=off".to_string() }).is_err());
+    }
+}
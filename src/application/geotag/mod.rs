@@ -0,0 +1,332 @@
+use std::io::Cursor;
+use std::sync::Arc;
+use chrono::{DateTime, Local};
+use exif::{Field, In, Rational, Tag, Value};
+use exif::experimental::Writer;
+use serde::Serialize;
+use crate::application::data_manage::Telemetry;
+use crate::application::data_manage::data_bus::DataBus;
+use crate::application::payload_orientator::get_servo_pitch;
+use crate::application::tasks::mavlink_adapter::{ATTITUDE, GLOBAL_POSITION};
+use crate::application::tasks::pib_adapter::PibCommander;
+
+const HEADING_UNKNOWN: u16 = u16::MAX;
+
+const JPEG_SOI: [u8; 2] = [0xFF, 0xD8];
+const JPEG_APP0: u8 = 0xE0;
+const JPEG_APP1: u8 = 0xE1;
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const XMP_NAMESPACE: &str = "urn:firedrone:flightcode:1.0";
+
+const TIFF_ENTRY_LENGTH: usize = 12;
+const TIFF_TAG_XMP: u16 = 700;
+const TIFF_TYPE_BYTE: u16 = 1;
+
+// Position and attitude of the drone, and the payload servo, at the time an image was captured.
+// Fields are missing while the autopilot or PIB have not reported them yet.
+// Serialized as the sidecar <timestamp>.json next to images without embedded metadata
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct GeoTag {
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    #[serde(rename = "altitude")]
+    pub altitude_m: Option<f64>,
+    #[serde(rename = "relative_altitude")]
+    pub relative_altitude_m: Option<f64>,
+    #[serde(rename = "heading")]
+    pub heading_deg: Option<f64>,
+    #[serde(rename = "position_timestamp")]
+    pub position_time: Option<DateTime<Local>>,
+    #[serde(rename = "roll")]
+    pub roll_deg: Option<f64>,
+    #[serde(rename = "pitch")]
+    pub pitch_deg: Option<f64>,
+    #[serde(rename = "yaw")]
+    pub yaw_deg: Option<f64>,
+    #[serde(rename = "attitude_timestamp")]
+    pub attitude_time: Option<DateTime<Local>>,
+    pub servo_value: Option<i8>,
+    // Drone pitch the payload servo was last commanded to compensate
+    #[serde(rename = "servo_angle")]
+    pub servo_angle_deg: Option<f64>
+}
+
+impl GeoTag {
+    // TIFF structured EXIF holding the GPS IFD, None without a position fix
+    pub fn to_exif(&self) -> Option<Vec<u8>> {
+        let (latitude, longitude) = (self.latitude?, self.longitude?);
+
+        let mut fields = vec![
+            Field { tag: Tag::GPSVersionID, ifd_num: In::PRIMARY, value: Value::Byte(vec![2, 3, 0, 0]) },
+            Field { tag: Tag::GPSLatitudeRef, ifd_num: In::PRIMARY, value: Value::Ascii(vec![if latitude < 0.0 {b"S".to_vec()} else {b"N".to_vec()}]) },
+            Field { tag: Tag::GPSLatitude, ifd_num: In::PRIMARY, value: Value::Rational(get_degrees_minutes_seconds(latitude)) },
+            Field { tag: Tag::GPSLongitudeRef, ifd_num: In::PRIMARY, value: Value::Ascii(vec![if longitude < 0.0 {b"W".to_vec()} else {b"E".to_vec()}]) },
+            Field { tag: Tag::GPSLongitude, ifd_num: In::PRIMARY, value: Value::Rational(get_degrees_minutes_seconds(longitude)) }
+        ];
+
+        if let Some(altitude) = self.altitude_m {
+            fields.push(Field { tag: Tag::GPSAltitudeRef, ifd_num: In::PRIMARY, value: Value::Byte(vec![if altitude < 0.0 {1} else {0}]) });
+            fields.push(Field { tag: Tag::GPSAltitude, ifd_num: In::PRIMARY, value: Value::Rational(vec![get_rational(altitude.abs(), 1000)]) });
+        }
+
+        // Heading of the drone, the payload looks along it
+        if let Some(heading) = self.heading_deg {
+            fields.push(Field { tag: Tag::GPSImgDirectionRef, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"T".to_vec()]) });
+            fields.push(Field { tag: Tag::GPSImgDirection, ifd_num: In::PRIMARY, value: Value::Rational(vec![get_rational(heading, 100)]) });
+        }
+
+        let mut writer = Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+
+        let mut exif = Cursor::new(Vec::new());
+        writer.write(&mut exif, true).ok()?;
+        return Some(exif.into_inner());
+    }
+
+    // XMP packet carrying the attitude and servo, which EXIF has no tags for, and the position in the EXIF namespace for
+    // TIFFs, which are tagged with XMP only
+    pub fn to_xmp(&self) -> String {
+        let mut properties = String::new();
+        if let (Some(latitude), Some(longitude)) = (self.latitude, self.longitude) {
+            properties.push_str(&format!(" exif:GPSLatitude=\"{}\" exif:GPSLongitude=\"{}\"",
+                                         get_xmp_coordinate(latitude, 'N', 'S'), get_xmp_coordinate(longitude, 'E', 'W')));
+        }
+        if let Some(altitude) = self.altitude_m {
+            let altitude_ref = if altitude < 0.0 {1} else {0};
+            let altitude = get_rational(altitude.abs(), 1000);
+            properties.push_str(&format!(" exif:GPSAltitudeRef=\"{}\" exif:GPSAltitude=\"{}/{}\"", altitude_ref, altitude.num, altitude.denom));
+        }
+
+        let values = [
+            ("RelativeAltitude", self.relative_altitude_m),
+            ("Roll", self.roll_deg),
+            ("Pitch", self.pitch_deg),
+            ("Yaw", self.yaw_deg),
+            ("ServoValue", self.servo_value.map(f64::from)),
+            ("ServoAngle", self.servo_angle_deg)
+        ];
+        for (name, value) in values {
+            if let Some(value) = value {
+                properties.push_str(&format!(" flightcode:{}=\"{}\"", name, value));
+            }
+        }
+
+        return format!(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\
+             <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\
+             <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\
+             <rdf:Description rdf:about=\"\" xmlns:exif=\"http://ns.adobe.com/exif/1.0/\" xmlns:flightcode=\"{}\"{}/>\
+             </rdf:RDF></x:xmpmeta><?xpacket end=\"w\"?>",
+            XMP_NAMESPACE, properties);
+    }
+
+    // Inserts EXIF and XMP APP1 segments right after the JFIF header of a JPEG
+    pub fn embed_in_jpeg(&self, jpeg: &[u8]) -> Result<Vec<u8>, String> {
+        if !jpeg.starts_with(&JPEG_SOI) {
+            return Err("Not a JPEG image".to_string());
+        }
+
+        let mut insert_at = JPEG_SOI.len();
+        if jpeg.len() >= insert_at + 4 && jpeg[insert_at] == 0xFF && jpeg[insert_at + 1] == JPEG_APP0 {
+            insert_at += 2 + u16::from_be_bytes([jpeg[insert_at + 2], jpeg[insert_at + 3]]) as usize;
+        }
+        if insert_at > jpeg.len() {
+            return Err("Truncated JPEG header".to_string());
+        }
+
+        let mut segments = Vec::new();
+        if let Some(exif) = self.to_exif() {
+            segments.extend(get_app1_segment(&[EXIF_HEADER, &exif].concat())?);
+        }
+        segments.extend(get_app1_segment(&[XMP_HEADER, self.to_xmp().as_bytes()].concat())?);
+
+        return Ok([&jpeg[..insert_at], &segments, &jpeg[insert_at..]].concat());
+    }
+
+    // Appends the XMP packet and a copy of the first IFD holding the XMP tag, then points the header at the copy.
+    // Offsets of the image data stay valid as nothing before the end of the file is moved
+    pub fn embed_in_tiff(&self, tiff: &[u8]) -> Result<Vec<u8>, String> {
+        let little_endian = match tiff.get(..4) {
+            Some(b"II*\0") => true,
+            Some(b"MM\0*") => false,
+            _ => {
+                return Err("Not a TIFF image".to_string());
+            }
+        };
+        let read_u16 = |offset: usize| tiff.get(offset..offset + 2).map(|bytes| read_u16_from(bytes, little_endian));
+        let read_u32 = |offset: usize| tiff.get(offset..offset + 4)
+            .map(|bytes| if little_endian {u32::from_le_bytes(bytes.try_into().unwrap())} else {u32::from_be_bytes(bytes.try_into().unwrap())});
+        let u16_bytes = |value: u16| if little_endian {value.to_le_bytes()} else {value.to_be_bytes()};
+        let u32_bytes = |value: u32| if little_endian {value.to_le_bytes()} else {value.to_be_bytes()};
+
+        let ifd_offset = read_u32(4).ok_or("Truncated TIFF header")? as usize;
+        let entry_count = read_u16(ifd_offset).ok_or("Truncated TIFF IFD")? as usize;
+        let entries_end = ifd_offset + 2 + entry_count * TIFF_ENTRY_LENGTH;
+        let next_ifd_offset = read_u32(entries_end).ok_or("Truncated TIFF IFD")?;
+
+        // Entries are copied as they are, any previous XMP is replaced
+        let mut entries: Vec<(u16, Vec<u8>)> = tiff[ifd_offset + 2..entries_end].chunks(TIFF_ENTRY_LENGTH)
+            .map(|entry| (read_u16_from(entry, little_endian), entry.to_vec()))
+            .filter(|(tag, _)| *tag != TIFF_TAG_XMP)
+            .collect();
+
+        let mut tagged = tiff.to_vec();
+        tagged.resize(tagged.len() + tagged.len() % 2, 0);
+
+        let xmp = self.to_xmp();
+        let xmp_offset = u32::try_from(tagged.len()).map_err(|_| "TIFF too large".to_string())?;
+        tagged.extend_from_slice(xmp.as_bytes());
+        tagged.resize(tagged.len() + tagged.len() % 2, 0);
+
+        let xmp_entry = [&u16_bytes(TIFF_TAG_XMP)[..], &u16_bytes(TIFF_TYPE_BYTE), &u32_bytes(xmp.len() as u32), &u32_bytes(xmp_offset)].concat();
+        entries.push((TIFF_TAG_XMP, xmp_entry));
+        entries.sort_by_key(|(tag, _)| *tag);
+
+        let new_ifd_offset = u32::try_from(tagged.len()).map_err(|_| "TIFF too large".to_string())?;
+        tagged.extend_from_slice(&u16_bytes(entries.len() as u16));
+        for (_, entry) in entries {
+            tagged.extend_from_slice(&entry);
+        }
+        tagged.extend_from_slice(&u32_bytes(next_ifd_offset));
+        tagged[4..8].copy_from_slice(&u32_bytes(new_ifd_offset));
+
+        return Ok(tagged);
+    }
+}
+
+fn read_u16_from(bytes: &[u8], little_endian: bool) -> u16 {
+    return if little_endian {u16::from_le_bytes([bytes[0], bytes[1]])} else {u16::from_be_bytes([bytes[0], bytes[1]])};
+}
+
+// Degrees and decimal minutes followed by the direction, such as 33,42.000000S
+fn get_xmp_coordinate(coordinate: f64, positive: char, negative: char) -> String {
+    let degrees = coordinate.abs().floor();
+    let minutes = (coordinate.abs() - degrees) * 60.0;
+    return format!("{},{:.6}{}", degrees, minutes, if coordinate < 0.0 {negative} else {positive});
+}
+
+fn get_rational(value: f64, denom: u32) -> Rational {
+    return Rational { num: (value * denom as f64).round() as u32, denom };
+}
+
+fn get_degrees_minutes_seconds(coordinate: f64) -> Vec<Rational> {
+    let coordinate = coordinate.abs();
+    let degrees = coordinate.floor();
+    let minutes = ((coordinate - degrees) * 60.0).floor();
+    let seconds = ((coordinate - degrees) * 60.0 - minutes) * 60.0;
+    return vec![get_rational(degrees, 1), get_rational(minutes, 1), get_rational(seconds, 10000)];
+}
+
+fn get_app1_segment(contents: &[u8]) -> Result<Vec<u8>, String> {
+    let length = u16::try_from(contents.len() + 2).map_err(|_| "Metadata too large for a JPEG segment".to_string())?;
+    return Ok([&[0xFF, JPEG_APP1], &length.to_be_bytes()[..], contents].concat());
+}
+
+// Handed to image collectors so every frame is tagged with the latest telemetry at the time it is captured
+#[derive(Clone)]
+pub struct GeoTagger {
+    data_bus: Arc<DataBus>,
+    pib_commander: Arc<PibCommander>
+}
+
+impl GeoTagger {
+    pub fn new(data_bus: Arc<DataBus>, pib_commander: Arc<PibCommander>) -> Self {
+        Self {
+            data_bus,
+            pib_commander
+        }
+    }
+
+    pub fn get_geotag(&self) -> GeoTag {
+        let mut geotag = GeoTag::default();
+
+        if let Some(current_position) = self.data_bus.get_latest(GLOBAL_POSITION.source) {
+            if let Some(Telemetry::GlobalPosition(position)) = current_position.telemetry.as_ref() {
+                geotag.latitude = Some(position.lat as f64 / 1e7);
+                geotag.longitude = Some(position.lon as f64 / 1e7);
+                geotag.altitude_m = Some(position.alt as f64 / 1000.0);
+                geotag.relative_altitude_m = Some(position.relative_alt as f64 / 1000.0);
+                geotag.heading_deg = Some(position.hdg).filter(|hdg| *hdg != HEADING_UNKNOWN).map(|hdg| hdg as f64 / 100.0);
+                geotag.position_time = Some(current_position.time_stamp);
+            }
+        }
+        if let Some(current_attitude) = self.data_bus.get_latest(ATTITUDE.source) {
+            if let Some(Telemetry::Attitude(attitude)) = current_attitude.telemetry.as_ref() {
+                geotag.roll_deg = Some((attitude.roll as f64).to_degrees());
+                geotag.pitch_deg = Some((attitude.pitch as f64).to_degrees());
+                geotag.yaw_deg = Some((attitude.yaw as f64).to_degrees());
+                geotag.attitude_time = Some(current_attitude.time_stamp);
+            }
+        }
+
+        geotag.servo_value = self.pib_commander.get_servo_value();
+        geotag.servo_angle_deg = geotag.servo_value.map(|servo_value| (get_servo_pitch(servo_value) as f64).to_degrees());
+
+        return geotag;
+    }
+}
+
+#[cfg(test)]
+mod geotag_tests {
+    use std::io::Cursor;
+    use std::sync::{Arc, mpsc};
+    use exif::{Context, In, Reader, Tag};
+    use mavlink::common::{ATTITUDE_DATA, GLOBAL_POSITION_INT_DATA};
+    use crate::application::data_manage::{IncomingData, Telemetry};
+    use crate::application::data_manage::data_bus::DataBus;
+    use crate::application::geotag::GeoTagger;
+    use crate::application::simulation::synthetic_tiff::encode_grayscale16;
+    use crate::application::tasks::mavlink_adapter::{ATTITUDE, GLOBAL_POSITION};
+    use crate::application::tasks::pib_adapter::PibCommander;
+
+    #[test]
+    fn tags_from_current_data() {
+        let position = GLOBAL_POSITION_INT_DATA { lat: -337000000, lon: 1511900000, alt: 52500, relative_alt: 30000, hdg: 9000, ..Default::default() };
+        let attitude = ATTITUDE_DATA { roll: 0.0, pitch: -0.5, yaw: 1.0, ..Default::default() };
+
+        let data_bus = Arc::new(DataBus::new(&[&GLOBAL_POSITION, &ATTITUDE]));
+        data_bus.publish(Arc::new(IncomingData::new(GLOBAL_POSITION.source, Some(Telemetry::GlobalPosition(position)), None)));
+        data_bus.publish(Arc::new(IncomingData::new(ATTITUDE.source, Some(Telemetry::Attitude(attitude)), None)));
+
+        let (frame_sender, _frame_recv) = mpsc::sync_channel(1);
+        let pib_commander = Arc::new(PibCommander::new(frame_sender));
+        pib_commander.put_servo_set(127).unwrap();
+        // Queue of the adapter is full, the command is refused rather than blocking and the servo value is unchanged
+        assert!(pib_commander.put_servo_set(0).is_err());
+
+        let geotag = GeoTagger::new(data_bus, pib_commander).get_geotag();
+        assert_eq!(geotag.latitude, Some(-33.7));
+        assert_eq!(geotag.heading_deg, Some(90.0));
+        assert_eq!(geotag.servo_value, Some(127));
+        assert!(geotag.servo_angle_deg.unwrap().abs() < 0.01);
+        assert_eq!(serde_json::to_value(&geotag).unwrap()["relative_altitude"], 30.0);
+
+        // Bare JFIF header followed by the end of image
+        let jpeg = [0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00, 0xFF, 0xD9];
+        let tagged = geotag.embed_in_jpeg(&jpeg).unwrap();
+        assert!(tagged.starts_with(&jpeg[..8]) && tagged.ends_with(&jpeg[8..]));
+        assert!(String::from_utf8_lossy(&tagged).contains("flightcode:Pitch=\"-28.6478"));
+
+        let exif = Reader::new().read_from_container(&mut Cursor::new(tagged)).unwrap();
+        assert_eq!(exif.get_field(Tag::GPSLatitudeRef, In::PRIMARY).unwrap().display_value().to_string(), "S");
+        assert_eq!(exif.get_field(Tag::GPSLatitude, In::PRIMARY).unwrap().display_value().to_string(), "33 deg 42 min 0 sec");
+        assert!(geotag.embed_in_jpeg(b"\x89PNG").is_err());
+
+        let tiff = encode_grayscale16(2, 1, &[29315, 30315]);
+        let tagged = geotag.embed_in_tiff(&tiff).unwrap();
+        assert!(String::from_utf8_lossy(&tagged).contains("exif:GPSLatitude=\"33,42.000000S\""));
+        assert!(tagged.windows(4).any(|pixels| pixels == [0x83, 0x72, 0x6B, 0x76]));
+
+        let exif = Reader::new().read_raw(tagged.clone()).unwrap();
+        assert_eq!(exif.get_field(Tag::ImageWidth, In::PRIMARY).unwrap().value.get_uint(0), Some(2));
+        assert!(exif.get_field(Tag(Context::Tiff, 700), In::PRIMARY).is_some());
+
+        // Tagging again replaces the XMP packet instead of adding another one
+        let retagged = geotag.embed_in_tiff(&tagged).unwrap();
+        assert_eq!(Reader::new().read_raw(retagged).unwrap().fields().filter(|field| field.tag == Tag(Context::Tiff, 700)).count(), 1);
+        assert!(geotag.embed_in_tiff(b"\x89PNG").is_err());
+    }
+}
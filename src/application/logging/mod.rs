@@ -0,0 +1,342 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use chrono::{Local, SecondsFormat};
+use envconfig::Envconfig;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use crate::application::clock::clock;
+
+const LOG_FILE_NAME: &str = "flightcode.log";
+
+// Module paths are logged relative to the application module
+const TARGET_PREFIX: &str = "flightcode::application::";
+const CRATE_PREFIX: &str = "flightcode::";
+
+// Repeat tracking entries are pruned once this many distinct messages have been seen
+const REPEAT_TRACKING_MAX: usize = 256;
+
+#[derive(Envconfig, Debug)]
+pub struct LoggingConfig {
+    // Default level followed by per module overrides, such as "info,pib_adapter=debug,tasks::obc_telem=off"
+    #[envconfig(from = "FLIGHTCODE_LOG", default = "info")]
+    pub filter: String,
+    #[envconfig(from = "FLIGHTCODE_LOG_FILE_MAX_BYTES", default = "5242880")]
+    pub file_max_bytes: u64,
+    // Number of rotated log files kept next to the active one
+    #[envconfig(from = "FLIGHTCODE_LOG_FILE_COUNT", default = "4")]
+    pub file_count: u32,
+    // Identical warnings and errors are logged at most once per interval, with a count of those suppressed
+    #[envconfig(from = "FLIGHTCODE_LOG_REPEAT_INTERVAL_MS", default = "10000")]
+    pub repeat_interval_ms: u64,
+    // Lines kept in memory for the REST API
+    #[envconfig(from = "FLIGHTCODE_LOG_HISTORY_LINES", default = "1000")]
+    pub history_lines: usize
+}
+
+struct LogFilter {
+    default_level: LevelFilter,
+    module_levels: Vec<(String, LevelFilter)>
+}
+
+impl LogFilter {
+    fn parse(filter: &str) -> Result<Self, String> {
+        let mut log_filter = LogFilter { default_level: LevelFilter::Info, module_levels: Vec::new() };
+
+        for directive in filter.split(',').map(|directive| directive.trim()).filter(|directive| !directive.is_empty()) {
+            match directive.split_once('=') {
+                Some((module, level)) => {
+                    let level = level.trim().parse::<LevelFilter>()
+                        .map_err(|_| format!("Invalid log level {} for module {}", level, module))?;
+                    log_filter.module_levels.push((module.trim().to_string(), level));
+                }
+                None => {
+                    log_filter.default_level = directive.parse::<LevelFilter>()
+                        .map_err(|_| format!("Invalid log level {}", directive))?;
+                }
+            }
+        }
+
+        // Most specific module is matched first
+        log_filter.module_levels.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()));
+
+        return Ok(log_filter);
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        return self.module_levels.iter().map(|(_, level)| *level).fold(self.default_level, Ord::max);
+    }
+
+    // A module matches any consecutive run of segments in the target path, so "pib_adapter" matches "flightcode::application::tasks::pib_adapter"
+    fn level_for(&self, target: &str) -> LevelFilter {
+        let target = format!("::{}::", target);
+
+        return self.module_levels.iter()
+            .find(|(module, _)| target.contains(&format!("::{}::", module)))
+            .map_or(self.default_level, |(_, level)| *level);
+    }
+}
+
+struct RepeatLimiter {
+    interval: Duration,
+    last_logged: HashMap<(String, String), (Instant, u32)>
+}
+
+impl RepeatLimiter {
+    // Returns the number of suppressed repeats when the message should be logged, None while it is suppressed
+    fn check(&mut self, target: &str, message: &str) -> Option<u32> {
+        let now = Instant::now();
+        let key = (target.to_string(), message.to_string());
+
+        if let Some((last, suppressed)) = self.last_logged.get_mut(&key) {
+            if now.duration_since(*last).lt(&self.interval) {
+                *suppressed += 1;
+                return None;
+            }
+
+            let repeats = *suppressed;
+            *last = now;
+            *suppressed = 0;
+            return Some(repeats);
+        }
+
+        if self.last_logged.len() >= REPEAT_TRACKING_MAX {
+            let interval = self.interval;
+            self.last_logged.retain(|_, (last, _)| now.duration_since(*last).lt(&interval));
+        }
+        self.last_logged.insert(key, (now, 0));
+
+        return Some(0);
+    }
+}
+
+struct RotatingLogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_bytes: u64,
+    file_count: u32
+}
+
+impl RotatingLogFile {
+    fn open(path: PathBuf, max_bytes: u64, file_count: u32) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        return Ok(Self {
+            path,
+            file,
+            size,
+            max_bytes,
+            file_count
+        });
+    }
+
+    fn get_rotated_path(&self, index: u32) -> PathBuf {
+        return PathBuf::from(format!("{}.{}", self.path.display(), index));
+    }
+
+    // flightcode.log becomes flightcode.log.1, flightcode.log.1 becomes flightcode.log.2 and the oldest is removed
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.sync_all()?;
+
+        if self.file_count == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.get_rotated_path(self.file_count));
+            for index in (1..self.file_count).rev() {
+                let _ = fs::rename(self.get_rotated_path(index), self.get_rotated_path(index + 1));
+            }
+            fs::rename(&self.path, self.get_rotated_path(1))?;
+        }
+
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+
+        return Ok(());
+    }
+
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 + 1 > self.max_bytes {
+            self.rotate()?;
+        }
+
+        writeln!(self.file, "{}", line)?;
+        self.size += line.len() as u64 + 1;
+
+        return Ok(());
+    }
+}
+
+struct LoggerState {
+    repeat_limiter: RepeatLimiter,
+    history: VecDeque<String>,
+    log_file: Option<RotatingLogFile>
+}
+
+// Writes every log line to stdout, the in memory history and the log file once the mission storage directory is known
+struct FlightLogger {
+    filter: LogFilter,
+    config: LoggingConfig,
+    state: Mutex<LoggerState>
+}
+
+impl Log for FlightLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        return metadata.level() <= self.filter.level_for(metadata.target());
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let target = record.target().strip_prefix(TARGET_PREFIX)
+            .or(record.target().strip_prefix(CRATE_PREFIX))
+            .unwrap_or(record.target());
+        let message = record.args().to_string();
+
+        let mut state = self.state.lock().unwrap();
+
+        let mut line = format!("{} {:<5} {}: {}", clock().now().with_timezone(&Local).to_rfc3339_opts(SecondsFormat::Millis, false),
+                               record.level(), target, message);
+
+        if record.level() <= Level::Warn {
+            match state.repeat_limiter.check(target, &message) {
+                None => {
+                    return;
+                }
+                Some(0) => {}
+                Some(repeats) => {
+                    line.push_str(&format!(" (repeated {} more times)", repeats));
+                }
+            }
+        }
+
+        println!("{}", line);
+
+        if let Some(log_file) = state.log_file.as_mut() {
+            if let Err(err) = log_file.write_line(&line) {
+                println!("Failed to write to log file {}: {}", log_file.path.display(), err);
+            }
+        }
+
+        state.history.push_back(line);
+        if state.history.len() > self.config.history_lines {
+            state.history.pop_front();
+        }
+    }
+
+    fn flush(&self) {
+        let state = self.state.lock().unwrap();
+        if let Some(log_file) = state.log_file.as_ref() {
+            let _ = log_file.file.sync_all();
+        }
+    }
+}
+
+static LOGGER: OnceLock<FlightLogger> = OnceLock::new();
+
+pub fn init_logging() -> Result<(), String> {
+    let config = LoggingConfig::init_from_env().unwrap();
+    let filter = LogFilter::parse(&config.filter)?;
+    let max_level = filter.max_level();
+
+    let logger = LOGGER.get_or_init(|| FlightLogger {
+        filter,
+        state: Mutex::new(LoggerState {
+            repeat_limiter: RepeatLimiter { interval: Duration::from_millis(config.repeat_interval_ms), last_logged: HashMap::new() },
+            history: VecDeque::new(),
+            log_file: None
+        }),
+        config
+    });
+
+    log::set_logger(logger).map_err(|err| err.to_string())?;
+    log::set_max_level(max_level);
+
+    return Ok(());
+}
+
+// Lines logged before the first file is opened are written to it from the history, so startup messages are not lost.
+// Opening another file, such as when a mission starts, continues logging there and syncs the previous file
+pub fn open_log_file(storage_dir: &str) {
+    let logger = match LOGGER.get() {
+        Some(logger) => logger,
+        None => return
+    };
+
+    let path = Path::new(storage_dir).join(LOG_FILE_NAME);
+    let log_file = RotatingLogFile::open(path.clone(), logger.config.file_max_bytes, logger.config.file_count);
+    if let Err(err) = log_file {
+        log::error!("Failed to open log file {}: {}", path.display(), err);
+        return;
+    }
+
+    let mut state = logger.state.lock().unwrap();
+    let mut log_file = log_file.unwrap();
+
+    match state.log_file.as_ref() {
+        Some(previous_file) => {
+            let _ = previous_file.file.sync_all();
+        }
+        None => {
+            for line in state.history.iter() {
+                if let Err(err) = log_file.write_line(line) {
+                    println!("Failed to write to log file {}: {}", path.display(), err);
+                    break;
+                }
+            }
+        }
+    }
+    state.log_file = Option::from(log_file);
+}
+
+pub fn get_recent_lines(count: usize) -> Vec<String> {
+    let logger = match LOGGER.get() {
+        Some(logger) => logger,
+        None => return Vec::new()
+    };
+
+    let state = logger.state.lock().unwrap();
+    return state.history.iter().skip(state.history.len().saturating_sub(count)).cloned().collect();
+}
+
+#[cfg(test)]
+mod logging_tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use log::LevelFilter;
+    use crate::application::logging::{LogFilter, RepeatLimiter};
+
+    #[test]
+    fn module_filters() {
+        let filter = LogFilter::parse("warn, tasks=info, tasks::pib_adapter=debug").ok().unwrap();
+
+        assert_eq!(filter.level_for("flightcode::application::tasks::pib_adapter"), LevelFilter::Debug);
+        assert_eq!(filter.level_for("flightcode::application::tasks::obc_telem"), LevelFilter::Info);
+        assert_eq!(filter.level_for("flightcode::application::data_manage"), LevelFilter::Warn);
+        // Partial segment names do not match
+        assert_eq!(filter.level_for("flightcode::application::tasks_extra"), LevelFilter::Warn);
+        assert_eq!(filter.max_level(), LevelFilter::Debug);
+
+        assert!(LogFilter::parse("loud").is_err());
+        assert!(LogFilter::parse("info,pib_adapter=verbose").is_err());
+    }
+
+    #[test]
+    fn repeats_are_suppressed() {
+        let mut limiter = RepeatLimiter { interval: Duration::from_millis(50), last_logged: HashMap::new() };
+
+        assert_eq!(limiter.check("tasks::pib_adapter", "PIB port not connected!"), Some(0));
+        assert_eq!(limiter.check("tasks::pib_adapter", "PIB port not connected!"), None);
+        assert_eq!(limiter.check("tasks::pib_adapter", "PIB port not connected!"), None);
+        assert_eq!(limiter.check("tasks::mavlink_adapter", "PIB port not connected!"), Some(0));
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(limiter.check("tasks::pib_adapter", "PIB port not connected!"), Some(2));
+    }
+}
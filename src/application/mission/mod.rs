@@ -0,0 +1,442 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+use chrono::{DateTime, Local};
+use envconfig::Envconfig;
+use json::{JsonValue, object};
+use log::{error, info, warn};
+use crate::application::clock::{clock, ClockQuality};
+use crate::application::data_manage::{DataSource, DataSourceSpec, DataStorageConfig, IncomingData, Telemetry};
+use crate::application::logging::open_log_file;
+use crate::application::task_config::{BuiltTask, TaskConfig, TaskContext, TaskSpec, TasksConfig};
+use crate::application::task_registry::Dependency;
+use crate::application::tasks::mavlink_adapter::HEARTBEAT;
+use crate::application::timer::{TimedTask, Timer};
+
+const MISSIONS_DIR: &str = "missions";
+const IDLE_DIR: &str = "idle";
+const MANIFEST_FILE: &str = "manifest.json";
+
+// Environment variables with these prefixes are included in the configuration snapshot of each manifest
+const CONFIG_ENV_PREFIXES: &[&str] = &["FLIGHTCODE_", "PIB_", "PIXHAWK_", "GOPRO_", "BATTERY_", "PAYLOAD_", "SIM_"];
+
+const HEARTBEAT_MAX_AGE: Duration = Duration::from_secs(5);
+// Heartbeats arrive at 1 Hz, so this covers the monitor being held back for a while
+const HEARTBEAT_BUFFER: usize = 16;
+
+pub const TASK_SPEC: TaskSpec = TaskSpec { name: "mission_arming", enabled: true, interval_ms: 0, params: &[], sources: &[], build };
+
+#[derive(Envconfig, Debug)]
+pub struct MissionConfig {
+    #[envconfig(from = "FLIGHTCODE_VEHICLE_ID", default = "firedrone")]
+    pub vehicle_id: String
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MissionTrigger {
+    Rest,
+    Arming
+}
+
+impl MissionTrigger {
+    fn name(&self) -> &'static str {
+        match self {
+            MissionTrigger::Rest => {"rest"}
+            MissionTrigger::Arming => {"arming"}
+        }
+    }
+}
+
+// Why a mission could not be started or stopped
+pub enum MissionError {
+    // Not possible in the current state, such as starting while a mission is already active
+    State(String),
+    // The mission directory or manifest could not be written, such as on a full or read-only card
+    Storage(String)
+}
+
+impl fmt::Display for MissionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MissionError::State(err) => write!(f, "{}", err),
+            MissionError::Storage(err) => write!(f, "{}", err)
+        }
+    }
+}
+
+struct Mission {
+    id: u32,
+    name: String,
+    dir: String,
+    start_time: DateTime<Local>,
+    start_clock: ClockQuality,
+    started_by: MissionTrigger,
+    sample_counts: HashMap<DataSource, u64>
+}
+
+struct MissionState {
+    active: Option<Mission>,
+    next_id: u32
+}
+
+// Tracks the active mission, everything stored while no mission is active goes to the idle area of this run
+pub struct MissionManager {
+    vehicle_id: String,
+    missions_dir: String,
+    idle_dir: String,
+    sources: Vec<&'static DataSourceSpec>,
+    config_snapshot: JsonValue,
+    state: Mutex<MissionState>
+}
+
+impl MissionManager {
+    pub fn new(sources: Vec<&'static DataSourceSpec>, tasks_config: &TasksConfig) -> Self {
+        let target_path = DataStorageConfig::init_from_env().unwrap().target_path;
+
+        let missions_dir = format!("{}/{}", target_path, MISSIONS_DIR);
+        // Named without a time, as the clock is rarely synced to GPS time yet when FlightCode starts
+        let idle_root = format!("{}/{}", target_path, IDLE_DIR);
+        let idle_dir = format!("{}/{:04}-boot-{}", idle_root, get_next_mission_id(&idle_root), get_boot_id());
+        // Samples are still published and served without their directory, the failed writes are logged like on a full card
+        if let Err(err) = create_source_directories(&idle_dir, &sources) {
+            error!("{}, data will not be stored until it can be created", err);
+        }
+
+        Self {
+            vehicle_id: MissionConfig::init_from_env().unwrap().vehicle_id,
+            state: Mutex::new(MissionState { active: None, next_id: get_next_mission_id(&missions_dir) }),
+            missions_dir,
+            idle_dir,
+            sources,
+            config_snapshot: get_config_snapshot(tasks_config)
+        }
+    }
+
+    // Directory that incoming data is currently stored in
+    pub fn get_storage_dir(&self) -> String {
+        return self.state.lock().unwrap().active.as_ref()
+            .map_or(self.idle_dir.clone(), |mission| mission.dir.clone());
+    }
+
+    // Counts a sample towards the active mission, returning the directory it is to be stored in. Both are taken under the
+    // same lock, so a sample counted for a mission is never stored after the mission has stopped
+    pub fn record_sample(&self, source: DataSource) -> String {
+        return match self.state.lock().unwrap().active.as_mut() {
+            Some(mission) => {
+                *mission.sample_counts.entry(source).or_insert(0) += 1;
+                mission.dir.clone()
+            }
+            None => self.idle_dir.clone()
+        };
+    }
+
+    pub fn is_active(&self) -> bool {
+        return self.state.lock().unwrap().active.is_some();
+    }
+
+    // Nothing that can fail runs in a way that could panic while the state is locked, as a poisoned lock would stop
+    // the data manager from storing anything for the rest of the flight
+    pub fn start(&self, name: Option<String>, started_by: MissionTrigger) -> Result<JsonValue, MissionError> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(mission) = state.active.as_ref() {
+            return Err(MissionError::State(format!("Mission {} is already active", mission.id)));
+        }
+
+        let id = state.next_id;
+        let name = name.map(|name| sanitize_name(&name)).filter(|name| !name.is_empty())
+            .unwrap_or(format!("mission-{}", id));
+        let dir = format!("{}/{:04}-{}", self.missions_dir, id, name);
+
+        create_source_directories(&dir, &self.sources).map_err(MissionError::Storage)?;
+
+        let mission = Mission {
+            id,
+            name,
+            dir,
+            start_time: clock().now().with_timezone(&Local),
+            start_clock: clock().get_quality(),
+            started_by,
+            sample_counts: HashMap::new()
+        };
+
+        let manifest = self.get_manifest(&mission, None);
+        write_manifest(&mission.dir, &manifest).map_err(MissionError::Storage)?;
+
+        info!("Started mission {} {} by {}", mission.id, mission.name, started_by.name());
+        open_log_file(&mission.dir);
+
+        state.next_id = id + 1;
+        state.active = Option::from(mission);
+
+        return Ok(manifest);
+    }
+
+    pub fn stop(&self) -> Result<JsonValue, MissionError> {
+        let mut state = self.state.lock().unwrap();
+
+        let mission = match state.active.take() {
+            Some(mission) => mission,
+            None => {
+                return Err(MissionError::State("No mission is active".to_string()));
+            }
+        };
+
+        let manifest = self.get_manifest(&mission, Option::from(clock().now().with_timezone(&Local)));
+        let result = write_manifest(&mission.dir, &manifest);
+
+        info!("Stopped mission {} {}", mission.id, mission.name);
+        open_log_file(&self.idle_dir);
+
+        return result.map(|_| manifest).map_err(MissionError::Storage);
+    }
+
+    pub fn get_active_trigger(&self) -> Option<MissionTrigger> {
+        return self.state.lock().unwrap().active.as_ref().map(|mission| mission.started_by);
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let state = self.state.lock().unwrap();
+
+        return match state.active.as_ref() {
+            Some(mission) => self.get_manifest(mission, None),
+            None => object!{ active: false }
+        };
+    }
+
+    // Manifests of every mission stored on this vehicle, oldest first
+    pub fn get_past_missions(&self) -> JsonValue {
+        let missions: Vec<JsonValue> = get_mission_dirs(&self.missions_dir).into_iter()
+            .filter_map(|(_, dir)| read_manifest(&dir))
+            .collect();
+
+        return JsonValue::from(missions);
+    }
+
+    pub fn get_mission_dir(&self, id: u32) -> Option<String> {
+        return get_mission_dirs(&self.missions_dir).into_iter()
+            .find(|(mission_id, _)| *mission_id == id)
+            .map(|(_, dir)| dir.to_string_lossy().to_string());
+    }
+
+    // Recorded in the manifest once the mission data has been copied off the vehicle, making it eligible for pruning
+    pub fn mark_offloaded(&self, id: u32) -> Result<JsonValue, String> {
+        if self.state.lock().unwrap().active.as_ref().is_some_and(|mission| mission.id == id) {
+            return Err(format!("Mission {} is still active", id));
+        }
+
+        let dir = self.get_mission_dir(id).ok_or(format!("Mission {} does not exist", id))?;
+
+        let mut manifest = read_manifest(Path::new(&dir)).ok_or(format!("Mission {} has no readable manifest", id))?;
+        manifest["offloaded_time"] = JsonValue::from(clock().now().with_timezone(&Local).to_rfc3339());
+        write_manifest(&dir, &manifest)?;
+
+        info!("Marked mission {} as offloaded", id);
+        return Ok(manifest);
+    }
+
+    // Deletes the oldest mission marked as offloaded, returning its directory
+    pub fn prune_oldest_offloaded(&self) -> Option<String> {
+        let active_id = self.state.lock().unwrap().active.as_ref().map(|mission| mission.id);
+
+        let (_, dir) = get_mission_dirs(&self.missions_dir).into_iter()
+            .filter(|(id, _)| Some(*id) != active_id)
+            .find(|(_, dir)| read_manifest(dir).is_some_and(|manifest| !manifest["offloaded_time"].is_null()))?;
+
+        if let Err(err) = fs::remove_dir_all(&dir) {
+            warn!("Failed to prune mission {}: {}", dir.display(), err);
+            return None;
+        }
+
+        return Option::from(dir.to_string_lossy().to_string());
+    }
+
+    fn get_manifest(&self, mission: &Mission, end_time: Option<DateTime<Local>>) -> JsonValue {
+        let mut sources = JsonValue::new_object();
+        for spec in self.sources.iter() {
+            sources[spec.source.name()] = object!{
+                samples: *mission.sample_counts.get(&spec.source).unwrap_or(&0)
+            };
+        }
+
+        return object!{
+            active: end_time.is_none(),
+            id: mission.id,
+            name: mission.name.clone(),
+            vehicle_id: self.vehicle_id.clone(),
+            software_version: env!("CARGO_PKG_VERSION"),
+            started_by: mission.started_by.name(),
+            start_time: mission.start_time.to_rfc3339(),
+            end_time: end_time.map(|end_time| end_time.to_rfc3339()),
+            clock: object!{
+                start: get_clock_json(&mission.start_clock),
+                latest: get_clock_json(&clock().get_quality())
+            },
+            config: self.config_snapshot.clone(),
+            sources: sources
+        };
+    }
+}
+
+fn create_source_directories(storage_dir: &str, sources: &[&DataSourceSpec]) -> Result<(), String> {
+    for spec in sources {
+        let source_dir = format!("{}/{}", storage_dir, spec.source);
+        if !Path::new(&source_dir).exists() {
+            fs::create_dir_all(&source_dir).map_err(|err| format!("Failed to create directory {}: {}", source_dir, err))?;
+        }
+    }
+    return Ok(());
+}
+
+// Missions are stored as <id>-<name>, sorted oldest first
+fn get_mission_dirs(missions_dir: &str) -> Vec<(u32, PathBuf)> {
+    let mut mission_dirs: Vec<(u32, PathBuf)> = fs::read_dir(missions_dir).into_iter().flatten().flatten()
+        .filter_map(|entry| entry.file_name().to_str()
+            .and_then(|name| name.split('-').next())
+            .and_then(|id| id.parse::<u32>().ok())
+            .map(|id| (id, entry.path())))
+        .collect();
+
+    mission_dirs.sort_by_key(|(id, _)| *id);
+    return mission_dirs;
+}
+
+// Numbering continues from the highest id already stored
+fn get_next_mission_id(missions_dir: &str) -> u32 {
+    return get_mission_dirs(missions_dir).last().map_or(1, |(id, _)| id + 1);
+}
+
+// Short form of the kernel's random id of the current boot, which tells runs apart that never got a GPS time
+fn get_boot_id() -> String {
+    return fs::read_to_string("/proc/sys/kernel/random/boot_id").ok()
+        .map(|boot_id| boot_id.trim().chars().filter(|c| c.is_ascii_hexdigit()).take(8).collect::<String>())
+        .filter(|boot_id| !boot_id.is_empty())
+        .unwrap_or("unknown".to_string());
+}
+
+fn read_manifest(mission_dir: &Path) -> Option<JsonValue> {
+    return fs::read_to_string(mission_dir.join(MANIFEST_FILE)).ok()
+        .and_then(|manifest| json::parse(&manifest).ok());
+}
+
+fn sanitize_name(name: &str) -> String {
+    return name.trim().chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' {c} else {'_'})
+        .collect();
+}
+
+// Source and quality of the time that mission timestamps were taken from
+fn get_clock_json(clock_quality: &ClockQuality) -> JsonValue {
+    return serde_json::to_string(clock_quality).ok()
+        .and_then(|clock_quality| json::parse(&clock_quality).ok())
+        .unwrap_or(JsonValue::Null);
+}
+
+fn get_config_snapshot(tasks_config: &TasksConfig) -> JsonValue {
+    let tasks = serde_json::to_string(tasks_config).ok()
+        .and_then(|tasks| json::parse(&tasks).ok())
+        .unwrap_or(JsonValue::Null);
+
+    let mut environment = JsonValue::new_object();
+    let mut variables: Vec<(String, String)> = env::vars()
+        .filter(|(key, _)| CONFIG_ENV_PREFIXES.iter().any(|prefix| key.starts_with(prefix)))
+        .collect();
+    variables.sort();
+    for (key, value) in variables {
+        environment[key.as_str()] = JsonValue::from(value);
+    }
+
+    return object!{
+        tasks: tasks["tasks"].clone(),
+        environment: environment
+    };
+}
+
+// Written next to the file and renamed over it, so a power loss leaves either the old or the new manifest
+fn write_manifest(mission_dir: &str, manifest: &JsonValue) -> Result<(), String> {
+    let path = format!("{}/{}", mission_dir, MANIFEST_FILE);
+    let temp_path = format!("{}.tmp", path);
+
+    fs::write(&temp_path, manifest.pretty(2))
+        .and_then(|_| fs::File::open(&temp_path)?.sync_all())
+        .and_then(|_| fs::rename(&temp_path, &path))
+        .map_err(|err| format!("Failed to write mission manifest {}: {}", path, err))?;
+
+    return Ok(());
+}
+
+fn build(context: &TaskContext, config: &TaskConfig) -> Option<BuiltTask> {
+    let arming_monitor = ArmingMonitor {
+        heartbeats: context.data_bus.subscribe_samples(HEARTBEAT.source, HEARTBEAT_BUFFER),
+        mission_manager: context.mission_manager.clone(),
+        last_armed: None
+    };
+
+    let arming_monitor_timer = Timer::on_data("MissionArming".to_string(), context.data_bus.subscribe(HEARTBEAT.source))
+        .rate_limit(config.interval())
+        .depends_on(Dependency::FreshData(HEARTBEAT.source, HEARTBEAT_MAX_AGE));
+
+    return Some((arming_monitor_timer, Box::from(arming_monitor)));
+}
+
+// Starts a mission when the vehicle arms and stops it on disarming, missions started over REST are left alone.
+// Only changes of the armed state are acted on, so a mission stopped over REST while armed is not restarted.
+// Every heartbeat is looked at, so arming briefly between two executions is not missed
+struct ArmingMonitor {
+    heartbeats: Receiver<Arc<IncomingData>>,
+    mission_manager: Arc<MissionManager>,
+    last_armed: Option<bool>
+}
+
+impl ArmingMonitor {
+    fn on_armed(&mut self, armed: bool) {
+        if self.last_armed == Some(armed) {
+            return;
+        }
+        self.last_armed = Option::from(armed);
+
+        if armed && !self.mission_manager.is_active() {
+            if let Err(err) = self.mission_manager.start(None, MissionTrigger::Arming) {
+                warn!("Failed to start mission on arming: {}", err);
+            }
+        } else if !armed && self.mission_manager.get_active_trigger() == Some(MissionTrigger::Arming) {
+            if let Err(err) = self.mission_manager.stop() {
+                warn!("Failed to stop mission on disarming: {}", err);
+            }
+        }
+    }
+}
+
+impl TimedTask for ArmingMonitor {
+    fn execute(&mut self) -> () {
+        while let Ok(heartbeat) = self.heartbeats.try_recv() {
+            if let Some(Telemetry::Heartbeat(heartbeat)) = heartbeat.telemetry.as_ref() {
+                self.on_armed(heartbeat.armed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod mission_tests {
+    use crate::application::mission::{get_next_mission_id, sanitize_name};
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn mission_ids_continue_numbering() {
+        let missions_dir = TempDir::new("missions");
+        assert_eq!(get_next_mission_id(missions_dir.to_str().unwrap()), 1);
+
+        std::fs::create_dir_all(missions_dir.join("0001-survey")).unwrap();
+        std::fs::create_dir_all(missions_dir.join("0007-mission-7")).unwrap();
+        std::fs::create_dir_all(missions_dir.join("notes")).unwrap();
+        assert_eq!(get_next_mission_id(missions_dir.to_str().unwrap()), 8);
+
+        assert_eq!(sanitize_name(" north field/2 "), "north_field_2");
+    }
+}
@@ -1,92 +1,252 @@
+use std::cell::Cell;
+use std::fs;
+use std::path::Path;
+use std::process;
 use std::sync::{Arc, mpsc};
-use std::sync::mpsc::SyncSender;
 use std::time::Duration;
-use crate::application::battery_monitor::spawn_battery_monitor;
-use crate::application::tasks::capture_go_pro_images::GoProTask;
-use crate::application::data_manage::{IncomingData, spawn_data_manager};
-use crate::application::payload_orientator::spawn_payload_orientator;
+use envconfig::Envconfig;
+use log::{error, info, warn};
+use crate::application::battery_monitor::BatteryMonitorConfig;
+use crate::application::clock::ClockConfig;
+use crate::application::tasks::capture_go_pro_images::GoProConfig;
+use crate::application::data_manage::{DataStorageConfig, spawn_data_manager};
+use crate::application::data_manage::data_bus::DataBus;
+use crate::application::data_manage::disk_guard::{DiskGuard, DiskGuardConfig};
+use crate::application::data_manage::image_writer::{ImageWriter, ImageWriterConfig};
+use crate::application::data_manage::jsonl_stream::{convert_to_legacy, recover_segments, TelemetryStorageConfig};
+use crate::application::data_manage::recent_samples::{RecentSamples, RecentSamplesConfig};
+use crate::application::data_manage::storage_queue::{storage_queue, StorageQueueConfig, StorageSender};
+use crate::application::data_manage::storage_sampling::{StorageSampling, StorageSamplingConfig};
+use crate::application::geotag::GeoTagger;
+use crate::application::logging::{init_logging, LoggingConfig, open_log_file};
+use crate::application::mission::{MissionConfig, MissionManager};
 use crate::application::rest_api_server::spawn_rest_server;
-use crate::application::tasks::capture_ircam_images::CaptureIrImages;
-use crate::application::tasks::capture_picam_images::CapturePiCamImages;
-use crate::application::tasks::example_task::ExampleTask;
-use crate::application::tasks::pib_adapter::{PibAdapter, PibCommander};
-use crate::application::tasks::mavlink_adapter::MavlinkAdapter;
-use crate::application::tasks::obc_telem::ObcTelem;
-use crate::application::timer::{spawn_timer, Timer};
+use crate::application::tasks::pib_adapter::{PibAdapterConfig, PibCommander};
+use crate::application::tasks::mavlink_adapter::MavlinkConfig;
+use crate::application::payload_orientator::PayloadOrientatorConfig;
+use crate::application::shutdown::{shutdown_all, ShutdownHandle};
+use crate::application::simulation::{SimulatedFlight, SimulationConfig};
+use crate::application::systemd_notify::SystemdNotifier;
+use crate::application::task_config::{get_data_sources, load_tasks_config, TASK_SPECS, TaskConfigLocation, TaskContext, TasksConfig};
+use crate::application::task_registry::TaskRegistry;
+use crate::application::timer::spawn_timer;
+use crate::mission_data::integrity::{IntegrityDigests, recover_manifests};
 
 mod timer;
 mod tasks;
-mod data_manage;
+pub mod data_manage;
 mod rest_api_server;
 mod battery_monitor;
 mod payload_orientator;
+mod task_registry;
+mod systemd_notify;
+mod task_config;
+mod shutdown;
+mod simulation;
+mod logging;
+mod mission;
+pub(crate) mod geotag;
+mod clock;
+
+// Adapters draining the PIB and PixHawk links sleep up to 10 seconds between reconnection attempts
+const ADAPTER_STALL_TIMEOUT: Duration = Duration::from_secs(20);
+
+// Time each task or service is given to stop before shutdown moves on without it
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub trait DataCollector {
-    fn new(storage_sender: SyncSender<IncomingData>) -> Self;
+    fn new(storage_sender: StorageSender) -> Self;
 }
 
-pub fn start_application() {
-    let (queue_sender, queue_recv) = mpsc::sync_channel(15);
+pub fn print_config() {
+    let tasks_config = load_tasks_config_or_exit();
 
-    let current_data = spawn_data_manager(queue_recv);
+    println!("# Tasks");
+    println!("{}", tasks_config.to_toml());
 
-    let example_task = ExampleTask::new(queue_sender.clone());
-    let example_timer = Timer::new("Example_Task".to_string(), Duration::from_secs(1));
-    let example_timer_handler = spawn_timer(example_timer, Box::from(example_task));
+    println!("# Environment");
+    println!("{:#?}", TaskConfigLocation::init_from_env().unwrap());
+    println!("{:#?}", DataStorageConfig::init_from_env().unwrap());
+    println!("{:#?}", DiskGuardConfig::init_from_env().unwrap());
+    println!("{:#?}", TelemetryStorageConfig::init_from_env().unwrap());
+    println!("{:#?}", ClockConfig::init_from_env().unwrap());
+    println!("{:#?}", StorageQueueConfig::init_from_env().unwrap());
+    println!("{:#?}", ImageWriterConfig::init_from_env().unwrap());
+    println!("{:#?}", RecentSamplesConfig::init_from_env().unwrap());
+    println!("{:#?}", StorageSamplingConfig::init_from_env().unwrap());
+    println!("{:#?}", LoggingConfig::init_from_env().unwrap());
+    println!("{:#?}", MissionConfig::init_from_env().unwrap());
+    println!("{:#?}", PibAdapterConfig::init_from_env().unwrap());
+    println!("{:#?}", MavlinkConfig::init_from_env().unwrap());
+    println!("{:#?}", GoProConfig::init_from_env().unwrap());
+    println!("{:#?}", BatteryMonitorConfig::init_from_env().unwrap());
+    println!("{:#?}", PayloadOrientatorConfig::init_from_env().unwrap());
+    println!("{:#?}", SimulationConfig::init_from_env().unwrap());
+}
 
-    let example_task1 = ExampleTask::new(queue_sender.clone());
-    let example_timer1 = Timer::new("Example_Task1".to_string(), Duration::from_secs(2));
-    let example_timer1_handler = spawn_timer(example_timer1, Box::from(example_task1));
+fn load_tasks_config_or_exit() -> TasksConfig {
+    match load_tasks_config() {
+        Ok(tasks_config) => {
+            return tasks_config;
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(2);
+        }
+    }
+}
 
-    let gopro_task = GoProTask::new();
-    let gopro_timer = Timer::new("GoProControl".to_string(), Duration::from_secs(5));
-    let gopro_handler = spawn_timer(gopro_timer, Box::from(gopro_task));
+// Writes the telemetry of every source in a mission or idle directory as <source>/<source>.json in the legacy format,
+// exiting with 1 when any source failed to convert and with 2 when the directory cannot be read
+pub fn convert_legacy(storage_dir: &str) {
+    let source_dirs = match fs::read_dir(storage_dir) {
+        Ok(entries) => entries.flatten().filter(|entry| entry.path().is_dir()),
+        Err(err) => {
+            eprintln!("Failed to read {}: {}", storage_dir, err);
+            process::exit(2);
+        }
+    };
 
-    let ir_cam_task = CaptureIrImages::new(queue_sender.clone());
-    let ir_cam_timer = Timer::new("IrCamCapture".to_string(), Duration::from_secs(1));
-    let ir_cam_handler = spawn_timer(ir_cam_timer, Box::from(ir_cam_task));
+    let mut failed = false;
+    for source_dir in source_dirs {
+        let source_name = source_dir.file_name().to_string_lossy().to_string();
+        let output = source_dir.path().join(format!("{}.json", source_name));
 
-    let pi_cam_task = CapturePiCamImages::new(queue_sender.clone());
-    let pi_cam_timer = Timer::new("PiCamCapture".to_string(), Duration::from_secs(1));
-    let pi_cam_handler = spawn_timer(pi_cam_timer, Box::from(pi_cam_task));
+        match convert_to_legacy(&source_dir.path(), &source_name, &output) {
+            Ok(0) => {
+                let _ = fs::remove_file(&output);
+            }
+            Ok(records) => {
+                println!("Converted {} records of {} to {}", records, source_name, output.display());
+            }
+            Err(err) => {
+                eprintln!("Failed to convert {}: {}", source_name, err);
+                failed = true;
+            }
+        }
+    }
 
-    let (frame_sender, frame_recv) = mpsc::sync_channel(10);
-    let pib_adapter_task = PibAdapter::new(queue_sender.clone(), frame_recv);
-    let pib_adapter_timer = Timer::new("PIBAdapter".to_string(), Duration::from_secs(0));
-    let pib_adapter_handler = spawn_timer(pib_adapter_timer, Box::from(pib_adapter_task));
-    let pib_commander = Arc::new(PibCommander::new(frame_sender));
+    if failed {
+        process::exit(1);
+    }
+}
 
-    // TODO: make polling intervals config parameters
-    let (mavlink_cmd_sender, mavlink_cmd_recv) = mpsc::sync_channel(3);
-    let mavlink_adapter = MavlinkAdapter::new(queue_sender.clone(), mavlink_cmd_recv);
-    let mavlink_adapter_timer = Timer::new("MavlinkAdapter".to_string(), Duration::from_millis(0));
-    let mavlink_adapter_handler = spawn_timer(mavlink_adapter_timer, Box::from(mavlink_adapter));
+// Simulated mode replaces the hardware facing tasks with stand-ins driven by a scripted flight
+pub fn start_application(simulated: bool) {
+    if let Err(err) = init_logging() {
+        eprintln!("Failed to initialize logging: {}", err);
+        process::exit(2);
+    }
 
-    let obc_telemetry = ObcTelem::new(queue_sender.clone());
-    let obc_telemetry_timer = Timer::new("ObcTelemetry".to_string(), Duration::from_secs(1));
-    let obc_telemetry_handler = spawn_timer(obc_telemetry_timer, Box::from(obc_telemetry));
+    let tasks_config = load_tasks_config_or_exit();
 
-    spawn_rest_server(current_data.clone());
+    // Telemetry torn by a power cut during the previous run is truncated back to its last intact record
+    let target_path = DataStorageConfig::init_from_env().unwrap().target_path;
+    for (segment, result) in recover_segments(Path::new(&target_path)) {
+        match result {
+            Ok(removed) => warn!("Removed {} bytes of torn records from {}", removed, segment.display()),
+            Err(err) => error!("Failed to recover {}: {}", segment.display(), err)
+        }
+    }
 
-    spawn_battery_monitor(current_data.clone(), mavlink_cmd_sender.clone());
+    // Integrity manifests of the directories in use at the time describe the files before recovery, so they are rebuilt
+    for (storage_dir, result) in recover_manifests(Path::new(&target_path)) {
+        match result {
+            Ok(files) => warn!("Rebuilt integrity manifest of {} from {} recovered files", storage_dir.display(), files),
+            Err(err) => error!("Failed to rebuild integrity manifest of {}: {}", storage_dir.display(), err)
+        }
+    }
 
-    spawn_payload_orientator(current_data.clone(), pib_commander.clone());
+    let flight = if simulated {
+        info!("Running in simulation mode, no hardware will be accessed");
+        Some(Arc::new(SimulatedFlight::new()))
+    } else {
+        None
+    };
 
-    let (ctrlc_tx, ctrlc_rx) = mpsc::channel();
-    ctrlc::set_handler(move || {
-        example_timer_handler.send(true).expect("Failed to send kill signal to collection task");
-        example_timer1_handler.send(true).expect("Failed to send kill signal to collection task");
+    let (queue_sender, queue_recv) = match storage_queue(&get_data_sources(), &StorageQueueConfig::init_from_env().unwrap()) {
+        Ok(queue) => queue,
+        Err(err) => {
+            error!("{}", err);
+            process::exit(2);
+        }
+    };
+
+    let recent_samples = match RecentSamples::new(&get_data_sources(), &RecentSamplesConfig::init_from_env().unwrap()) {
+        Ok(recent_samples) => Arc::new(recent_samples),
+        Err(err) => {
+            error!("{}", err);
+            process::exit(2);
+        }
+    };
+
+    let storage_sampling = match StorageSampling::new(&get_data_sources(), &StorageSamplingConfig::init_from_env().unwrap()) {
+        Ok(storage_sampling) => Arc::new(storage_sampling),
+        Err(err) => {
+            error!("{}", err);
+            process::exit(2);
+        }
+    };
+
+    let data_bus = Arc::new(DataBus::new(&get_data_sources()));
+    let mission_manager = Arc::new(MissionManager::new(get_data_sources(), &tasks_config));
+    open_log_file(&mission_manager.get_storage_dir());
+    let disk_guard_config = DiskGuardConfig::init_from_env().unwrap();
+    let disk_guard = disk_guard_config.enabled.then(|| DiskGuard::new(disk_guard_config));
+    let integrity = Arc::new(IntegrityDigests::new());
+    let image_writer = ImageWriter::spawn(&ImageWriterConfig::init_from_env().unwrap(), integrity.clone());
+    let data_manager_handle = spawn_data_manager(mission_manager.clone(), get_data_sources(), queue_recv, data_bus.clone(),
+                                                 recent_samples.clone(), storage_sampling.clone(), disk_guard, image_writer, integrity);
 
-        gopro_handler.send(true).expect("Failed to send kill signal to collection task");
-        ir_cam_handler.send(true).expect("Failed to send kill signal to collection task");
-        pi_cam_handler.send(true).expect("Failed to send kill signal to collection task");
-        pib_adapter_handler.send(true).expect("Failed to send kill signal to collection task");
-        mavlink_adapter_handler.send(true).expect("Failed to send kill signal to collection task");
-        obc_telemetry_handler.send(true).expect("Failed to send kill signal to collection task");
+    let task_registry = Arc::new(TaskRegistry::new(data_bus.clone()));
 
+    let rest_server_handle = spawn_rest_server(data_bus.clone(), recent_samples.clone(), storage_sampling.clone(), task_registry.clone(),
+                                               mission_manager.clone());
+
+    // Created ahead of the cameras, which tag their images with the servo value last commanded
+    let (frame_sender, frame_recv) = mpsc::sync_channel(10);
+    let pib_commander = Arc::new(PibCommander::new(frame_sender));
+    // No task commands the PixHawk yet, so the MAVLink adapter drains a channel without senders
+    let (_, mavlink_cmd_recv) = mpsc::sync_channel(3);
+    let task_context = TaskContext {
+        flight,
+        storage_sender: queue_sender.clone(),
+        data_bus: data_bus.clone(),
+        mission_manager: mission_manager.clone(),
+        geotagger: GeoTagger::new(data_bus.clone(), pib_commander.clone()),
+        pib_commander,
+        pib_frames: Cell::new(Some(frame_recv)),
+        mavlink_commands: Cell::new(Some(mavlink_cmd_recv))
+    };
+
+    // Tasks are started in the order of their specs and stopped in reverse, so consumers stop before the adapters they command
+    let mut task_handlers: Vec<ShutdownHandle> = Vec::new();
+    for spec in TASK_SPECS {
+        let task_config = tasks_config.get(spec.name);
+        if !task_config.enabled {
+            continue;
+        }
+
+        if let Some((timer, task)) = (spec.build)(&task_context, task_config) {
+            task_handlers.push(spawn_timer(timer, task, task_registry.clone()));
+        }
+    }
+
+    // Tasks hold their own senders, so the data manager drains once the queue sender below is dropped
+    drop(task_context);
+
+    // Handles both SIGINT and SIGTERM, as sent by systemd when stopping the service
+    let (ctrlc_tx, ctrlc_rx) = mpsc::channel();
+    ctrlc::set_handler(move || {
         ctrlc_tx.send(true).expect("Failed to send signal to shutdown main thread!");
     }).expect("Error setting Ctrl-C handler");
 
+    let mut systemd_notifier = SystemdNotifier::from_env();
+    if systemd_notifier.is_some() {
+        systemd_notifier.as_ref().unwrap().ready();
+    }
+
+    let mut unhealthy_tasks: Vec<String> = Vec::new();
     let mut must_quit = false;
     while !must_quit {
         let contr_shutdown = ctrlc_rx.recv_timeout(Duration::from_millis(100));
@@ -95,5 +255,56 @@ pub fn start_application() {
                 must_quit = true;
             }
         }
+
+        // Watchdog is only fed while the main loop is running and all critical tasks are making progress
+        if systemd_notifier.is_some() && systemd_notifier.as_ref().unwrap().is_watchdog_due() {
+            let notifier = systemd_notifier.as_mut().unwrap();
+
+            let mut current_unhealthy = task_registry.get_unhealthy_tasks();
+            current_unhealthy.sort();
+            if current_unhealthy != unhealthy_tasks {
+                if current_unhealthy.is_empty() {
+                    notifier.status("All critical tasks healthy");
+                } else {
+                    warn!("Withholding watchdog, critical tasks stalled: {}", current_unhealthy.join(", "));
+                    notifier.status(&format!("Critical tasks stalled: {}", current_unhealthy.join(", ")));
+                }
+                unhealthy_tasks = current_unhealthy;
+            }
+
+            if unhealthy_tasks.is_empty() {
+                notifier.watchdog();
+            }
+        }
+    }
+
+    info!("Shutting down FlightCode");
+    if systemd_notifier.is_some() {
+        systemd_notifier.as_ref().unwrap().stopping();
     }
+
+    let mut shutdown_handles = vec![rest_server_handle];
+    shutdown_handles.extend(task_handlers.into_iter().rev());
+    let mut failures = shutdown_all(shutdown_handles, SHUTDOWN_TIMEOUT);
+
+    // Data manager stores everything queued by the stopped tasks before syncing to disk
+    drop(queue_sender);
+    failures.extend(shutdown_all(vec![data_manager_handle], SHUTDOWN_TIMEOUT));
+
+    // Manifest is finalized last so its sample counts include everything the data manager stored
+    if mission_manager.is_active() {
+        if let Err(err) = mission_manager.stop() {
+            error!("{}", err);
+            failures.push(err.to_string());
+        }
+    }
+
+    if !failures.is_empty() {
+        error!("FlightCode shut down with {} failures", failures.len());
+        log::logger().flush();
+        process::exit(1);
+    }
+
+    info!("FlightCode shut down cleanly");
+    log::logger().flush();
 }
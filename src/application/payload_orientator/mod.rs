@@ -1,48 +1,49 @@
-use std::sync::{Arc, Mutex};
-use std::thread;
-use std::thread::sleep;
+use std::sync::Arc;
 use std::time::Duration;
-use actix_web::http::StatusCode;
-use actix_web::HttpResponse;
-use crate::application::data_manage::{DataSource, IncomingData};
+use envconfig::Envconfig;
+use log::{info, warn};
+use crate::application::data_manage::Telemetry;
+use crate::application::data_manage::data_bus::DataBus;
+use crate::application::task_config::{BuiltTask, TaskConfig, TaskContext, TaskSpec};
+use crate::application::task_registry::Dependency;
+use crate::application::tasks::mavlink_adapter::ATTITUDE;
+use crate::application::tasks::pib_adapter;
 use crate::application::tasks::pib_adapter::PibCommander;
+use crate::application::timer::{TimedTask, Timer};
 
-pub fn spawn_payload_orientator(current_data_storage: Arc<Mutex<Box<[Option<IncomingData>; DataSource::COUNT]>>>, pib_commander: Arc<PibCommander>) {
-    thread::spawn(move || {
-        payload_orientator_loop(current_data_storage, pib_commander);
-    });
-}
-
-fn get_drone_orientation(current_data_storage: Arc<Mutex<Box<[Option<IncomingData>; DataSource::COUNT]>>>) -> f32 {
-    let current_attitude_result = &current_data_storage.lock().unwrap()[DataSource::Attitude as usize];
+const ATTITUDE_MAX_AGE: Duration = Duration::from_secs(1);
 
-    if current_attitude_result.is_none() || current_attitude_result.as_ref().unwrap().serialized.is_none() {
-        return 0.0;
-    }
+pub const TASK_SPEC: TaskSpec = TaskSpec { name: "payload_orientator", enabled: true, interval_ms: 50, params: &[], sources: &[], build };
 
-    let json_result = current_attitude_result.as_ref().unwrap().serialized.as_ref().unwrap().to_string();
+#[derive(Envconfig, Debug)]
+pub struct PayloadOrientatorConfig {
+    // Servo value the payload is parked at on shutdown
+    #[envconfig(from = "PAYLOAD_STOW_SERVO_VALUE", default = "0")]
+    pub stow_servo_value: i8
+}
 
-    // Find the position of the substring
-    let substring = "\"pitch\":";
-    let start_index = match json_result.find(substring) {
-        Some(index) => index + substring.len(),
-        None => {
-            return 0.0;
-        }
+fn build(context: &TaskContext, config: &TaskConfig) -> Option<BuiltTask> {
+    let attitude_changes = context.data_bus.subscribe(ATTITUDE.source);
+    let orientator = PayloadOrientator {
+        data_bus: context.data_bus.clone(),
+        pib_commander: context.pib_commander.clone(),
+        stow_servo_value: PayloadOrientatorConfig::init_from_env().unwrap().stow_servo_value
     };
 
-    // Extract the substring containing the float
-    let float_str = &json_result[start_index..start_index+5];
+    // Servo is only commanded once attitude estimations are arriving, otherwise it would be driven to an end stop.
+    // No servo frames are sent while the PIB adapter is disabled or stalled
+    let orientator_timer = Timer::on_data("PayloadOrientator".to_string(), attitude_changes)
+        .rate_limit(config.interval())
+        .depends_on(Dependency::FreshData(ATTITUDE.source, ATTITUDE_MAX_AGE))
+        .depends_on(Dependency::Task(pib_adapter::TIMER_NAME.to_string()));
 
-    // Parse the float from the extracted substring
-    match float_str.trim().parse::<f32>() {
-        Ok(float_value) => {
-            return float_value;
-        }
-        Err(_) => {
-            println!("Failed to parse float");
-            return 0.0;
-        }
+    return Some((orientator_timer, Box::from(orientator)));
+}
+
+fn get_drone_orientation(data_bus: &DataBus) -> f32 {
+    match data_bus.get_latest(ATTITUDE.source).as_ref().and_then(|current_attitude| current_attitude.telemetry.as_ref()) {
+        Some(Telemetry::Attitude(attitude)) => {attitude.pitch}
+        _ => {0.0}
     }
 }
 
@@ -52,9 +53,20 @@ const PITCH_ANGLE_MAX: f32 = 0.0;
 const SERVO_VALUE_MIN: f32 = -128.0;
 const SERVO_VALUE_MAX: f32 = 127.0;
 
-fn payload_orientator_loop(current_data_storage: Arc<Mutex<Box<[Option<IncomingData>; DataSource::COUNT]>>>, pib_commander: Arc<PibCommander>) {
-    loop {
-        let pitch = get_drone_orientation(current_data_storage.clone());
+// Inverse of the mapping below, the drone pitch in radians a servo value compensates
+pub fn get_servo_pitch(servo_value: i8) -> f32 {
+    return ((servo_value as f32 - SERVO_VALUE_MIN) / (SERVO_VALUE_MAX - SERVO_VALUE_MIN)) * (PITCH_ANGLE_MAX - PITCH_ANGLE_MIN) + PITCH_ANGLE_MIN;
+}
+
+struct PayloadOrientator {
+    data_bus: Arc<DataBus>,
+    pib_commander: Arc<PibCommander>,
+    stow_servo_value: i8
+}
+
+impl TimedTask for PayloadOrientator {
+    fn execute(&mut self) -> () {
+        let pitch = get_drone_orientation(&self.data_bus);
 
         // 90 degrees downwards(copter) 122 : 90 degrees up (cruise) -128
         // pitch value 0 for copter : pitch value 1.58 for cruise:
@@ -62,8 +74,13 @@ fn payload_orientator_loop(current_data_storage: Arc<Mutex<Box<[Option<IncomingD
         // Perform the linear mapping
         let servo_value = (((pitch - PITCH_ANGLE_MIN) / (PITCH_ANGLE_MAX - PITCH_ANGLE_MIN)) * (SERVO_VALUE_MAX - SERVO_VALUE_MIN) + SERVO_VALUE_MIN) as i8;
 
-        pib_commander.put_servo_set(servo_value);
+        if let Err(err) = self.pib_commander.put_servo_set(servo_value) {
+            warn!("Failed to command payload servo: {}", err);
+        }
+    }
 
-        sleep(Duration::from_millis(50)); // 20 hz
+    fn shutdown(&mut self) -> Result<(), String> {
+        info!("Stowing payload at servo value {}", self.stow_servo_value);
+        return self.pib_commander.put_servo_set(self.stow_servo_value);
     }
 }
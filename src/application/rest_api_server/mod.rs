@@ -1,25 +1,40 @@
-use std::sync::{Arc, Mutex};
+use std::path::Path;
+use std::sync::{Arc, mpsc};
+use std::sync::mpsc::Receiver;
 use std::thread;
-use std::thread::sleep;
-use std::time::Duration;
 use actix_files::Files;
 use actix_web::{App, HttpResponse, HttpServer, web};
 use actix_web::http::StatusCode;
-use crate::application::data_manage::{DataSource, get_data_source_by_name, IncomingData};
+use chrono::Local;
+use json::JsonValue;
+use serde::Deserialize;
+use crate::application::clock::clock;
+use crate::application::data_manage::ContentType;
+use crate::application::data_manage::data_bus::DataBus;
+use crate::application::data_manage::history::{HistoryQuery, LIMIT_DEFAULT, LIMIT_MAX, query_history};
+use crate::application::data_manage::recent_samples::RecentSamples;
+use crate::application::data_manage::storage_sampling::{SamplingRule, StorageSampling};
+use crate::application::logging::get_recent_lines;
+use crate::application::mission::{MissionError, MissionManager, MissionTrigger};
+use crate::application::shutdown::ShutdownHandle;
+use crate::application::task_config::{DATA_MANAGER_SOURCES, find_data_source, TASK_SPECS};
+use crate::application::task_registry::TaskRegistry;
+use crate::mission_data::track_export::{read_track, TrackFormat};
 
-async fn handle_get_request(current_data_storage: web::Data<Arc<Mutex<Box<[Option<IncomingData>; DataSource::COUNT]>>>>,
+async fn handle_get_request(data_bus: web::Data<Arc<DataBus>>,
                             path: web::Path<(String,)>) -> HttpResponse {
     let source_string = path.0.clone();
 
-    let source = get_data_source_by_name(source_string.clone());
+    let spec = match find_data_source(&source_string) {
+        Some(spec) => spec,
+        None => {
+            return HttpResponse::build(StatusCode::BAD_REQUEST)
+                .content_type("text")
+                .body(format!("Invalid data source: {}!", source_string.clone()));
+        }
+    };
 
-    if source == DataSource::Invalid {
-        return HttpResponse::build(StatusCode::BAD_REQUEST)
-            .content_type("text")
-            .body(format!("Invalid data source: {}!", source_string.clone()));
-    }
-
-    let current_data_result = &current_data_storage.lock().unwrap()[source as usize];
+    let current_data_result = data_bus.get_latest(spec.source);
 
     if current_data_result.is_none() {
         return HttpResponse::build(StatusCode::NO_CONTENT)
@@ -27,20 +42,24 @@ async fn handle_get_request(current_data_storage: web::Data<Arc<Mutex<Box<[Optio
             .body(format!("There is no data yet for: {}", source_string.clone()));
     }
 
-    let current_data = current_data_result.as_ref().unwrap();
+    let current_data = current_data_result.unwrap();
 
     if current_data.file.is_some() {
         let response_data = current_data.file.as_ref().unwrap();
         return HttpResponse::build(StatusCode::OK)
-            .content_type("image/png")
+            .content_type(current_data.get_content_type(spec).mime_type())
             .body(response_data.clone());
     }
 
-    if current_data.serialized.is_some() {
-        let current_data = current_data.serialized.as_ref().unwrap().to_string();
-        return HttpResponse::build(StatusCode::OK)
-            .content_type("json")
-            .body(current_data);
+    if let Some(telemetry) = current_data.telemetry.as_ref() {
+        return match serde_json::to_string(telemetry) {
+            Ok(body) => HttpResponse::build(StatusCode::OK)
+                .content_type(spec.content_type.mime_type())
+                .body(body),
+            Err(err) => HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+                .content_type("text")
+                .body(err.to_string())
+        };
     }
 
     return HttpResponse::build(StatusCode::NO_CONTENT)
@@ -48,23 +67,354 @@ async fn handle_get_request(current_data_storage: web::Data<Arc<Mutex<Box<[Optio
         .body(format!("Empty incoming data for: {}!", source_string.clone()));
 }
 
-pub fn spawn_rest_server(current_data_storage: Arc<Mutex<Box<[Option<IncomingData>; DataSource::COUNT]>>>) {
-    sleep(Duration::from_secs(10));
+#[derive(Deserialize)]
+struct HistoryRequest {
+    from: Option<String>,
+    to: Option<String>,
+    limit: Option<usize>,
+    offset: Option<usize>,
+    decimate: Option<usize>,
+    mission: Option<u32>
+}
+
+impl HistoryRequest {
+    fn to_query(&self) -> Result<HistoryQuery, String> {
+        return Ok(HistoryQuery {
+            from: self.from.as_deref().map(HistoryQuery::parse_time).transpose()?,
+            to: self.to.as_deref().map(HistoryQuery::parse_time).transpose()?,
+            decimate: self.decimate.unwrap_or(1).max(1),
+            offset: self.offset.unwrap_or(0),
+            limit: self.limit.unwrap_or(LIMIT_DEFAULT).clamp(1, LIMIT_MAX)
+        });
+    }
+}
+
+// Stored telemetry of the current mission, or of a past mission by id
+async fn handle_history_request(mission_manager: web::Data<Arc<MissionManager>>, path: web::Path<(String,)>,
+                                request: web::Query<HistoryRequest>) -> HttpResponse {
+    let source_string = path.0.clone();
+
+    let spec = match find_data_source(&source_string) {
+        Some(spec) if spec.content_type == ContentType::Json => spec,
+        Some(_) => {
+            return HttpResponse::build(StatusCode::BAD_REQUEST)
+                .content_type("text")
+                .body(format!("History is only stored for telemetry sources, {} is not one", source_string));
+        }
+        None => {
+            return HttpResponse::build(StatusCode::BAD_REQUEST)
+                .content_type("text")
+                .body(format!("Invalid data source: {}!", source_string));
+        }
+    };
+
+    let query = match request.to_query() {
+        Ok(query) => query,
+        Err(err) => {
+            return HttpResponse::build(StatusCode::BAD_REQUEST)
+                .content_type("text")
+                .body(err);
+        }
+    };
+
+    let storage_dir = match request.mission {
+        Some(id) => match mission_manager.get_mission_dir(id) {
+            Some(dir) => dir,
+            None => {
+                return HttpResponse::build(StatusCode::NOT_FOUND)
+                    .content_type("text")
+                    .body(format!("Mission {} does not exist", id));
+            }
+        },
+        None => mission_manager.get_storage_dir()
+    };
+
+    let source_dir = Path::new(&storage_dir).join(spec.source.name());
+
+    // Reading back a long flight can take a while, so it is kept off the server's worker threads
+    let history = web::block(move || query_history(&source_dir, spec.source.name(), &query)).await;
+
+    return match history {
+        Ok(Ok(history)) => HttpResponse::build(StatusCode::OK)
+            .content_type("json")
+            .body(history.to_string()),
+        Ok(Err(err)) => HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+            .content_type("text")
+            .body(err),
+        Err(err) => HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+            .content_type("text")
+            .body(err.to_string())
+    };
+}
+
+#[derive(Deserialize)]
+struct RecentRequest {
+    seconds: Option<u32>
+}
+
+// Samples kept in memory for the source, files are listed by time, media type, size and geotag without their contents
+async fn handle_recent_request(recent_samples: web::Data<Arc<RecentSamples>>, path: web::Path<(String,)>,
+                               request: web::Query<RecentRequest>) -> HttpResponse {
+    let source_string = path.0.clone();
+
+    let spec = match find_data_source(&source_string) {
+        Some(spec) => spec,
+        None => {
+            return HttpResponse::build(StatusCode::BAD_REQUEST)
+                .content_type("text")
+                .body(format!("Invalid data source: {}!", source_string));
+        }
+    };
+
+    let since = request.seconds.map(|seconds| clock().now().with_timezone(&Local) - chrono::Duration::seconds(seconds as i64));
+    let samples: Vec<serde_json::Value> = recent_samples.get_window(spec.source, since).iter()
+        .map(|sample| match sample.file.as_ref() {
+            Some(file) => serde_json::json!({
+                "timestamp": sample.time_stamp.to_rfc3339(),
+                "content_type": sample.get_content_type(spec).mime_type(),
+                "bytes": file.len(),
+                "geotag": sample.geotag
+            }),
+            None => serde_json::json!({ "timestamp": sample.time_stamp.to_rfc3339(), "data": sample.telemetry })
+        })
+        .collect();
+
+    return HttpResponse::build(StatusCode::OK)
+        .content_type("json")
+        .body(serde_json::Value::from(samples).to_string());
+}
+
+async fn handle_sources_request() -> HttpResponse {
+    let sources: Vec<JsonValue> = TASK_SPECS.iter()
+        .flat_map(|task| task.sources.iter().map(move |spec| {
+            let mut source = spec.to_json();
+            source["task"] = JsonValue::from(task.name);
+            source["default_interval_ms"] = JsonValue::from(task.interval_ms);
+            source
+        }))
+        .chain(DATA_MANAGER_SOURCES.iter().map(|spec| {
+            let mut source = spec.to_json();
+            source["task"] = JsonValue::from("data_manager");
+            source["default_interval_ms"] = JsonValue::Null;
+            source
+        }))
+        .collect();
+
+    return HttpResponse::build(StatusCode::OK)
+        .content_type("json")
+        .body(JsonValue::from(sources).to_string());
+}
+
+async fn handle_storage_sampling_request(storage_sampling: web::Data<Arc<StorageSampling>>) -> HttpResponse {
+    return match serde_json::to_string(&storage_sampling.get_status()) {
+        Ok(status) => HttpResponse::build(StatusCode::OK)
+            .content_type("json")
+            .body(status),
+        Err(err) => HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+            .content_type("text")
+            .body(format!("Failed to serialize storage sampling: {}", err))
+    };
+}
+
+#[derive(Deserialize)]
+struct SamplingRequest {
+    rule: String
+}
+
+// Changes which samples of the source are stored until the next restart, such as {"rule": "max_hz:5"}
+async fn handle_storage_sampling_update(storage_sampling: web::Data<Arc<StorageSampling>>, path: web::Path<(String,)>,
+                                        request: web::Json<SamplingRequest>) -> HttpResponse {
+    let source_string = path.0.clone();
+
+    let spec = match find_data_source(&source_string) {
+        Some(spec) => spec,
+        None => {
+            return HttpResponse::build(StatusCode::BAD_REQUEST)
+                .content_type("text")
+                .body(format!("Invalid data source: {}!", source_string.clone()));
+        }
+    };
+
+    let result = SamplingRule::parse(&request.rule)
+        .and_then(|rule| storage_sampling.set_rule(spec.source, rule));
+    if let Err(err) = result {
+        return HttpResponse::build(StatusCode::BAD_REQUEST)
+            .content_type("text")
+            .body(err);
+    }
+
+    return handle_storage_sampling_request(storage_sampling).await;
+}
+
+async fn handle_task_status_request(task_registry: web::Data<Arc<TaskRegistry>>) -> HttpResponse {
+    return HttpResponse::build(StatusCode::OK)
+        .content_type("json")
+        .body(task_registry.to_json().to_string());
+}
+
+const LOG_LINES_DEFAULT: usize = 100;
 
-    thread::spawn(|| {
-        let _ = run_rest_server(current_data_storage);
+#[derive(Deserialize)]
+struct LogQuery {
+    lines: Option<usize>
+}
+
+async fn handle_log_request(query: web::Query<LogQuery>) -> HttpResponse {
+    let lines = get_recent_lines(query.lines.unwrap_or(LOG_LINES_DEFAULT));
+
+    return HttpResponse::build(StatusCode::OK)
+        .content_type("json")
+        .body(JsonValue::from(lines).to_string());
+}
+
+#[derive(Deserialize)]
+struct MissionStartRequest {
+    name: Option<String>
+}
+
+async fn handle_mission_start_request(mission_manager: web::Data<Arc<MissionManager>>,
+                                      request: Option<web::Json<MissionStartRequest>>) -> HttpResponse {
+    let name = request.and_then(|request| request.into_inner().name);
+
+    return match mission_manager.start(name, MissionTrigger::Rest) {
+        Ok(manifest) => HttpResponse::build(StatusCode::OK)
+            .content_type("json")
+            .body(manifest.to_string()),
+        Err(err) => get_mission_error_response(err)
+    };
+}
+
+async fn handle_mission_stop_request(mission_manager: web::Data<Arc<MissionManager>>) -> HttpResponse {
+    return match mission_manager.stop() {
+        Ok(manifest) => HttpResponse::build(StatusCode::OK)
+            .content_type("json")
+            .body(manifest.to_string()),
+        Err(err) => get_mission_error_response(err)
+    };
+}
+
+// Requests that do not fit the current mission state conflict with it, failures to write the mission are server errors
+fn get_mission_error_response(err: MissionError) -> HttpResponse {
+    let status = match err {
+        MissionError::State(_) => {StatusCode::CONFLICT}
+        MissionError::Storage(_) => {StatusCode::INTERNAL_SERVER_ERROR}
+    };
+
+    return HttpResponse::build(status)
+        .content_type("text")
+        .body(err.to_string());
+}
+
+async fn handle_mission_request(mission_manager: web::Data<Arc<MissionManager>>) -> HttpResponse {
+    return HttpResponse::build(StatusCode::OK)
+        .content_type("json")
+        .body(mission_manager.to_json().to_string());
+}
+
+async fn handle_missions_request(mission_manager: web::Data<Arc<MissionManager>>) -> HttpResponse {
+    return HttpResponse::build(StatusCode::OK)
+        .content_type("json")
+        .body(mission_manager.get_past_missions().to_string());
+}
+
+async fn handle_mission_offloaded_request(mission_manager: web::Data<Arc<MissionManager>>,
+                                          path: web::Path<(u32,)>) -> HttpResponse {
+    return match mission_manager.mark_offloaded(path.0) {
+        Ok(manifest) => HttpResponse::build(StatusCode::OK)
+            .content_type("json")
+            .body(manifest.to_string()),
+        Err(err) => HttpResponse::build(StatusCode::CONFLICT)
+            .content_type("text")
+            .body(err)
+    };
+}
+
+// Flight track and image capture points of a mission as GPX, KML or GeoJSON, downloaded as track.<format>
+async fn handle_mission_track_request(mission_manager: web::Data<Arc<MissionManager>>,
+                                      path: web::Path<(u32, String)>) -> HttpResponse {
+    let (id, format_name) = path.into_inner();
+
+    let format = match TrackFormat::from_name(&format_name) {
+        Some(format) => format,
+        None => {
+            return HttpResponse::build(StatusCode::BAD_REQUEST)
+                .content_type("text")
+                .body(format!("Unknown track format {}, expected gpx, kml or geojson", format_name));
+        }
+    };
+
+    let mission_dir = match mission_manager.get_mission_dir(id) {
+        Some(dir) => dir,
+        None => {
+            return HttpResponse::build(StatusCode::NOT_FOUND)
+                .content_type("text")
+                .body(format!("Mission {} does not exist", id));
+        }
+    };
+
+    let track = web::block(move || read_track(Path::new(&mission_dir)).map(|track| track.export(format))).await;
+
+    return match track {
+        Ok(Ok(track)) => HttpResponse::build(StatusCode::OK)
+            .content_type(format.mime_type())
+            .insert_header(("Content-Disposition", format!("attachment; filename=\"mission-{}-track.{}\"", id, format.extension())))
+            .body(track),
+        Ok(Err(err)) => HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+            .content_type("text")
+            .body(err),
+        Err(err) => HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR)
+            .content_type("text")
+            .body(err.to_string())
+    };
+}
+
+pub fn spawn_rest_server(data_bus: Arc<DataBus>, recent_samples: Arc<RecentSamples>, storage_sampling: Arc<StorageSampling>,
+                         task_registry: Arc<TaskRegistry>, mission_manager: Arc<MissionManager>) -> ShutdownHandle {
+    let (kill_sender, kill_recv) = mpsc::channel();
+
+    let join_handle = thread::spawn(|| {
+        return run_rest_server(data_bus, recent_samples, storage_sampling, task_registry, mission_manager, kill_recv)
+            .map_err(|err| err.to_string());
     });
+
+    return ShutdownHandle::new("RestApiServer".to_string(), kill_sender, join_handle);
 }
 
 #[actix_web::main]
-async fn run_rest_server(current_data_storage: Arc<Mutex<Box<[Option<IncomingData>; DataSource::COUNT]>>>) -> std::io::Result<()> {
-    return HttpServer::new(move || {
+async fn run_rest_server(data_bus: Arc<DataBus>, recent_samples: Arc<RecentSamples>, storage_sampling: Arc<StorageSampling>,
+                         task_registry: Arc<TaskRegistry>, mission_manager: Arc<MissionManager>, kill_recv: Receiver<bool>) -> std::io::Result<()> {
+    let server = HttpServer::new(move || {
                 App::new()
-                    .app_data(web::Data::new(current_data_storage.clone()))
+                    .app_data(web::Data::new(data_bus.clone()))
+                    .app_data(web::Data::new(recent_samples.clone()))
+                    .app_data(web::Data::new(storage_sampling.clone()))
+                    .app_data(web::Data::new(task_registry.clone()))
+                    .app_data(web::Data::new(mission_manager.clone()))
+                    .route("/api/status/tasks", web::get().to(handle_task_status_request))
+                    .route("/api/logs", web::get().to(handle_log_request))
+                    .route("/api/sources", web::get().to(handle_sources_request))
+                    .route("/api/storage/sampling", web::get().to(handle_storage_sampling_request))
+                    .route("/api/storage/sampling/{data_source}", web::put().to(handle_storage_sampling_update))
+                    .route("/api/mission", web::get().to(handle_mission_request))
+                    .route("/api/mission/start", web::post().to(handle_mission_start_request))
+                    .route("/api/mission/stop", web::post().to(handle_mission_stop_request))
+                    .route("/api/missions", web::get().to(handle_missions_request))
+                    .route("/api/missions/{id}/offloaded", web::post().to(handle_mission_offloaded_request))
+                    .route("/api/missions/{id}/track.{format}", web::get().to(handle_mission_track_request))
+                    .route("/api/{data_source}/recent", web::get().to(handle_recent_request))
+                    .route("/api/{data_source}/history", web::get().to(handle_history_request))
                     .route("/api/{data_source}", web::get().to(handle_get_request))
                     .service(Files::new("/", "./src/application/rest_api_server/frontend/out").index_file("index.html"))
             })
             .bind("0.0.0.0:8080").expect("Failed to bind address for REST API server!")
-            .run()
-            .await;
+            .run();
+
+    // Gracefully stop the server, finishing in flight requests, once the kill signal arrives
+    let server_handle = server.handle();
+    actix_web::rt::spawn(async move {
+        let _ = web::block(move || kill_recv.recv()).await;
+        server_handle.stop(true).await;
+    });
+
+    return server.await;
 }
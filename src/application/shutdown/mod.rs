@@ -0,0 +1,67 @@
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use log::{error, info};
+
+const SHUTDOWN_POLL_PERIOD: Duration = Duration::from_millis(10);
+
+// Stops a task or service thread by sending it a kill signal and waiting for it to finish cleaning up
+pub struct ShutdownHandle {
+    name: String,
+    kill_sender: Sender<bool>,
+    join_handle: JoinHandle<Result<(), String>>
+}
+
+impl ShutdownHandle {
+    pub fn new(name: String, kill_sender: Sender<bool>, join_handle: JoinHandle<Result<(), String>>) -> Self {
+        Self {
+            name,
+            kill_sender,
+            join_handle
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        return &self.name;
+    }
+
+    // Thread is left running if it does not finish within the timeout, such as when blocked on a read
+    pub fn shutdown(self, timeout: Duration) -> Result<(), String> {
+        // Thread may have already exited and dropped its receiver
+        let _ = self.kill_sender.send(true);
+
+        let deadline = Instant::now() + timeout;
+        while !self.join_handle.is_finished() {
+            if Instant::now() >= deadline {
+                return Err(format!("{} did not stop within {} ms", self.name, timeout.as_millis()));
+            }
+            thread::sleep(SHUTDOWN_POLL_PERIOD);
+        }
+
+        match self.join_handle.join() {
+            Ok(result) => {
+                return result.map_err(|err| format!("{}: {}", self.name, err));
+            }
+            Err(_) => {
+                return Err(format!("{} panicked", self.name));
+            }
+        }
+    }
+}
+
+// Stops the handles in order and returns every failure that occurred along the way
+pub fn shutdown_all(handles: Vec<ShutdownHandle>, timeout: Duration) -> Vec<String> {
+    let mut failures = Vec::new();
+
+    for handle in handles {
+        info!("Stopping {}", handle.get_name());
+
+        if let Err(err) = handle.shutdown(timeout) {
+            error!("Shutdown failure: {}", err);
+            failures.push(err);
+        }
+    }
+
+    return failures;
+}
@@ -0,0 +1,203 @@
+use std::f64::consts::PI;
+use std::time::{Duration, Instant};
+use chrono::Utc;
+use envconfig::Envconfig;
+
+pub mod sim_cameras;
+pub mod sim_mavlink;
+pub mod sim_obc_telem;
+pub mod sim_pib;
+mod synthetic_png;
+pub mod synthetic_tiff;
+
+#[derive(Envconfig, Debug)]
+pub struct SimulationConfig {
+    #[envconfig(from = "SIM_HOME_LATITUDE", default = "49.8075")]
+    pub home_latitude: f64,
+    #[envconfig(from = "SIM_HOME_LONGITUDE", default = "-97.1366")]
+    pub home_longitude: f64,
+    #[envconfig(from = "SIM_HOME_ALTITUDE", default = "232.0")]
+    pub home_altitude: f64,
+    #[envconfig(from = "SIM_ORBIT_RADIUS", default = "250.0")]
+    pub orbit_radius: f64,
+    #[envconfig(from = "SIM_CRUISE_ALTITUDE", default = "80.0")]
+    pub cruise_altitude: f64,
+    #[envconfig(from = "SIM_CRUISE_SPEED", default = "15.0")]
+    pub cruise_speed: f64,
+    #[envconfig(from = "SIM_ORBIT_COUNT", default = "2")]
+    pub orbit_count: u32,
+    // How far the system clock is behind the simulated GPS time, as on a Pi that booted without network time
+    #[envconfig(from = "SIM_SYSTEM_CLOCK_ERROR_S", default = "0")]
+    pub system_clock_error_s: i64
+}
+
+const EARTH_RADIUS: f64 = 6_371_000.0;
+const GRAVITY: f64 = 9.81;
+
+// Scripted flight phase durations in seconds, cruise lasts for the configured number of orbits
+const ARMED_DURATION: f64 = 10.0;
+const CLIMB_DURATION: f64 = 30.0;
+const TRANSITION_DURATION: f64 = 10.0;
+const DESCENT_DURATION: f64 = 30.0;
+const LANDED_DURATION: f64 = 30.0;
+
+// Pitch reported by the PixHawk while hovering as a copter and while in cruise flight
+const COPTER_PITCH: f64 = 0.0;
+const CRUISE_PITCH: f64 = -1.5;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FlightPhase {
+    Landed,
+    Climb,
+    Transition,
+    Cruise,
+    BackTransition,
+    Descent
+}
+
+// Consistent vehicle state at a point in the flight script
+#[derive(Clone, Copy)]
+pub struct FlightState {
+    pub phase: FlightPhase,
+    pub armed: bool,
+    pub time_boot_ms: u32,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: f64,
+    pub relative_altitude: f64,
+    pub velocity_north: f64,
+    pub velocity_east: f64,
+    pub velocity_down: f64,
+    pub roll: f64,
+    pub pitch: f64,
+    pub yaw: f64
+}
+
+// Repeating scripted flight: climb as a copter, transition, orbit home in cruise, transition back and land
+pub struct SimulatedFlight {
+    config: SimulationConfig,
+    boot_time: Instant
+}
+
+impl SimulatedFlight {
+    pub fn new() -> Self {
+        Self {
+            config: SimulationConfig::init_from_env().unwrap(),
+            boot_time: Instant::now()
+        }
+    }
+
+    // Position in meters north and east of home
+    pub fn get_offset_from_home(&self, state: &FlightState) -> (f64, f64) {
+        let north = (state.latitude - self.config.home_latitude).to_radians() * EARTH_RADIUS;
+        let east = (state.longitude - self.config.home_longitude).to_radians() * EARTH_RADIUS * self.config.home_latitude.to_radians().cos();
+        return (north, east);
+    }
+
+    // GPS time in microseconds since the Unix epoch
+    pub fn get_gps_time_us(&self) -> u64 {
+        return (Utc::now().timestamp_micros() + self.config.system_clock_error_s * 1_000_000) as u64;
+    }
+
+    pub fn get_state(&self) -> FlightState {
+        return self.get_state_at(self.boot_time.elapsed());
+    }
+
+    pub fn get_state_at(&self, since_boot: Duration) -> FlightState {
+        let config = &self.config;
+
+        let orbit_period = 2.0 * PI * config.orbit_radius / config.cruise_speed;
+        let climb_rate = config.cruise_altitude / CLIMB_DURATION;
+        let descent_rate = config.cruise_altitude / DESCENT_DURATION;
+
+        const ARM_END: f64 = ARMED_DURATION;
+        const CLIMB_END: f64 = ARM_END + CLIMB_DURATION;
+        const TRANSITION_END: f64 = CLIMB_END + TRANSITION_DURATION;
+        let cruise_end = TRANSITION_END + orbit_period * config.orbit_count as f64;
+        let back_transition_end = cruise_end + TRANSITION_DURATION;
+        let descent_end = back_transition_end + DESCENT_DURATION;
+        let script_length = descent_end + LANDED_DURATION;
+
+        let t = since_boot.as_secs_f64() % script_length;
+
+        let (phase, relative_altitude, velocity_down, pitch) = if t < ARM_END {
+            (FlightPhase::Landed, 0.0, 0.0, COPTER_PITCH)
+        } else if t < CLIMB_END {
+            (FlightPhase::Climb, (t - ARM_END) * climb_rate, -climb_rate, COPTER_PITCH)
+        } else if t < TRANSITION_END {
+            let progress = (t - CLIMB_END) / TRANSITION_DURATION;
+            (FlightPhase::Transition, config.cruise_altitude, 0.0, COPTER_PITCH + (CRUISE_PITCH - COPTER_PITCH) * progress)
+        } else if t < cruise_end {
+            // Small pitch oscillation so the payload orientator has something to follow
+            (FlightPhase::Cruise, config.cruise_altitude, 0.0, CRUISE_PITCH + 0.05 * (t * 0.5).sin())
+        } else if t < back_transition_end {
+            let progress = (t - cruise_end) / TRANSITION_DURATION;
+            (FlightPhase::BackTransition, config.cruise_altitude, 0.0, CRUISE_PITCH + (COPTER_PITCH - CRUISE_PITCH) * progress)
+        } else if t < descent_end {
+            (FlightPhase::Descent, config.cruise_altitude - (t - back_transition_end) * descent_rate, descent_rate, COPTER_PITCH)
+        } else {
+            (FlightPhase::Landed, 0.0, 0.0, COPTER_PITCH)
+        };
+
+        // Orbit is centered east of home so that it starts and ends overhead home, flown clockwise seen from above
+        let (north, east, velocity_north, velocity_east, roll, yaw) = if phase == FlightPhase::Cruise {
+            let angle = PI - 2.0 * PI * (t - TRANSITION_END) / orbit_period;
+            let north = config.orbit_radius * angle.sin();
+            let east = config.orbit_radius + config.orbit_radius * angle.cos();
+            let velocity_north = -config.cruise_speed * angle.cos();
+            let velocity_east = config.cruise_speed * angle.sin();
+            let roll = (config.cruise_speed * config.cruise_speed / (GRAVITY * config.orbit_radius)).atan();
+            (north, east, velocity_north, velocity_east, roll, velocity_east.atan2(velocity_north))
+        } else {
+            (0.0, 0.0, 0.0, 0.0, 0.0, 0.0)
+        };
+
+        let latitude = config.home_latitude + (north / EARTH_RADIUS).to_degrees();
+        let longitude = config.home_longitude + (east / (EARTH_RADIUS * config.home_latitude.to_radians().cos())).to_degrees();
+
+        return FlightState {
+            phase,
+            // Armed on the ground before the climb, disarmed once landed
+            armed: t < descent_end,
+            time_boot_ms: since_boot.as_millis() as u32,
+            latitude,
+            longitude,
+            altitude: config.home_altitude + relative_altitude,
+            relative_altitude,
+            velocity_north,
+            velocity_east,
+            velocity_down,
+            roll,
+            pitch,
+            yaw
+        };
+    }
+}
+
+#[cfg(test)]
+mod simulation_tests {
+    use std::time::Duration;
+    use crate::application::simulation::{FlightPhase, SimulatedFlight};
+
+    #[test]
+    fn flight_script_is_continuous() {
+        let flight = SimulatedFlight::new();
+
+        let mut previous = flight.get_state_at(Duration::ZERO);
+        for step in 1..6000 {
+            let state = flight.get_state_at(Duration::from_millis(step * 100));
+
+            // No teleporting between samples 100 ms apart
+            assert!((state.latitude - previous.latitude).abs() < 0.0001);
+            assert!((state.longitude - previous.longitude).abs() < 0.0001);
+            assert!((state.relative_altitude - previous.relative_altitude).abs() < 1.0);
+            assert!(state.relative_altitude >= 0.0);
+
+            previous = state;
+        }
+
+        assert!(flight.get_state_at(Duration::from_secs(100)).phase == FlightPhase::Cruise);
+        assert!(flight.get_state_at(Duration::from_secs(5)).phase == FlightPhase::Landed);
+        assert!(flight.get_state_at(Duration::from_secs(5)).armed);
+    }
+}
@@ -0,0 +1,141 @@
+use std::sync::Arc;
+use bytes::Bytes;
+use log::warn;
+use crate::application::data_manage::{ContentType, DataSource, IncomingData};
+use crate::application::data_manage::storage_queue::StorageSender;
+use crate::application::geotag::GeoTagger;
+use crate::application::simulation::{SimulatedFlight, synthetic_png, synthetic_tiff};
+use crate::application::tasks::capture_ircam_images::THERMAL_IMAGE;
+use crate::application::tasks::capture_picam_images::PICAM_IMAGE;
+use crate::application::timer::TimedTask;
+
+pub const IMAGE_FORMATS: &[ContentType] = &[ContentType::Png, ContentType::Tiff];
+
+const THERMAL_WIDTH: u32 = 160;
+const THERMAL_HEIGHT: u32 = 120;
+const PICAM_WIDTH: u32 = 320;
+const PICAM_HEIGHT: u32 = 240;
+
+// Ground distance in meters covered by one pixel of the synthetic frames
+const GROUND_SAMPLE_DISTANCE: f64 = 0.5;
+
+// Simulated fire hotspot position, in meters north and east of home
+const HOTSPOT_NORTH: f64 = 120.0;
+const HOTSPOT_EAST: f64 = 250.0;
+
+#[derive(Clone, Copy)]
+pub enum SimCameraKind {
+    Thermal,
+    PiCam
+}
+
+// Generates frames of the ground below the simulated flight, with a hotspot visible in thermal frames.
+// Frames are PNG or TIFF, thermal TIFFs are 16 bit radiometric frames in centikelvin
+pub struct SimCamera {
+    storage_sender: StorageSender,
+    flight: Arc<SimulatedFlight>,
+    kind: SimCameraKind,
+    image_format: ContentType,
+    geotagger: Option<GeoTagger>
+}
+
+impl SimCamera {
+    pub fn new(storage_sender: StorageSender, flight: Arc<SimulatedFlight>, kind: SimCameraKind) -> Self {
+        Self {
+            storage_sender,
+            flight,
+            kind,
+            image_format: ContentType::Png,
+            geotagger: None
+        }
+    }
+
+    pub fn with_image_format(mut self, image_format: ContentType) -> Self {
+        self.image_format = image_format;
+        return self;
+    }
+
+    pub fn with_geotagger(mut self, geotagger: GeoTagger) -> Self {
+        self.geotagger = Option::from(geotagger);
+        return self;
+    }
+
+    fn get_source(&self) -> DataSource {
+        match self.kind {
+            SimCameraKind::Thermal => {THERMAL_IMAGE.source}
+            SimCameraKind::PiCam => {PICAM_IMAGE.source}
+        }
+    }
+
+    // Intensities double as temperatures in degrees Celsius for radiometric frames
+    fn render_thermal(north: f64, east: f64, image_format: ContentType) -> Vec<u8> {
+        let mut temperatures = Vec::with_capacity((THERMAL_WIDTH * THERMAL_HEIGHT) as usize);
+
+        for y in 0..THERMAL_HEIGHT {
+            for x in 0..THERMAL_WIDTH {
+                let (pixel_north, pixel_east) = SimCamera::get_ground_position(north, east, x, y, THERMAL_WIDTH, THERMAL_HEIGHT);
+                let distance = ((pixel_north - HOTSPOT_NORTH).powi(2) + (pixel_east - HOTSPOT_EAST).powi(2)).sqrt();
+
+                let background = 60.0 + 10.0 * ((pixel_north * 0.05).sin() + (pixel_east * 0.05).cos());
+                let hotspot = 195.0 * (-distance * distance / 200.0).exp();
+                temperatures.push(background + hotspot);
+            }
+        }
+
+        if image_format == ContentType::Tiff {
+            let pixels: Vec<u16> = temperatures.iter().map(|temperature| ((temperature + 273.15) * 100.0).round() as u16).collect();
+            return synthetic_tiff::encode_grayscale16(THERMAL_WIDTH, THERMAL_HEIGHT, &pixels);
+        }
+
+        let pixels: Vec<u8> = temperatures.iter().map(|temperature| temperature.min(255.0) as u8).collect();
+        return synthetic_png::encode_grayscale(THERMAL_WIDTH, THERMAL_HEIGHT, &pixels);
+    }
+
+    fn render_picam(north: f64, east: f64, image_format: ContentType) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity((PICAM_WIDTH * PICAM_HEIGHT * 3) as usize);
+
+        for y in 0..PICAM_HEIGHT {
+            for x in 0..PICAM_WIDTH {
+                let (pixel_north, pixel_east) = SimCamera::get_ground_position(north, east, x, y, PICAM_WIDTH, PICAM_HEIGHT);
+
+                // Fields in a 40 m grid, alternating crop colors
+                let field = ((pixel_north / 40.0).floor() + (pixel_east / 40.0).floor()) as i64;
+                let (red, green, blue) = if field.rem_euclid(2) == 0 {(70, 120, 40)} else {(150, 130, 70)};
+                pixels.extend_from_slice(&[red, green, blue]);
+            }
+        }
+
+        if image_format == ContentType::Tiff {
+            return synthetic_tiff::encode_rgb(PICAM_WIDTH, PICAM_HEIGHT, &pixels);
+        }
+        return synthetic_png::encode_rgb(PICAM_WIDTH, PICAM_HEIGHT, &pixels);
+    }
+
+    fn get_ground_position(north: f64, east: f64, x: u32, y: u32, width: u32, height: u32) -> (f64, f64) {
+        let pixel_north = north + (height as f64 / 2.0 - y as f64) * GROUND_SAMPLE_DISTANCE;
+        let pixel_east = east + (x as f64 - width as f64 / 2.0) * GROUND_SAMPLE_DISTANCE;
+        return (pixel_north, pixel_east);
+    }
+}
+
+impl TimedTask for SimCamera {
+    fn execute(&mut self) -> () {
+        let (north, east) = self.flight.get_offset_from_home(&self.flight.get_state());
+        let geotag = self.geotagger.as_ref().map(|geotagger| geotagger.get_geotag());
+
+        let image = match self.kind {
+            SimCameraKind::Thermal => SimCamera::render_thermal(north, east, self.image_format),
+            SimCameraKind::PiCam => SimCamera::render_picam(north, east, self.image_format)
+        };
+
+        let source = self.get_source();
+        let mut image_data = IncomingData::new(source, None, Option::from(Bytes::from(image))).with_content_type(self.image_format);
+        if let Some(geotag) = geotag {
+            image_data = image_data.with_geotag(geotag);
+        }
+
+        if let Err(err) = self.storage_sender.send(image_data) {
+            warn!("Failed to queue {} data for storage: {}", source, err);
+        }
+    }
+}
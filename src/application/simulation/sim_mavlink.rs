@@ -0,0 +1,98 @@
+use std::sync::Arc;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+use mavlink::common::{ATTITUDE_DATA, GLOBAL_POSITION_INT_DATA, HEARTBEAT_DATA, MavAutopilot, MavMessage, MavModeFlag, MavState, MavType, SYSTEM_TIME_DATA};
+use log::info;
+use crate::application::data_manage::storage_queue::StorageSender;
+use crate::application::simulation::SimulatedFlight;
+use crate::application::tasks::mavlink_adapter::MavlinkAdapter;
+use crate::application::timer::TimedTask;
+
+const MESSAGE_INTERVAL: Duration = Duration::from_millis(100);
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+// Stands in for the PixHawk, feeding scripted flight messages through the real MAVLink message handling
+pub struct SimMavlinkAdapter {
+    storage_sender: StorageSender,
+    command_recv: Receiver<MavMessage>,
+    flight: Arc<SimulatedFlight>,
+    last_message: Option<Instant>,
+    last_heartbeat: Option<Instant>
+}
+
+impl SimMavlinkAdapter {
+    pub fn new(storage_sender: StorageSender, command_recv: Receiver<MavMessage>, flight: Arc<SimulatedFlight>) -> Self {
+        Self {
+            storage_sender,
+            command_recv,
+            flight,
+            last_message: None,
+            last_heartbeat: None
+        }
+    }
+}
+
+impl TimedTask for SimMavlinkAdapter {
+    fn execute(&mut self) -> () {
+        while let Ok(command) = self.command_recv.try_recv() {
+            info!("Simulated PixHawk received command: {:?}", command);
+        }
+
+        if self.last_message.is_some() && self.last_message.unwrap().elapsed().lt(&MESSAGE_INTERVAL) {
+            return;
+        }
+        self.last_message = Option::from(Instant::now());
+
+        let state = self.flight.get_state();
+
+        if self.last_heartbeat.is_none() || self.last_heartbeat.unwrap().elapsed().ge(&HEARTBEAT_INTERVAL) {
+            self.last_heartbeat = Option::from(Instant::now());
+
+            let base_mode = if state.armed {
+                MavModeFlag::MAV_MODE_FLAG_CUSTOM_MODE_ENABLED | MavModeFlag::MAV_MODE_FLAG_SAFETY_ARMED
+            } else {
+                MavModeFlag::MAV_MODE_FLAG_CUSTOM_MODE_ENABLED
+            };
+
+            let heartbeat = MavMessage::HEARTBEAT(HEARTBEAT_DATA {
+                custom_mode: 0,
+                mavtype: MavType::MAV_TYPE_FIXED_WING,
+                autopilot: MavAutopilot::MAV_AUTOPILOT_ARDUPILOTMEGA,
+                base_mode,
+                system_status: if state.armed {MavState::MAV_STATE_ACTIVE} else {MavState::MAV_STATE_STANDBY},
+                mavlink_version: 0x3
+            });
+            MavlinkAdapter::handle_message(heartbeat, &self.storage_sender);
+
+            let system_time = MavMessage::SYSTEM_TIME(SYSTEM_TIME_DATA {
+                time_unix_usec: self.flight.get_gps_time_us(),
+                time_boot_ms: state.time_boot_ms
+            });
+            MavlinkAdapter::handle_message(system_time, &self.storage_sender);
+        }
+
+        let global_position = MavMessage::GLOBAL_POSITION_INT(GLOBAL_POSITION_INT_DATA {
+            time_boot_ms: state.time_boot_ms,
+            lat: (state.latitude * 1e7) as i32,
+            lon: (state.longitude * 1e7) as i32,
+            alt: (state.altitude * 1000.0) as i32,
+            relative_alt: (state.relative_altitude * 1000.0) as i32,
+            vx: (state.velocity_north * 100.0) as i16,
+            vy: (state.velocity_east * 100.0) as i16,
+            vz: (state.velocity_down * 100.0) as i16,
+            hdg: (state.yaw.to_degrees().rem_euclid(360.0) * 100.0) as u16
+        });
+        MavlinkAdapter::handle_message(global_position, &self.storage_sender);
+
+        let attitude = MavMessage::ATTITUDE(ATTITUDE_DATA {
+            time_boot_ms: state.time_boot_ms,
+            roll: state.roll as f32,
+            pitch: state.pitch as f32,
+            yaw: state.yaw as f32,
+            rollspeed: 0.0,
+            pitchspeed: 0.0,
+            yawspeed: 0.0
+        });
+        MavlinkAdapter::handle_message(attitude, &self.storage_sender);
+    }
+}
@@ -0,0 +1,44 @@
+use std::time::Instant;
+use log::warn;
+use crate::application::data_manage::{IncomingData, Telemetry};
+use crate::application::data_manage::storage_queue::StorageSender;
+use crate::application::DataCollector;
+use crate::application::tasks::obc_telem::{OBC_TELEMETRY, ObcTelemetry};
+use crate::application::timer::TimedTask;
+
+// Stands in for vcgencmd and df on the Raspberry Pi, reporting in the same format as the real OBC telemetry
+pub struct SimObcTelem {
+    storage_sender: StorageSender,
+    start_time: Instant
+}
+
+impl DataCollector for SimObcTelem {
+    fn new(storage_sender: StorageSender) -> Self {
+        Self {
+            storage_sender,
+            start_time: Instant::now()
+        }
+    }
+}
+
+impl TimedTask for SimObcTelem {
+    fn execute(&mut self) -> () {
+        let uptime = self.start_time.elapsed().as_secs_f32();
+
+        // Core warms up from 45 C and settles at 65 C, storage fills by roughly 1 MB per second
+        let core_temperature = 65.0 - 20.0 * (-uptime / 120.0).exp();
+        let storage_used = 8.2 + uptime / 1024.0;
+
+        // Rounded to the single decimal reported by vcgencmd
+        let telemetry = ObcTelemetry {
+            core_temperature: Option::from((core_temperature * 10.0).round() / 10.0),
+            storage_space: Option::from(format!("{:.1}G/29G", storage_used))
+        };
+
+        let obc_telem = IncomingData::new(OBC_TELEMETRY.source, Option::from(Telemetry::ObcTelemetry(telemetry)), None);
+
+        if let Err(err) = self.storage_sender.send(obc_telem) {
+            warn!("Failed to queue {} data for storage: {}", OBC_TELEMETRY.source, err);
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::sync::Arc;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+use byteorder::{BigEndian, ByteOrder};
+use ll_protocol::frame::Frame;
+use log::debug;
+use crate::application::data_manage::storage_queue::StorageSender;
+use crate::application::simulation::{FlightPhase, SimulatedFlight};
+use crate::application::tasks::pib_adapter::{ACTUATOR_CONTROL_SERVICE, ENVIRONMENTAL_SENSOR_SERVICE, PibAdapter,
+                                              POWER_TELEMETRY_SERVICE, TEMPERATURE_TELEMETRY_SERVICE};
+use crate::application::timer::TimedTask;
+
+const BATTERY_FULL_VOLTAGE: f32 = 25.2;
+const BATTERY_SAG_PER_AMP: f32 = 0.02;
+// Voltage lost per ampere-second drawn, drains a full battery to around 21 V over a 10 minute hover
+const BATTERY_DRAIN_PER_AMP_SECOND: f32 = 0.00013;
+
+const AMBIENT_TEMPERATURE: f32 = 18.0;
+
+const TELEMETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+// Stands in for the PIB, feeding generated telemetry frames through the real PIB frame handling
+pub struct SimPibAdapter {
+    storage_sender: StorageSender,
+    frame_receiver: Receiver<Frame>,
+    flight: Arc<SimulatedFlight>,
+    drained_voltage: f32,
+    esc_temperature: f32,
+    servo_value: Option<i8>,
+    last_telemetry: Option<Instant>
+}
+
+impl SimPibAdapter {
+    pub fn new(storage_sender: StorageSender, frame_receiver: Receiver<Frame>, flight: Arc<SimulatedFlight>) -> Self {
+        Self {
+            storage_sender,
+            frame_receiver,
+            flight,
+            drained_voltage: 0.0,
+            esc_temperature: AMBIENT_TEMPERATURE,
+            servo_value: None,
+            last_telemetry: None
+        }
+    }
+
+    fn get_current_draw(phase: FlightPhase) -> f32 {
+        match phase {
+            FlightPhase::Landed => {1.5}
+            FlightPhase::Climb => {42.0}
+            FlightPhase::Transition | FlightPhase::BackTransition => {48.0}
+            FlightPhase::Cruise => {14.0}
+            FlightPhase::Descent => {30.0}
+        }
+    }
+
+    fn handle_frame(&mut self, frame: Frame) {
+        PibAdapter::handle_in_frame(frame, self.storage_sender.clone());
+    }
+
+    fn encode_floats(service: u8, values: &[f32]) -> Frame {
+        let mut payload = vec![0; values.len() * 4];
+        for (index, value) in values.iter().enumerate() {
+            BigEndian::write_f32(&mut payload[index * 4..(index + 1) * 4], *value);
+        }
+        return Frame::new(service, payload);
+    }
+}
+
+impl TimedTask for SimPibAdapter {
+    fn execute(&mut self) -> () {
+        while let Ok(mut frame) = self.frame_receiver.try_recv() {
+            if frame.get_service() == ACTUATOR_CONTROL_SERVICE && frame.get_payload_length() == 1 {
+                let servo_value = frame.get_payload()[0] as i8;
+                if self.servo_value != Some(servo_value) {
+                    debug!("Simulated PIB servo set to {}", servo_value);
+                    self.servo_value = Option::from(servo_value);
+                }
+            }
+        }
+
+        if self.last_telemetry.is_some() && self.last_telemetry.unwrap().elapsed().lt(&TELEMETRY_INTERVAL) {
+            return;
+        }
+        let elapsed = self.last_telemetry.map_or(0.0, |last_telemetry| last_telemetry.elapsed().as_secs_f32());
+        self.last_telemetry = Option::from(Instant::now());
+
+        let state = self.flight.get_state();
+
+        let current = SimPibAdapter::get_current_draw(state.phase);
+        self.drained_voltage += current * elapsed * BATTERY_DRAIN_PER_AMP_SECOND;
+        let voltage = BATTERY_FULL_VOLTAGE - self.drained_voltage - current * BATTERY_SAG_PER_AMP;
+
+        // ESCs heat up with current draw and cool towards ambient
+        self.esc_temperature += (AMBIENT_TEMPERATURE + current * 0.9 - self.esc_temperature) * 0.05;
+
+        let power = SimPibAdapter::encode_floats(POWER_TELEMETRY_SERVICE, &[voltage, current, voltage * current]);
+        self.handle_frame(power);
+
+        let temperature = SimPibAdapter::encode_floats(TEMPERATURE_TELEMETRY_SERVICE, &[
+            self.esc_temperature * 0.8,
+            self.esc_temperature,
+            self.esc_temperature + 0.4,
+            self.esc_temperature - 0.3,
+            self.esc_temperature + 0.1
+        ]);
+        self.handle_frame(temperature);
+
+        // Air cools with altitude at the standard lapse rate
+        let air_temperature = AMBIENT_TEMPERATURE - 0.0065 * state.relative_altitude as f32;
+        let environmental = SimPibAdapter::encode_floats(ENVIRONMENTAL_SENSOR_SERVICE, &[air_temperature, 45.0]);
+        self.handle_frame(environmental);
+    }
+}
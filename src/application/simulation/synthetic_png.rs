@@ -0,0 +1,104 @@
+// Minimal PNG encoder for synthetic camera frames, pixel data is stored uncompressed inside the zlib stream
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+const COLOR_TYPE_GRAYSCALE: u8 = 0;
+const COLOR_TYPE_RGB: u8 = 2;
+const STORED_BLOCK_MAX: usize = 65_535;
+
+pub fn encode_grayscale(width: u32, height: u32, pixels: &[u8]) -> Vec<u8> {
+    return encode(width, height, COLOR_TYPE_GRAYSCALE, 1, pixels);
+}
+
+pub fn encode_rgb(width: u32, height: u32, pixels: &[u8]) -> Vec<u8> {
+    return encode(width, height, COLOR_TYPE_RGB, 3, pixels);
+}
+
+fn encode(width: u32, height: u32, color_type: u8, channels: usize, pixels: &[u8]) -> Vec<u8> {
+    let row_length = width as usize * channels;
+    assert_eq!(pixels.len(), row_length * height as usize, "Pixel buffer does not match image dimensions");
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[8, color_type, 0, 0, 0]);
+
+    // Every scanline is prefixed with filter type 0 (none)
+    let mut scanlines = Vec::with_capacity(pixels.len() + height as usize);
+    for row in pixels.chunks(row_length) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(row);
+    }
+
+    let mut png = PNG_SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&scanlines));
+    write_chunk(&mut png, b"IEND", &[]);
+
+    return png;
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+
+    let blocks: Vec<&[u8]> = if data.is_empty() {vec![&[]]} else {data.chunks(STORED_BLOCK_MAX).collect()};
+    for (index, block) in blocks.iter().enumerate() {
+        let is_final = index + 1 == blocks.len();
+        let length = block.len() as u16;
+
+        stream.push(if is_final {1} else {0});
+        stream.extend_from_slice(&length.to_le_bytes());
+        stream.extend_from_slice(&(!length).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+
+    return stream;
+}
+
+fn write_chunk(png: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+
+    let crc_start = png.len();
+    png.extend_from_slice(chunk_type);
+    png.extend_from_slice(data);
+
+    let crc = crc32(&png[crc_start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {(crc >> 1) ^ 0xEDB8_8320} else {crc >> 1};
+        }
+    }
+    return !crc;
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let mut a = 1u32;
+    let mut b = 0u32;
+    for &byte in data {
+        a = (a + byte as u32) % 65_521;
+        b = (b + a) % 65_521;
+    }
+    return (b << 16) | a;
+}
+
+#[cfg(test)]
+mod synthetic_png_tests {
+    use crate::application::simulation::synthetic_png::{crc32, encode_grayscale};
+
+    #[test]
+    fn encodes_valid_chunks() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+
+        let png = encode_grayscale(2, 2, &[0, 64, 128, 255]);
+        assert_eq!(&png[..8], &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[png.len() - 12..], &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]);
+    }
+}
@@ -0,0 +1,92 @@
+// Minimal little endian TIFF encoder for synthetic camera frames, pixel data is stored uncompressed in a single strip
+
+const TIFF_HEADER: [u8; 4] = [b'I', b'I', 42, 0];
+const IFD_OFFSET: u32 = 8;
+const ENTRY_COUNT: u16 = 9;
+
+const TYPE_SHORT: u16 = 3;
+const TYPE_LONG: u16 = 4;
+
+const PHOTOMETRIC_BLACK_IS_ZERO: u16 = 1;
+const PHOTOMETRIC_RGB: u16 = 2;
+
+// 16 bit samples such as radiometric thermal frames
+pub fn encode_grayscale16(width: u32, height: u32, pixels: &[u16]) -> Vec<u8> {
+    let data: Vec<u8> = pixels.iter().flat_map(|pixel| pixel.to_le_bytes()).collect();
+    return encode(width, height, 16, 1, PHOTOMETRIC_BLACK_IS_ZERO, &data);
+}
+
+pub fn encode_rgb(width: u32, height: u32, pixels: &[u8]) -> Vec<u8> {
+    return encode(width, height, 8, 3, PHOTOMETRIC_RGB, pixels);
+}
+
+fn encode(width: u32, height: u32, bits_per_sample: u16, samples_per_pixel: u16, photometric: u16, data: &[u8]) -> Vec<u8> {
+    let bytes_per_pixel = (bits_per_sample / 8 * samples_per_pixel) as usize;
+    assert_eq!(data.len(), width as usize * height as usize * bytes_per_pixel, "Pixel buffer does not match image dimensions");
+
+    // Bits per sample of each channel follow the IFD when they do not fit into the entry, then the pixel data
+    let ifd_end = IFD_OFFSET + 2 + ENTRY_COUNT as u32 * 12 + 4;
+    let bits_per_sample_offset = ifd_end;
+    let strip_offset = if samples_per_pixel > 2 {bits_per_sample_offset + samples_per_pixel as u32 * 2} else {ifd_end};
+
+    let mut tiff = TIFF_HEADER.to_vec();
+    tiff.extend_from_slice(&IFD_OFFSET.to_le_bytes());
+
+    tiff.extend_from_slice(&ENTRY_COUNT.to_le_bytes());
+    write_entry(&mut tiff, 256, TYPE_LONG, 1, width);
+    write_entry(&mut tiff, 257, TYPE_LONG, 1, height);
+    if samples_per_pixel > 2 {
+        write_entry(&mut tiff, 258, TYPE_SHORT, samples_per_pixel as u32, bits_per_sample_offset);
+    } else {
+        write_entry(&mut tiff, 258, TYPE_SHORT, samples_per_pixel as u32, bits_per_sample as u32);
+    }
+    write_entry(&mut tiff, 259, TYPE_SHORT, 1, 1);
+    write_entry(&mut tiff, 262, TYPE_SHORT, 1, photometric as u32);
+    write_entry(&mut tiff, 273, TYPE_LONG, 1, strip_offset);
+    write_entry(&mut tiff, 277, TYPE_SHORT, 1, samples_per_pixel as u32);
+    write_entry(&mut tiff, 278, TYPE_LONG, 1, height);
+    write_entry(&mut tiff, 279, TYPE_LONG, 1, data.len() as u32);
+    tiff.extend_from_slice(&0u32.to_le_bytes());
+
+    if samples_per_pixel > 2 {
+        for _ in 0..samples_per_pixel {
+            tiff.extend_from_slice(&bits_per_sample.to_le_bytes());
+        }
+    }
+    tiff.extend_from_slice(data);
+
+    return tiff;
+}
+
+// Values of a single SHORT or LONG are stored within the entry, left aligned
+fn write_entry(tiff: &mut Vec<u8>, tag: u16, field_type: u16, count: u32, value: u32) {
+    tiff.extend_from_slice(&tag.to_le_bytes());
+    tiff.extend_from_slice(&field_type.to_le_bytes());
+    tiff.extend_from_slice(&count.to_le_bytes());
+    if field_type == TYPE_SHORT && count == 1 {
+        tiff.extend_from_slice(&(value as u16).to_le_bytes());
+        tiff.extend_from_slice(&[0, 0]);
+    } else {
+        tiff.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod synthetic_tiff_tests {
+    use exif::{In, Reader, Tag};
+    use crate::application::simulation::synthetic_tiff::{encode_grayscale16, encode_rgb};
+
+    #[test]
+    fn encodes_readable_ifd() {
+        let tiff = encode_grayscale16(3, 2, &[0, 1000, 2000, 30000, 40000, 65535]);
+        let exif = Reader::new().read_raw(tiff.clone()).unwrap();
+        assert_eq!(exif.get_field(Tag::ImageWidth, In::PRIMARY).unwrap().value.get_uint(0), Some(3));
+        assert_eq!(exif.get_field(Tag::BitsPerSample, In::PRIMARY).unwrap().value.get_uint(0), Some(16));
+        assert_eq!(&tiff[tiff.len() - 2..], &65535u16.to_le_bytes());
+
+        let tiff = encode_rgb(1, 1, &[10, 20, 30]);
+        let exif = Reader::new().read_raw(tiff.clone()).unwrap();
+        assert_eq!(exif.get_field(Tag::BitsPerSample, In::PRIMARY).unwrap().value.get_uint(2), Some(8));
+        assert_eq!(&tiff[tiff.len() - 3..], &[10, 20, 30]);
+    }
+}
@@ -0,0 +1,113 @@
+use std::env;
+use std::io;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::{Duration, Instant};
+use log::warn;
+
+// Implements the sd_notify protocol for readiness and watchdog notifications to systemd
+pub struct SystemdNotifier {
+    socket: UnixDatagram,
+    address: SocketAddr,
+    watchdog_interval: Option<Duration>,
+    last_watchdog: Instant
+}
+
+impl SystemdNotifier {
+    pub fn new(notify_socket: &str, watchdog_timeout: Option<Duration>) -> io::Result<Self> {
+        // Addresses starting with @ are in the abstract namespace
+        let address = match notify_socket.strip_prefix('@') {
+            Some(abstract_name) => SocketAddr::from_abstract_name(abstract_name.as_bytes())?,
+            None => SocketAddr::from_pathname(notify_socket)?
+        };
+
+        return Ok(Self {
+            socket: UnixDatagram::unbound()?,
+            address,
+            // Ping at half the timeout so a single late ping does not trigger the watchdog
+            watchdog_interval: watchdog_timeout.map(|timeout| timeout / 2),
+            last_watchdog: Instant::now()
+        });
+    }
+
+    // Returns None when not started by systemd with NOTIFY_SOCKET set
+    pub fn from_env() -> Option<Self> {
+        let notify_socket = env::var("NOTIFY_SOCKET").ok()?;
+
+        let watchdog_for_us = env::var("WATCHDOG_PID").map_or(true, |pid| pid == std::process::id().to_string());
+        let watchdog_timeout = env::var("WATCHDOG_USEC").ok()
+            .and_then(|usec| usec.parse::<u64>().ok())
+            .filter(|_| watchdog_for_us)
+            .map(Duration::from_micros);
+
+        let notifier = SystemdNotifier::new(&notify_socket, watchdog_timeout);
+        if notifier.is_err() {
+            warn!("Failed to open systemd notify socket {}: {:?}", notify_socket, notifier.err());
+            return None;
+        }
+
+        return notifier.ok();
+    }
+
+    pub fn notify(&self, state: &str) -> io::Result<()> {
+        self.socket.send_to_addr(state.as_bytes(), &self.address)?;
+        return Ok(());
+    }
+
+    pub fn ready(&self) {
+        if let Err(err) = self.notify("READY=1") {
+            warn!("Failed to notify systemd of readiness: {:?}", err);
+        }
+    }
+
+    pub fn stopping(&self) {
+        if let Err(err) = self.notify("STOPPING=1") {
+            warn!("Failed to notify systemd of stopping: {:?}", err);
+        }
+    }
+
+    pub fn status(&self, status: &str) {
+        if let Err(err) = self.notify(&format!("STATUS={}", status)) {
+            warn!("Failed to send status to systemd: {:?}", err);
+        }
+    }
+
+    pub fn is_watchdog_due(&self) -> bool {
+        return self.watchdog_interval.map_or(false, |interval| self.last_watchdog.elapsed().ge(&interval));
+    }
+
+    pub fn watchdog(&mut self) {
+        if let Err(err) = self.notify("WATCHDOG=1") {
+            warn!("Failed to send watchdog ping to systemd: {:?}", err);
+        }
+        self.last_watchdog = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod systemd_notify_tests {
+    use std::os::unix::net::UnixDatagram;
+    use std::time::Duration;
+    use crate::application::systemd_notify::SystemdNotifier;
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn notifies_fake_socket() {
+        let socket_dir = TempDir::new("notify");
+        let socket_path = socket_dir.join("notify.sock");
+        let fake_systemd = UnixDatagram::bind(&socket_path).unwrap();
+        fake_systemd.set_read_timeout(Option::from(Duration::from_secs(1))).unwrap();
+
+        let mut notifier = SystemdNotifier::new(socket_path.to_str().unwrap(), Option::from(Duration::ZERO)).unwrap();
+        let mut buf = [0u8; 64];
+
+        notifier.ready();
+        let len = fake_systemd.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+
+        assert!(notifier.is_watchdog_due());
+        notifier.watchdog();
+        let len = fake_systemd.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"WATCHDOG=1");
+    }
+}
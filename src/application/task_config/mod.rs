@@ -0,0 +1,314 @@
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::process;
+use std::sync::Arc;
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+use envconfig::Envconfig;
+use log::error;
+use ll_protocol::frame::Frame;
+use mavlink::common::MavMessage;
+use serde::{Deserialize, Serialize};
+use toml::{Table, Value};
+use crate::application::battery_monitor;
+use crate::application::data_manage::{disk_guard, image_writer, ContentType, DataSourceSpec, storage_queue};
+use crate::application::data_manage::data_bus::DataBus;
+use crate::application::data_manage::storage_queue::StorageSender;
+use crate::application::geotag::GeoTagger;
+use crate::application::mission;
+use crate::application::mission::MissionManager;
+use crate::application::payload_orientator;
+use crate::application::simulation::SimulatedFlight;
+use crate::application::tasks::pib_adapter::PibCommander;
+use crate::application::timer::{TimedTask, Timer};
+use crate::application::tasks::{capture_go_pro_images, capture_ircam_images, capture_picam_images, example_task, mavlink_adapter, obc_telem, pib_adapter};
+
+#[derive(Envconfig, Debug)]
+pub struct TaskConfigLocation {
+    #[envconfig(from = "FLIGHTCODE_TASK_CONFIG", default = "./tasks.toml")]
+    pub path: String
+}
+
+#[derive(Clone, Copy)]
+pub enum ParamKind {
+    Integer,
+    String
+}
+
+impl ParamKind {
+    fn name(&self) -> &'static str {
+        match self {
+            ParamKind::Integer => {"an integer"}
+            ParamKind::String => {"a string"}
+        }
+    }
+
+    fn matches(&self, value: &Value) -> bool {
+        match self {
+            ParamKind::Integer => {value.is_integer()}
+            ParamKind::String => {value.is_str()}
+        }
+    }
+}
+
+// Declared by each task next to its implementation, along with its defaults and the data sources it produces
+pub struct TaskSpec {
+    pub name: &'static str,
+    pub enabled: bool,
+    pub interval_ms: u64,
+    pub params: &'static [(&'static str, ParamKind)],
+    pub sources: &'static [DataSourceSpec],
+    // Creates the task and the timer driving it once the task is enabled, None if it has nothing to run in this mode
+    pub build: fn(&TaskContext, &TaskConfig) -> Option<BuiltTask>
+}
+
+pub type BuiltTask = (Timer, Box<dyn TimedTask + Send>);
+
+// Everything the tasks are built from
+pub struct TaskContext {
+    // Only present in simulated mode, where the hardware facing tasks are swapped for stand-ins driven by the flight
+    pub flight: Option<Arc<SimulatedFlight>>,
+    pub storage_sender: StorageSender,
+    pub data_bus: Arc<DataBus>,
+    pub mission_manager: Arc<MissionManager>,
+    pub geotagger: GeoTagger,
+    pub pib_commander: Arc<PibCommander>,
+    // Taken by whichever adapter drains them, so they can only be handed out once
+    pub pib_frames: Cell<Option<Receiver<Frame>>>,
+    pub mavlink_commands: Cell<Option<Receiver<MavMessage>>>
+}
+
+impl TaskContext {
+    pub fn take_pib_frames(&self) -> Receiver<Frame> {
+        return self.pib_frames.take().expect("PIB frames already taken by another adapter");
+    }
+
+    pub fn take_mavlink_commands(&self) -> Receiver<MavMessage> {
+        return self.mavlink_commands.take().expect("MAVLink commands already taken by another adapter");
+    }
+}
+
+// Every task that can be listed in the task configuration file, data sources are discovered from here
+pub const TASK_SPECS: &[&TaskSpec] = &[
+    &example_task::TASK_SPEC,
+    &capture_go_pro_images::TASK_SPEC,
+    &capture_ircam_images::TASK_SPEC,
+    &capture_picam_images::TASK_SPEC,
+    &pib_adapter::TASK_SPEC,
+    &mavlink_adapter::TASK_SPEC,
+    &obc_telem::TASK_SPEC,
+    // Data triggered services, the interval is the minimum time between executions
+    &battery_monitor::TASK_SPEC,
+    &payload_orientator::TASK_SPEC,
+    &mission::TASK_SPEC
+];
+
+// Published by the data manager itself, configured through the environment rather than the task configuration file
+pub const DATA_MANAGER_SOURCES: &[&DataSourceSpec] = &[&disk_guard::STORAGE, &storage_queue::STORAGE_QUEUE, &image_writer::IMAGE_WRITER];
+
+pub fn get_data_sources() -> Vec<&'static DataSourceSpec> {
+    return TASK_SPECS.iter().flat_map(|task| task.sources.iter()).chain(DATA_MANAGER_SOURCES.iter().copied()).collect();
+}
+
+pub fn find_data_source(name: &str) -> Option<&'static DataSourceSpec> {
+    return get_data_sources().into_iter().find(|spec| spec.source.name() == name);
+}
+
+pub enum ConfigError {
+    Read(String, std::io::Error),
+    Parse(String, toml::de::Error),
+    UnknownTask(String),
+    UnknownParameter(String, String),
+    InvalidParameter(String, String, ParamKind)
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, err) => {
+                write!(f, "Failed to read task configuration {}: {}", path, err)
+            }
+            ConfigError::Parse(path, err) => {
+                write!(f, "Invalid task configuration {}: {}", path, err)
+            }
+            ConfigError::UnknownTask(task) => {
+                let known: Vec<&str> = TASK_SPECS.iter().map(|spec| spec.name).collect();
+                write!(f, "Unknown task [tasks.{}], expected one of: {}", task, known.join(", "))
+            }
+            ConfigError::UnknownParameter(task, param) => {
+                write!(f, "Unknown parameter {} for task [tasks.{}]", param, task)
+            }
+            ConfigError::InvalidParameter(task, param, kind) => {
+                write!(f, "Parameter {} for task [tasks.{}] must be {}", param, task, kind.name())
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TaskConfigFile {
+    #[serde(default)]
+    tasks: BTreeMap<String, TaskConfigEntry>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TaskConfigEntry {
+    enabled: Option<bool>,
+    interval_ms: Option<u64>,
+    #[serde(default)]
+    params: Table
+}
+
+#[derive(Serialize, Clone)]
+pub struct TaskConfig {
+    pub enabled: bool,
+    pub interval_ms: u64,
+    #[serde(skip_serializing_if = "Table::is_empty")]
+    pub params: Table
+}
+
+impl TaskConfig {
+    pub fn interval(&self) -> Duration {
+        return Duration::from_millis(self.interval_ms);
+    }
+
+    pub fn get_integer_param(&self, param: &str) -> Option<i64> {
+        return self.params.get(param).and_then(|value| value.as_integer());
+    }
+
+    pub fn get_string_param(&self, param: &str) -> Option<String> {
+        return self.params.get(param).and_then(|value| value.as_str()).map(|value| value.to_string());
+    }
+}
+
+// Effective configuration of every known task, with defaults filled in for anything not in the file
+#[derive(Serialize)]
+pub struct TasksConfig {
+    pub tasks: BTreeMap<String, TaskConfig>
+}
+
+impl TasksConfig {
+    pub fn get(&self, name: &str) -> &TaskConfig {
+        return self.tasks.get(name).expect(&*format!("Task {} missing from task specs", name));
+    }
+
+    pub fn is_enabled(&self, name: &str) -> bool {
+        return self.get(name).enabled;
+    }
+
+    pub fn interval(&self, name: &str) -> Duration {
+        return self.get(name).interval();
+    }
+
+    pub fn to_toml(&self) -> String {
+        return toml::to_string(self).expect("Failed to serialize task configuration");
+    }
+}
+
+// Configured image_format of a camera task, which has to be one of the formats the camera can produce
+pub fn get_image_format_or_exit(config: &TaskConfig, task: &str, supported: &[ContentType]) -> ContentType {
+    let image_format = match config.get_string_param("image_format") {
+        Some(image_format) => image_format,
+        None => {
+            return ContentType::Png;
+        }
+    };
+
+    match ContentType::from_name(&image_format).filter(|content_type| supported.contains(content_type)) {
+        Some(content_type) => {content_type}
+        None => {
+            let supported: Vec<&str> = supported.iter().map(|content_type| content_type.extension()).collect();
+            error!("Invalid image_format {} of {}, expected one of {}", image_format, task, supported.join(", "));
+            process::exit(2);
+        }
+    }
+}
+
+pub fn load_tasks_config() -> Result<TasksConfig, ConfigError> {
+    let path = TaskConfigLocation::init_from_env().unwrap().path;
+
+    let contents = fs::read_to_string(&path).map_err(|err| ConfigError::Read(path.clone(), err))?;
+
+    return parse_tasks_config(&path, &contents);
+}
+
+fn parse_tasks_config(path: &str, contents: &str) -> Result<TasksConfig, ConfigError> {
+    let config_file: TaskConfigFile = toml::from_str(contents).map_err(|err| ConfigError::Parse(path.to_string(), err))?;
+
+    for (task, entry) in config_file.tasks.iter() {
+        let spec = TASK_SPECS.iter().find(|spec| spec.name == task)
+            .ok_or(ConfigError::UnknownTask(task.clone()))?;
+
+        for (param, value) in entry.params.iter() {
+            let kind = spec.params.iter().find(|(name, _)| name == param)
+                .map(|(_, kind)| *kind)
+                .ok_or(ConfigError::UnknownParameter(task.clone(), param.clone()))?;
+
+            if !kind.matches(value) {
+                return Err(ConfigError::InvalidParameter(task.clone(), param.clone(), kind));
+            }
+        }
+    }
+
+    let mut tasks = BTreeMap::new();
+    for spec in TASK_SPECS {
+        let entry = config_file.tasks.get(spec.name);
+
+        tasks.insert(spec.name.to_string(), TaskConfig {
+            enabled: entry.and_then(|entry| entry.enabled).unwrap_or(spec.enabled),
+            interval_ms: entry.and_then(|entry| entry.interval_ms).unwrap_or(spec.interval_ms),
+            params: entry.map(|entry| entry.params.clone()).unwrap_or_default()
+        });
+    }
+
+    return Ok(TasksConfig { tasks });
+}
+
+#[cfg(test)]
+mod task_config_tests {
+    use std::collections::HashSet;
+    use crate::application::task_config::{ConfigError, get_data_sources, parse_tasks_config, TASK_SPECS};
+
+    #[test]
+    fn registered_names_are_unique() {
+        let task_names: HashSet<&str> = TASK_SPECS.iter().map(|task| task.name).collect();
+        assert_eq!(task_names.len(), TASK_SPECS.len());
+
+        let sources = get_data_sources();
+        let source_names: HashSet<&str> = sources.iter().map(|spec| spec.source.name()).collect();
+        assert_eq!(source_names.len(), sources.len());
+    }
+
+    #[test]
+    fn defaults_and_overrides() {
+        let config = parse_tasks_config("tasks.toml", "
+            [tasks.ir_cam_capture]
+            interval_ms = 500
+            params = { camera_index = 2 }
+        ").ok().unwrap();
+
+        assert_eq!(config.get("ir_cam_capture").interval_ms, 500);
+        assert_eq!(config.get("ir_cam_capture").get_integer_param("camera_index"), Some(2));
+        assert!(!config.is_enabled("example"));
+        assert_eq!(config.get("obc_telemetry").interval_ms, 1000);
+    }
+
+    #[test]
+    fn rejects_invalid_entries() {
+        let unknown_task = parse_tasks_config("tasks.toml", "[tasks.lidar]\nenabled = true");
+        assert!(matches!(unknown_task, Err(ConfigError::UnknownTask(_))));
+
+        let unknown_param = parse_tasks_config("tasks.toml", "[tasks.pib_adapter]\nparams = { baud = 9600 }");
+        assert!(matches!(unknown_param, Err(ConfigError::UnknownParameter(_, _))));
+
+        let invalid_param = parse_tasks_config("tasks.toml", "[tasks.ir_cam_capture]\nparams = { camera_index = \"99\" }");
+        assert!(matches!(invalid_param, Err(ConfigError::InvalidParameter(_, _, _))));
+
+        let invalid_interval = parse_tasks_config("tasks.toml", "[tasks.example]\ninterval_ms = -5");
+        assert!(matches!(invalid_interval, Err(ConfigError::Parse(_, _))));
+    }
+}
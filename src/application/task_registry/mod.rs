@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use json::{JsonValue, object};
+use log::info;
+use crate::application::clock::clock;
+use crate::application::data_manage::DataSource;
+use crate::application::data_manage::data_bus::DataBus;
+
+// Condition that has to hold before a task or service is allowed to run
+#[derive(Clone)]
+pub enum Dependency {
+    FreshData(DataSource, Duration),
+    Task(String)
+}
+
+impl fmt::Display for Dependency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Dependency::FreshData(source, max_age) => {
+                write!(f, "{} fresher than {} ms", source, max_age.as_millis())
+            }
+            Dependency::Task(name) => {
+                write!(f, "task {} running", name)
+            }
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub enum TaskState {
+    Starting,
+    Waiting(Vec<String>),
+    Running,
+    Stopped
+}
+
+impl TaskState {
+    fn name(&self) -> &'static str {
+        match self {
+            TaskState::Starting => {"starting"}
+            TaskState::Waiting(_) => {"waiting"}
+            TaskState::Running => {"running"}
+            TaskState::Stopped => {"stopped"}
+        }
+    }
+}
+
+struct TaskStatus {
+    state: TaskState,
+    last_progress: Instant,
+    stall_timeout: Option<Duration>
+}
+
+pub struct TaskRegistry {
+    data_bus: Arc<DataBus>,
+    statuses: Mutex<HashMap<String, TaskStatus>>
+}
+
+impl TaskRegistry {
+    pub fn new(data_bus: Arc<DataBus>) -> Self {
+        Self {
+            data_bus,
+            statuses: Mutex::new(HashMap::new())
+        }
+    }
+
+    // Critical tasks must report progress within their stall timeout for the system to be considered healthy
+    pub fn register(&self, name: &str, stall_timeout: Option<Duration>) {
+        self.statuses.lock().unwrap().insert(name.to_string(), TaskStatus {
+            state: TaskState::Starting,
+            last_progress: Instant::now(),
+            stall_timeout
+        });
+    }
+
+    pub fn get_state(&self, name: &str) -> Option<TaskState> {
+        return self.statuses.lock().unwrap().get(name).map(|status| status.state.clone());
+    }
+
+    pub fn set_state(&self, name: &str, state: TaskState) {
+        let previous = match self.statuses.lock().unwrap().get_mut(name) {
+            Some(status) => std::mem::replace(&mut status.state, state.clone()),
+            None => {
+                return;
+            }
+        };
+
+        if previous == state {
+            return;
+        }
+
+        match &state {
+            TaskState::Waiting(unmet) => {
+                info!("Task {} waiting on: {}", name, unmet.join(", "));
+            }
+            _ => {
+                info!("Task {} is now {}", name, state.name());
+            }
+        }
+    }
+
+    pub fn report_progress(&self, name: &str) {
+        if let Some(status) = self.statuses.lock().unwrap().get_mut(name) {
+            status.last_progress = Instant::now();
+        }
+    }
+
+    // Names of critical tasks that have stopped or have not made progress within their stall timeout
+    pub fn get_unhealthy_tasks(&self) -> Vec<String> {
+        let current_time = Instant::now();
+
+        return self.statuses.lock().unwrap().iter()
+            .filter(|(_, status)| {
+                match status.stall_timeout {
+                    Some(stall_timeout) => {
+                        status.state == TaskState::Stopped
+                            || current_time.duration_since(status.last_progress).gt(&stall_timeout)
+                    }
+                    None => false
+                }
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+    }
+
+    // Evaluates the given dependencies and records whether the task is running or waiting on them
+    pub fn check_dependencies(&self, name: &str, dependencies: &[Dependency]) -> bool {
+        let unmet: Vec<String> = dependencies.iter()
+            .filter(|dependency| !self.is_satisfied(dependency))
+            .map(|dependency| dependency.to_string())
+            .collect();
+
+        let satisfied = unmet.is_empty();
+        if satisfied {
+            self.set_state(name, TaskState::Running);
+        } else {
+            self.set_state(name, TaskState::Waiting(unmet));
+        }
+
+        return satisfied;
+    }
+
+    fn is_satisfied(&self, dependency: &Dependency) -> bool {
+        match dependency {
+            Dependency::FreshData(source, max_age) => {
+                let current_data = self.data_bus.get_latest(*source);
+
+                if current_data.is_none() {
+                    return false;
+                }
+
+                // Aged on the monotonic clock, so the wall clock stepping to GPS time does not make data stale
+                let age = clock().get_monotonic_ms().saturating_sub(current_data.unwrap().monotonic_ms);
+
+                return age <= max_age.as_millis() as u64;
+            }
+            Dependency::Task(name) => {
+                return self.get_state(name) == Some(TaskState::Running);
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut tasks = JsonValue::new_object();
+
+        for (name, status) in self.statuses.lock().unwrap().iter() {
+            let mut waiting_on = JsonValue::new_array();
+            if let TaskState::Waiting(unmet) = &status.state {
+                for dependency in unmet {
+                    waiting_on.push(dependency.clone()).unwrap();
+                }
+            }
+
+            tasks[name.as_str()] = object!{
+                state: status.state.name(),
+                waiting_on: waiting_on,
+                critical: status.stall_timeout.is_some(),
+                last_progress_ms: status.last_progress.elapsed().as_millis() as u64
+            };
+        }
+
+        return tasks;
+    }
+}
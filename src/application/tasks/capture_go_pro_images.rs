@@ -1,9 +1,23 @@
 use reqwest::blocking::Client;
 use envconfig::Envconfig;
-use crate::application::timer::TimedTask;
+use log::warn;
+use crate::application::task_config::{BuiltTask, TaskConfig, TaskContext, TaskSpec};
+use crate::application::timer::{TimedTask, Timer};
 
-#[derive(Envconfig)]
-struct GoProConfig {
+// Images are stored on the GoPro itself, the task only keeps it recording
+pub const TASK_SPEC: TaskSpec = TaskSpec { name: "gopro_control", enabled: true, interval_ms: 5000, params: &[], sources: &[], build };
+
+// GoPro has no simulated stand-in as it only produces files on its own storage
+fn build(context: &TaskContext, config: &TaskConfig) -> Option<BuiltTask> {
+    if context.flight.is_some() {
+        return None;
+    }
+
+    return Some((Timer::new("GoProControl".to_string(), config.interval()), Box::from(GoProTask::new())));
+}
+
+#[derive(Envconfig, Debug)]
+pub struct GoProConfig {
     #[envconfig(from = "GOPRO_HTTP_ADDR", default = "http://172.23.186.51:8080")]
     pub go_pro_addr: String
 }
@@ -27,7 +41,7 @@ impl TimedTask for GoProTask {
         let response = self.client.get(&*format!("{}/gopro/camera/keep_alive", self.go_pro_addr)).send();
 
         if response.is_err() || response.unwrap().status() != 200 {
-            println!("WARNING: GoPro is not responding to keep alive command");
+            warn!("GoPro is not responding to keep alive command");
             return;
         }
     }
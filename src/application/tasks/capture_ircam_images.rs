@@ -1,36 +1,110 @@
-use std::sync::mpsc::SyncSender;
 use opencv::core::MatTraitConst;
-use crate::application::data_manage::{DataSource, get_data_source_string, IncomingData};
+use bytes::Bytes;
+use log::warn;
+use crate::application::data_manage::{ContentType, DataSource, DataSourceSpec, IncomingData};
+use crate::application::data_manage::storage_queue::StorageSender;
 use crate::application::DataCollector;
-use crate::application::timer::TimedTask;
+use crate::application::geotag::GeoTagger;
+use crate::application::simulation::sim_cameras;
+use crate::application::simulation::sim_cameras::{SimCamera, SimCameraKind};
+use crate::application::task_config::{BuiltTask, get_image_format_or_exit, ParamKind, TaskConfig, TaskContext, TaskSpec};
+use crate::application::timer::{TimedTask, Timer};
 use opencv::{imgcodecs, videoio, core};
 use opencv::prelude::Mat;
 use opencv::videoio::VideoCaptureTrait;
 
+const DEFAULT_CAMERA_INDEX: i32 = 99;
+
+pub const THERMAL_IMAGE: DataSourceSpec = DataSourceSpec { source: DataSource::new("thermal_img"), content_type: ContentType::Png, units: &[] };
+
+pub const IMAGE_FORMATS: &[ContentType] = &[ContentType::Png, ContentType::Jpeg, ContentType::Tiff];
+
+pub const TASK_SPEC: TaskSpec = TaskSpec {
+    name: "ir_cam_capture",
+    enabled: true,
+    interval_ms: 1000,
+    params: &[("camera_index", ParamKind::Integer), ("image_format", ParamKind::String)],
+    sources: &[THERMAL_IMAGE],
+    build
+};
+
+fn build(context: &TaskContext, config: &TaskConfig) -> Option<BuiltTask> {
+    let ir_cam_task: Box<dyn TimedTask + Send> = match &context.flight {
+        Some(flight) => {
+            let image_format = get_image_format_or_exit(config, TASK_SPEC.name, sim_cameras::IMAGE_FORMATS);
+            Box::from(SimCamera::new(context.storage_sender.clone(), flight.clone(), SimCameraKind::Thermal)
+                .with_image_format(image_format)
+                .with_geotagger(context.geotagger.clone()))
+        }
+        None => {
+            let image_format = get_image_format_or_exit(config, TASK_SPEC.name, IMAGE_FORMATS);
+            let mut ir_cam_task = CaptureIrImages::new(context.storage_sender.clone())
+                .with_image_format(image_format)
+                .with_geotagger(context.geotagger.clone());
+            if let Some(camera_index) = config.get_integer_param("camera_index") {
+                ir_cam_task = ir_cam_task.with_camera_index(camera_index as i32);
+            }
+            Box::from(ir_cam_task)
+        }
+    };
+
+    return Some((Timer::new("IrCamCapture".to_string(), config.interval()), ir_cam_task));
+}
+
 pub struct CaptureIrImages {
-    storage_sender: SyncSender<IncomingData>,
-    capture: Option<videoio::VideoCapture>
+    storage_sender: StorageSender,
+    capture: Option<videoio::VideoCapture>,
+    camera_index: i32,
+    image_format: ContentType,
+    geotagger: Option<GeoTagger>
 }
 
 impl DataCollector for CaptureIrImages {
-    fn new(storage_sender: SyncSender<IncomingData>) -> Self {
+    fn new(storage_sender: StorageSender) -> Self {
         Self {
             storage_sender,
-            capture: None
+            capture: None,
+            camera_index: DEFAULT_CAMERA_INDEX,
+            image_format: ContentType::Png,
+            geotagger: None
         }
     }
 }
 
+impl CaptureIrImages {
+    pub fn with_camera_index(mut self, camera_index: i32) -> Self {
+        self.camera_index = camera_index;
+        return self;
+    }
+
+    // TIFF frames keep the camera's own bit depth, such as the 16 bits of radiometric thermal cameras
+    pub fn with_image_format(mut self, image_format: ContentType) -> Self {
+        self.image_format = image_format;
+        return self;
+    }
+
+    pub fn with_geotagger(mut self, geotagger: GeoTagger) -> Self {
+        self.geotagger = Option::from(geotagger);
+        return self;
+    }
+}
+
 impl TimedTask for CaptureIrImages {
     fn execute(&mut self) -> () {
         if self.capture.is_none() {
             // TODO: find a way to differentiate cameras
-            let mut new_capture = videoio::VideoCapture::new(99,videoio::CAP_ANY);
+            let mut new_capture = videoio::VideoCapture::new(self.camera_index, videoio::CAP_ANY);
 
             if new_capture.is_ok() {
-                self.capture = Option::from(new_capture.unwrap());
+                let mut new_capture = new_capture.unwrap();
+                if self.image_format == ContentType::Tiff {
+                    if let Err(err) = new_capture.set(videoio::CAP_PROP_CONVERT_RGB, 0.0) {
+                        warn!("Failed to request raw thermal frames: {:?}", err);
+                    }
+                }
+                self.capture = Option::from(new_capture);
             } else {
-                println!("Thermal camera not connected!");
+                warn!("Thermal camera not connected!");
                 return;
             }
         }
@@ -39,18 +113,29 @@ impl TimedTask for CaptureIrImages {
         self.capture.as_mut().unwrap().read(&mut frame).unwrap();
 
         if frame.empty() {
-            println!("End of thermal camera stream!");
+            warn!("End of thermal camera stream!");
             return;
         }
 
+        // Tagged as soon as the frame is read, encoding takes a while
+        let geotag = self.geotagger.as_ref().map(|geotagger| geotagger.get_geotag());
+
         let mut image_data = core::Vector::<u8>::new();
 
-        imgcodecs::imencode(".png", &frame, &mut image_data, &core::Vector::<i32>::new()).expect("Failed to encode compressed thermal image into bytes");
+        let extension = format!(".{}", self.image_format.extension());
+        if let Err(err) = imgcodecs::imencode(&extension, &frame, &mut image_data, &core::Vector::<i32>::new()) {
+            warn!("Failed to encode thermal image as {}: {}", self.image_format.extension(), err);
+            return;
+        }
 
-        let ir_cam_incoming_data = IncomingData::new(DataSource::IrCamImage, None, Option::from(image_data.to_vec()));
+        let mut ir_cam_incoming_data = IncomingData::new(THERMAL_IMAGE.source, None, Option::from(Bytes::from(image_data.to_vec())))
+            .with_content_type(self.image_format);
+        if let Some(geotag) = geotag {
+            ir_cam_incoming_data = ir_cam_incoming_data.with_geotag(geotag);
+        }
 
-        self.storage_sender.send(ir_cam_incoming_data)
-            .expect(&*format!("Failed to send data into write queue: {}",
-                              get_data_source_string(&DataSource::IrCamImage)));
+        if let Err(err) = self.storage_sender.send(ir_cam_incoming_data) {
+            warn!("Failed to queue {} data for storage: {}", THERMAL_IMAGE.source, err);
+        }
     }
 }
@@ -1,31 +1,104 @@
 use std::process::{Command};
-use std::sync::mpsc::SyncSender;
 use std::thread;
 use std::time::Duration;
-use crate::application::data_manage::{DataSource, get_data_source_string, IncomingData};
+use bytes::Bytes;
+use log::warn;
+use crate::application::data_manage::{ContentType, DataSource, DataSourceSpec, IncomingData};
+use crate::application::data_manage::storage_queue::StorageSender;
 use crate::application::DataCollector;
-use crate::application::timer::TimedTask;
+use crate::application::geotag::GeoTagger;
+use crate::application::simulation::sim_cameras;
+use crate::application::simulation::sim_cameras::{SimCamera, SimCameraKind};
+use crate::application::task_config::{BuiltTask, get_image_format_or_exit, ParamKind, TaskConfig, TaskContext, TaskSpec};
+use crate::application::timer::{TimedTask, Timer};
+
+const DEFAULT_CAPTURE_SCRIPT: &str = "helper-scripts/capture-pi-camera-raw-frame.py";
+
+pub const PICAM_IMAGE: DataSourceSpec = DataSourceSpec { source: DataSource::new("picam_image"), content_type: ContentType::Png, units: &[] };
+
+pub const IMAGE_FORMATS: &[ContentType] = &[ContentType::Png, ContentType::Jpeg, ContentType::Tiff];
+
+pub const TASK_SPEC: TaskSpec = TaskSpec {
+    name: "pi_cam_capture",
+    enabled: true,
+    interval_ms: 1000,
+    params: &[("capture_script", ParamKind::String), ("image_format", ParamKind::String)],
+    sources: &[PICAM_IMAGE],
+    build
+};
+
+fn build(context: &TaskContext, config: &TaskConfig) -> Option<BuiltTask> {
+    let pi_cam_task: Box<dyn TimedTask + Send> = match &context.flight {
+        Some(flight) => {
+            let image_format = get_image_format_or_exit(config, TASK_SPEC.name, sim_cameras::IMAGE_FORMATS);
+            Box::from(SimCamera::new(context.storage_sender.clone(), flight.clone(), SimCameraKind::PiCam)
+                .with_image_format(image_format)
+                .with_geotagger(context.geotagger.clone()))
+        }
+        None => {
+            let image_format = get_image_format_or_exit(config, TASK_SPEC.name, IMAGE_FORMATS);
+            let mut pi_cam_task = CapturePiCamImages::new(context.storage_sender.clone())
+                .with_image_format(image_format)
+                .with_geotagger(context.geotagger.clone());
+            if let Some(capture_script) = config.get_string_param("capture_script") {
+                pi_cam_task = pi_cam_task.with_capture_script(capture_script);
+            }
+            Box::from(pi_cam_task)
+        }
+    };
+
+    return Some((Timer::new("PiCamCapture".to_string(), config.interval()), pi_cam_task));
+}
 
 pub struct CapturePiCamImages {
-    storage_sender: SyncSender<IncomingData>
+    storage_sender: StorageSender,
+    capture_script: String,
+    image_format: ContentType,
+    geotagger: Option<GeoTagger>
 }
 
 impl DataCollector for CapturePiCamImages {
-    fn new(storage_sender: SyncSender<IncomingData>) -> Self {
+    fn new(storage_sender: StorageSender) -> Self {
         Self {
-            storage_sender
+            storage_sender,
+            capture_script: DEFAULT_CAPTURE_SCRIPT.to_string(),
+            image_format: ContentType::Png,
+            geotagger: None
         }
     }
 }
 
+impl CapturePiCamImages {
+    pub fn with_capture_script(mut self, capture_script: String) -> Self {
+        self.capture_script = capture_script;
+        return self;
+    }
+
+    // Passed on to the capture script, which encodes the image
+    pub fn with_image_format(mut self, image_format: ContentType) -> Self {
+        self.image_format = image_format;
+        return self;
+    }
+
+    pub fn with_geotagger(mut self, geotagger: GeoTagger) -> Self {
+        self.geotagger = Option::from(geotagger);
+        return self;
+    }
+}
+
 impl TimedTask for CapturePiCamImages {
     fn execute(&mut self) -> () {
+        // Taken when the capture starts, the script only returns after the camera has settled and the image is encoded
+        let geotag = self.geotagger.as_ref().map(|geotagger| geotagger.get_geotag());
+
         let cam_proc_result = Command::new("python")
-            .arg("helper-scripts/capture-pi-camera-raw-frame.py")
+            .arg(&self.capture_script)
+            .arg("--format")
+            .arg(self.image_format.extension())
             .output();
 
         if cam_proc_result.is_err() {
-            println!("Failed to capture picamera image!");
+            warn!("Failed to capture picamera image!");
             thread::sleep(Duration::from_secs(10)); // TODO: only log every 10 secs but still reattempt as normal
             return;
         }
@@ -34,10 +107,14 @@ impl TimedTask for CapturePiCamImages {
 
         let image_data = cam_proc.stdout;
 
-        let pi_cam_incoming_data = IncomingData::new(DataSource::PiCamImage, None, Option::from(image_data));
+        let mut pi_cam_incoming_data = IncomingData::new(PICAM_IMAGE.source, None, Option::from(Bytes::from(image_data)))
+            .with_content_type(self.image_format);
+        if let Some(geotag) = geotag {
+            pi_cam_incoming_data = pi_cam_incoming_data.with_geotag(geotag);
+        }
 
-        self.storage_sender.send(pi_cam_incoming_data)
-            .expect(&*format!("Failed to send data into write queue: {}",
-                              get_data_source_string(&DataSource::PiCamImage)));
+        if let Err(err) = self.storage_sender.send(pi_cam_incoming_data) {
+            warn!("Failed to queue {} data for storage: {}", PICAM_IMAGE.source, err);
+        }
     }
 }
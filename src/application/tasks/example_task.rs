@@ -1,15 +1,31 @@
-use std::sync::mpsc::SyncSender;
-use json::object;
-use crate::application::data_manage::{DataSource, get_data_source_string, IncomingData};
+use log::warn;
+use serde::Serialize;
+use crate::application::data_manage::{ContentType, DataSource, DataSourceSpec, IncomingData, Telemetry};
+use crate::application::data_manage::storage_queue::StorageSender;
 use crate::application::DataCollector;
-use crate::application::timer::TimedTask;
+use crate::application::task_config::{BuiltTask, TaskConfig, TaskContext, TaskSpec};
+use crate::application::timer::{TimedTask, Timer};
+
+pub const EXAMPLE: DataSourceSpec = DataSourceSpec { source: DataSource::new("example"), content_type: ContentType::Json, units: &[] };
+
+pub const TASK_SPEC: TaskSpec = TaskSpec { name: "example", enabled: false, interval_ms: 1000, params: &[], sources: &[EXAMPLE], build };
+
+fn build(context: &TaskContext, config: &TaskConfig) -> Option<BuiltTask> {
+    let example_task = ExampleTask::new(context.storage_sender.clone());
+    return Some((Timer::new("Example_Task".to_string(), config.interval()), Box::from(example_task)));
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Example {
+    pub test: String
+}
 
 pub struct ExampleTask {
-    storage_sender: SyncSender<IncomingData>
+    storage_sender: StorageSender
 }
 
 impl DataCollector for ExampleTask {
-    fn new(storage_sender: SyncSender<IncomingData>) -> Self {
+    fn new(storage_sender: StorageSender) -> Self {
         Self {
             storage_sender
         }
@@ -18,14 +34,14 @@ impl DataCollector for ExampleTask {
 
 impl TimedTask for ExampleTask {
     fn execute(&mut self) -> () {
-        let example_payload = object!{
-            test: "This is a test!"
+        let example_payload = Example {
+            test: "This is a test!".to_string()
         };
 
-        let example_data = IncomingData::new(DataSource::Example, Option::from(example_payload), None);
+        let example_data = IncomingData::new(EXAMPLE.source, Option::from(Telemetry::Example(example_payload)), None);
 
-        self.storage_sender.send(example_data)
-            .expect(&*format!("Failed to send data into write queue: {}",
-                              get_data_source_string(&DataSource::Example)));
+        if let Err(err) = self.storage_sender.send(example_data) {
+            warn!("Failed to queue {} data for storage: {}", EXAMPLE.source, err);
+        }
     }
 }
\ No newline at end of file
@@ -1,34 +1,91 @@
 use std::sync::Arc;
-use std::sync::mpsc::{Receiver, SyncSender};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
 use std::thread;
-use std::time::Duration;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 use envconfig::Envconfig;
-use json::{JsonValue};
 use mavlink::common::{HEARTBEAT_DATA, MavAutopilot, MavMessage, MavModeFlag, MavState, MavType};
 use mavlink::{MavConnection};
-use crate::application::data_manage::{DataSource, get_data_source_string, IncomingData};
-use crate::application::timer::TimedTask;
+use log::{debug, error, warn};
+use serde::Serialize;
+use crate::application::clock::clock;
+use crate::application::data_manage::{ContentType, DataSource, DataSourceSpec, IncomingData, Telemetry};
+use crate::application::data_manage::storage_queue::StorageSender;
+use crate::application::ADAPTER_STALL_TIMEOUT;
+use crate::application::simulation::sim_mavlink::SimMavlinkAdapter;
+use crate::application::task_config::{BuiltTask, TaskConfig, TaskContext, TaskSpec};
+use crate::application::timer::{TimedTask, Timer};
+
+pub const GLOBAL_POSITION: DataSourceSpec = DataSourceSpec {
+    source: DataSource::new("global_position"),
+    content_type: ContentType::Json,
+    units: &[("time_boot_ms", "ms"), ("lat", "degE7"), ("lon", "degE7"), ("alt", "mm"), ("relative_alt", "mm"),
+        ("vx", "cm/s"), ("vy", "cm/s"), ("vz", "cm/s"), ("hdg", "cdeg")]
+};
+
+pub const ATTITUDE: DataSourceSpec = DataSourceSpec {
+    source: DataSource::new("attitude"),
+    content_type: ContentType::Json,
+    units: &[("time_boot_ms", "ms"), ("roll", "rad"), ("pitch", "rad"), ("yaw", "rad"),
+        ("rollspeed", "rad/s"), ("pitchspeed", "rad/s"), ("yawspeed", "rad/s")]
+};
+
+// Heartbeats of the autopilot, carrying whether the vehicle is armed
+pub const HEARTBEAT: DataSourceSpec = DataSourceSpec { source: DataSource::new("heartbeat"), content_type: ContentType::Json, units: &[] };
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Heartbeat {
+    pub armed: bool,
+    pub base_mode: u8,
+    pub custom_mode: u32,
+    pub system_status: String
+}
+
+pub const TASK_SPEC: TaskSpec = TaskSpec {
+    name: "mavlink_adapter",
+    enabled: true,
+    interval_ms: 0,
+    params: &[],
+    sources: &[GLOBAL_POSITION, ATTITUDE, HEARTBEAT],
+    build
+};
+
+fn build(context: &TaskContext, config: &TaskConfig) -> Option<BuiltTask> {
+    let mavlink_adapter: Box<dyn TimedTask + Send> = match &context.flight {
+        Some(flight) => Box::from(SimMavlinkAdapter::new(context.storage_sender.clone(), context.take_mavlink_commands(), flight.clone())),
+        None => Box::from(MavlinkAdapter::new(context.storage_sender.clone(), context.take_mavlink_commands()))
+    };
+
+    let mavlink_adapter_timer = Timer::new("MavlinkAdapter".to_string(), config.interval())
+        .critical(ADAPTER_STALL_TIMEOUT);
+    return Some((mavlink_adapter_timer, mavlink_adapter));
+}
 
-#[derive(Envconfig)]
-struct MavlinkConfig {
+#[derive(Envconfig, Debug)]
+pub struct MavlinkConfig {
     #[envconfig(from = "PIXHAWK_PORT", default = "serial:/dev/ttyAMA2:57600")]
     pub pixhawk_port: String
 }
 
 pub struct MavlinkAdapter {
-    storage_sender: SyncSender<IncomingData>,
+    storage_sender: StorageSender,
     command_recv: Receiver<MavMessage>,
     mavlink_connection: Option<Arc<Box<dyn MavConnection<MavMessage> + Sync + Send>>>,
-    heartbeat_lock: bool
+    heartbeat_lock: bool,
+    heartbeat_stop: Arc<AtomicBool>,
+    heartbeat_handle: Option<JoinHandle<()>>
 }
 
 impl MavlinkAdapter {
-    pub fn new(storage_sender: SyncSender<IncomingData>, command_recv: Receiver<MavMessage>) -> Self {
+    pub fn new(storage_sender: StorageSender, command_recv: Receiver<MavMessage>) -> Self {
         Self {
             storage_sender,
             command_recv,
             mavlink_connection: None,
-            heartbeat_lock: false
+            heartbeat_lock: false,
+            heartbeat_stop: Arc::new(AtomicBool::new(false)),
+            heartbeat_handle: None
         }
     }
 
@@ -36,7 +93,7 @@ impl MavlinkAdapter {
         if self.heartbeat_lock == false {
             self.heartbeat_lock = true;
 
-            thread::spawn({
+            self.heartbeat_handle = Option::from(thread::spawn({
                 let heartbeat = MavMessage::HEARTBEAT(HEARTBEAT_DATA {
                     custom_mode: 0,
                     mavtype: MavType::MAV_TYPE_ONBOARD_CONTROLLER,
@@ -47,15 +104,60 @@ impl MavlinkAdapter {
                 });
 
                 let connection = self.mavlink_connection.as_mut().unwrap().clone();
+                let heartbeat_stop = self.heartbeat_stop.clone();
 
-                move || loop {
+                move || while !heartbeat_stop.load(Ordering::Relaxed) {
                     let res = connection.send_default(&heartbeat);
                     if res.is_err() {
-                        println!("Failed to send initial heartbeat to PixHawk: {res:?}");
+                        warn!("Failed to send initial heartbeat to PixHawk: {res:?}");
                     }
                     thread::sleep(Duration::from_secs(1));
                 }
-            });
+            }));
+        }
+    }
+}
+
+impl MavlinkAdapter {
+    pub fn handle_message(message: MavMessage, storage_sender: &StorageSender) {
+        match message {
+            // Handle received messages as needed
+            MavMessage::COMMAND_LONG(command_long) => {
+                debug!("Received COMMAND_LONG: {:?}", command_long);
+            }
+            // Ground stations and companion computers send heartbeats with an invalid autopilot
+            MavMessage::HEARTBEAT(heartbeat) if heartbeat.autopilot != MavAutopilot::MAV_AUTOPILOT_INVALID => {
+                let status = Heartbeat {
+                    armed: heartbeat.base_mode.contains(MavModeFlag::MAV_MODE_FLAG_SAFETY_ARMED),
+                    base_mode: heartbeat.base_mode.bits(),
+                    custom_mode: heartbeat.custom_mode,
+                    system_status: format!("{:?}", heartbeat.system_status)
+                };
+
+                let payload = IncomingData::new(HEARTBEAT.source, Option::from(Telemetry::Heartbeat(status)), None);
+                if let Err(err) = storage_sender.send(payload) {
+                    warn!("Failed to queue {} data for storage: {}", HEARTBEAT.source, err);
+                }
+            }
+            MavMessage::GLOBAL_POSITION_INT(global_position) => {
+                let payload = IncomingData::new(GLOBAL_POSITION.source, Option::from(Telemetry::GlobalPosition(global_position)), None);
+                if let Err(err) = storage_sender.send(payload) {
+                    warn!("Failed to queue {} data for storage: {}", GLOBAL_POSITION.source, err);
+                }
+            }
+            // GPS time of the autopilot, zero until it has a fix
+            MavMessage::SYSTEM_TIME(system_time) => {
+                clock().sync_gps(system_time.time_unix_usec, Instant::now());
+            }
+            MavMessage::ATTITUDE(attitude) => {
+                let payload = IncomingData::new(ATTITUDE.source, Option::from(Telemetry::Attitude(attitude)), None);
+                if let Err(err) = storage_sender.send(payload) {
+                    warn!("Failed to queue {} data for storage: {}", ATTITUDE.source, err);
+                }
+            }
+            _ => {
+                debug!("Received MAVLink message from PixHawk: {:?}", message);
+            }
         }
     }
 }
@@ -68,7 +170,7 @@ impl TimedTask for MavlinkAdapter {
             if new_connection.is_ok() {
                 self.mavlink_connection = Option::from(Arc::new(new_connection.unwrap()));
             } else {
-                println!("PixHawk not connected!");
+                warn!("PixHawk not connected!");
                 thread::sleep(Duration::from_secs(10));
                 return;
             }
@@ -80,38 +182,10 @@ impl TimedTask for MavlinkAdapter {
         let connection = self.mavlink_connection.as_mut().unwrap().clone();
         match connection.recv() {
             Ok((_header, message)) => {
-                match message {
-                    // Handle received messages as needed
-                    MavMessage::COMMAND_LONG(command_long) => {
-                        //println!("Received COMMAND_LONG: {:?}", command_long);
-                    }
-                    MavMessage::GLOBAL_POSITION_INT(global_position) => {
-                        let json_string = serde_json::to_string(&global_position).expect("Failed to serialize MAVLink Global Position message to JSON for storage");
-                        let serialized = JsonValue::from(json_string);
-
-                        let payload = IncomingData::new(DataSource::GlobalPosition, Option::from(serialized), None);
-                        self.storage_sender.send(payload)
-                            .expect(&*format!("Failed to send data into write queue: {}",
-                                              get_data_source_string(&DataSource::GlobalPosition)));
-                    }
-                    MavMessage::ATTITUDE(attitude) => {
-                        let json_string = serde_json::to_string(&attitude).expect("Failed to serialize MAVLink Altitude message to JSON for storage");
-                        let serialized = JsonValue::from(json_string);
-
-                        //println!("RECEIVED ATTITUDE: {:?}", attitude);
-                        
-                        let payload = IncomingData::new(DataSource::Attitude, Option::from(serialized), None);
-                        self.storage_sender.send(payload)
-                            .expect(&*format!("Failed to send data into write queue: {}",
-                                              get_data_source_string(&DataSource::Attitude)));
-                    }
-                    _ => {
-                        //println!("Received MAVLink message from PixHawk: {:?}", message);
-                    }
-                }
+                MavlinkAdapter::handle_message(message, &self.storage_sender);
             }
             Err(err) => {
-                eprintln!("Error receiving MAVLink message from PixHawk: {:?}", err);
+                error!("Error receiving MAVLink message from PixHawk: {:?}", err);
             }
         }
 
@@ -121,8 +195,23 @@ impl TimedTask for MavlinkAdapter {
 
             let res = connection.send_default(&mavlink_command);
             if res.is_err() {
-                println!("Failed to send command to PixHawk: {res:?}");
+                warn!("Failed to send command to PixHawk: {res:?}");
             }
         }
     }
+
+    fn shutdown(&mut self) -> Result<(), String> {
+        self.heartbeat_stop.store(true, Ordering::Relaxed);
+
+        if let Some(heartbeat_handle) = self.heartbeat_handle.take() {
+            if heartbeat_handle.join().is_err() {
+                return Err("MAVLink heartbeat thread panicked".to_string());
+            }
+        }
+
+        // Connection is closed once the last reference, previously shared with the heartbeat thread, is dropped
+        self.mavlink_connection = None;
+
+        return Ok(());
+    }
 }
@@ -1,23 +1,66 @@
 use std::process::Command;
-use std::sync::mpsc::SyncSender;
-use json::object;
-use crate::application::data_manage::{DataSource, get_data_source_string, IncomingData};
+use log::warn;
+use serde::Serialize;
+use crate::application::data_manage::{ContentType, DataSource, DataSourceSpec, IncomingData, Telemetry};
+use crate::application::data_manage::storage_queue::StorageSender;
 use crate::application::DataCollector;
-use crate::application::timer::TimedTask;
+use crate::application::simulation::sim_obc_telem::SimObcTelem;
+use crate::application::task_config::{BuiltTask, ParamKind, TaskConfig, TaskContext, TaskSpec};
+use crate::application::timer::{TimedTask, Timer};
+
+const DEFAULT_STORAGE_PATH: &str = "/";
+
+pub const OBC_TELEMETRY: DataSourceSpec = DataSourceSpec {
+    source: DataSource::new("obc_telemetry"),
+    content_type: ContentType::Json,
+    units: &[("core_temperature", "degC")]
+};
+
+pub const TASK_SPEC: TaskSpec = TaskSpec {
+    name: "obc_telemetry",
+    enabled: true,
+    interval_ms: 1000,
+    params: &[("storage_path", ParamKind::String)],
+    sources: &[OBC_TELEMETRY],
+    build
+};
+
+fn build(context: &TaskContext, config: &TaskConfig) -> Option<BuiltTask> {
+    let obc_telemetry: Box<dyn TimedTask + Send> = if context.flight.is_some() {
+        Box::from(SimObcTelem::new(context.storage_sender.clone()))
+    } else {
+        let mut obc_telemetry = ObcTelem::new(context.storage_sender.clone());
+        if let Some(storage_path) = config.get_string_param("storage_path") {
+            obc_telemetry = obc_telemetry.with_storage_path(storage_path);
+        }
+        Box::from(obc_telemetry)
+    };
+
+    return Some((Timer::new("ObcTelemetry".to_string(), config.interval()), obc_telemetry));
+}
+
+// Readings that could not be taken are null
+#[derive(Clone, Debug, Serialize)]
+pub struct ObcTelemetry {
+    pub core_temperature: Option<f32>,
+    // Used out of total size of the storage volume, such as 8.2G/29G
+    pub storage_space: Option<String>
+}
 
 pub struct ObcTelem {
-    storage_sender: SyncSender<IncomingData>
+    storage_sender: StorageSender,
+    storage_path: String
 }
 
 impl ObcTelem {
-    fn get_temperature() -> String {
+    fn get_temperature() -> Option<f32> {
         let temp_proc_result = Command::new("vcgencmd")
             .arg("measure_temp")
             .output();
 
         if temp_proc_result.is_err() {
-            println!("Failed to call cmd to measure OBC temp!");
-            return "".to_string();
+            warn!("Failed to call cmd to measure OBC temp!");
+            return None;
         }
         let temp_proc = temp_proc_result.unwrap();
 
@@ -25,22 +68,31 @@ impl ObcTelem {
 
         let temp_string_split: Vec<&str> = temp_string.split("=").collect();
         if temp_string_split.len() != 2 {
-            println!("Failed to parse command response for OBC temp!");
-            return "".to_string();
+            warn!("Failed to parse command response for OBC temp!");
+            return None;
         }
 
-        return temp_string_split[1].strip_suffix("'C\n").unwrap().to_string();
+        let temperature = temp_string_split[1].strip_suffix("'C\n").and_then(|temperature| temperature.parse::<f32>().ok());
+        if temperature.is_none() {
+            warn!("Failed to parse command response for OBC temp!");
+        }
+        return temperature;
+    }
+
+    pub fn with_storage_path(mut self, storage_path: String) -> Self {
+        self.storage_path = storage_path;
+        return self;
     }
 
-    fn get_storage_left() -> String {
+    fn get_storage_left(storage_path: &str) -> Option<String> {
         let storage_proc_result = Command::new("df")
             .arg("-h")
-            .arg("/")
+            .arg(storage_path)
             .output();
 
         if storage_proc_result.is_err() {
-            println!("Failed to call cmd to check storage space!");
-            return "".to_string();
+            warn!("Failed to call cmd to check storage space!");
+            return None;
         }
         let storage_proc = storage_proc_result.unwrap();
 
@@ -49,25 +101,26 @@ impl ObcTelem {
         let storage_split: Vec<&str> = storage_output.split("\n").collect();
 
         if storage_split.len() < 2 {
-            println!("Failed to parse command response for OBC storage!");
-            return "".to_string();
+            warn!("Failed to parse command response for OBC storage!");
+            return None;
         }
 
         let storage_split: Vec<&str> = storage_split[1].split_whitespace().collect();
 
         if storage_split.len() < 6 {
-            println!("Failed to parse command response for OBC storage!");
-            return "".to_string();
+            warn!("Failed to parse command response for OBC storage!");
+            return None;
         }
 
-        return format!("{}/{}", storage_split[2], storage_split[1]);
+        return Option::from(format!("{}/{}", storage_split[2], storage_split[1]));
     }
 }
 
 impl DataCollector for ObcTelem {
-    fn new(storage_sender: SyncSender<IncomingData>) -> Self {
+    fn new(storage_sender: StorageSender) -> Self {
         Self {
-            storage_sender
+            storage_sender,
+            storage_path: DEFAULT_STORAGE_PATH.to_string()
         }
     }
 }
@@ -76,17 +129,17 @@ impl TimedTask for ObcTelem {
     fn execute(&mut self) -> () {
         let temp_value = ObcTelem::get_temperature();
 
-        let storage_space_remaining = ObcTelem::get_storage_left();
+        let storage_space_remaining = ObcTelem::get_storage_left(&self.storage_path);
 
-        let telem_json = object!{
+        let telemetry = ObcTelemetry {
             core_temperature: temp_value,
             storage_space: storage_space_remaining
         };
 
-        let obc_telem = IncomingData::new(DataSource::ObcTelemetry, Option::from(telem_json), None);
+        let obc_telem = IncomingData::new(OBC_TELEMETRY.source, Option::from(Telemetry::ObcTelemetry(telemetry)), None);
 
-        self.storage_sender.send(obc_telem)
-            .expect(&*format!("Failed to send data into write queue: {}",
-                              get_data_source_string(&DataSource::ObcTelemetry)));
+        if let Err(err) = self.storage_sender.send(obc_telem) {
+            warn!("Failed to queue {} data for storage: {}", OBC_TELEMETRY.source, err);
+        }
     }
 }
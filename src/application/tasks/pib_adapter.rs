@@ -1,28 +1,98 @@
-use std::sync::mpsc::{Receiver, SyncSender};
+use std::sync::Mutex;
+use std::sync::mpsc::{Receiver, SyncSender, TrySendError};
 use std::thread;
 use std::time::Duration;
 use envconfig::Envconfig;
 use serialport::SerialPort;
 use byteorder::{ByteOrder, BigEndian};
-use json::object;
+use serde::Serialize;
 use ll_protocol::frame::Frame;
 use ll_protocol::frame_deserializer::FrameDeserializer;
 use ll_protocol::frame_serializer::FrameSerializer;
-use crate::application::data_manage::{DataSource, get_data_source_string, IncomingData};
+use log::warn;
+use crate::application::data_manage::{ContentType, DataSource, DataSourceSpec, IncomingData, Telemetry};
+use crate::application::data_manage::storage_queue::StorageSender;
 use crate::application::DataCollector;
-use crate::application::timer::TimedTask;
+use crate::application::ADAPTER_STALL_TIMEOUT;
+use crate::application::simulation::sim_pib::SimPibAdapter;
+use crate::application::task_config::{BuiltTask, TaskConfig, TaskContext, TaskSpec};
+use crate::application::timer::{TimedTask, Timer};
 
-const POWER_TELEMETRY_SERVICE: u8 = 0;
-const TEMPERATURE_TELEMETRY_SERVICE: u8 = 1;
-const ENVIRONMENTAL_SENSOR_SERVICE: u8 = 2;
-const ACTUATOR_CONTROL_SERVICE: u8 = 3;
+pub const POWER_TELEMETRY_SERVICE: u8 = 0;
+pub const TEMPERATURE_TELEMETRY_SERVICE: u8 = 1;
+pub const ENVIRONMENTAL_SENSOR_SERVICE: u8 = 2;
+pub const ACTUATOR_CONTROL_SERVICE: u8 = 3;
 
 const PACKET_IN_POWER_TELEMETRY_LENGTH: u8 = 12;
 const PACKET_IN_TEMPERATURE_TELEMETRY_LENGTH: u8 = 20;
 const PACKET_IN_ENVIRONMENTAL_SENSOR_LENGTH: u8 = 8;
 
-#[derive(Envconfig)]
-struct PibAdapterConfig {
+pub const POWER: DataSourceSpec = DataSourceSpec {
+    source: DataSource::new("power"),
+    content_type: ContentType::Json,
+    units: &[("average_voltage", "V"), ("average_current", "A"), ("average_power", "W")]
+};
+
+pub const TEMPERATURE: DataSourceSpec = DataSourceSpec {
+    source: DataSource::new("temperature"),
+    content_type: ContentType::Json,
+    units: &[("power_converter_temperature", "degC"), ("esc_1_temperature", "degC"), ("esc_2_temperature", "degC"),
+        ("esc_3_temperature", "degC"), ("esc_4_temperature", "degC")]
+};
+
+pub const ENVIRONMENTAL: DataSourceSpec = DataSourceSpec {
+    source: DataSource::new("environmental"),
+    content_type: ContentType::Json,
+    units: &[("temperature", "degC"), ("humidity", "%")]
+};
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Power {
+    pub average_voltage: f32,
+    pub average_current: f32,
+    pub average_power: f32
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Temperature {
+    pub power_converter_temperature: f32,
+    pub esc_1_temperature: f32,
+    pub esc_2_temperature: f32,
+    pub esc_3_temperature: f32,
+    pub esc_4_temperature: f32
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Environmental {
+    pub temperature: f32,
+    pub humidity: f32
+}
+
+// Identifies the adapter's timer in the task registry, which tasks commanding the PIB depend on
+pub const TIMER_NAME: &str = "PIBAdapter";
+
+pub const TASK_SPEC: TaskSpec = TaskSpec {
+    name: "pib_adapter",
+    enabled: true,
+    interval_ms: 0,
+    params: &[],
+    sources: &[POWER, TEMPERATURE, ENVIRONMENTAL],
+    build
+};
+
+fn build(context: &TaskContext, config: &TaskConfig) -> Option<BuiltTask> {
+    let pib_adapter_task: Box<dyn TimedTask + Send> = match &context.flight {
+        Some(flight) => Box::from(SimPibAdapter::new(context.storage_sender.clone(), context.take_pib_frames(), flight.clone())),
+        None => Box::from(PibAdapter::new(context.storage_sender.clone(), context.take_pib_frames()))
+    };
+
+    let pib_adapter_timer = Timer::new(TIMER_NAME.to_string(), config.interval())
+        .critical(ADAPTER_STALL_TIMEOUT);
+    return Some((pib_adapter_timer, pib_adapter_task));
+}
+
+#[derive(Envconfig, Debug)]
+pub struct PibAdapterConfig {
     #[envconfig(from = "PIB_SERIAL_PORT", default = "/dev/ttyAMA0")]
     pub serial_port: String
 }
@@ -31,12 +101,12 @@ pub struct PibAdapter {
     serial: Option<Box<dyn SerialPort>>,
     frame_deserializer: FrameDeserializer,
     serial_buf: Vec<u8>,
-    storage_sender: SyncSender<IncomingData>,
+    storage_sender: StorageSender,
     frame_receiver: Receiver<Frame>
 }
 
 impl PibAdapter {
-    pub fn new(storage_sender: SyncSender<IncomingData>, frame_receiver: Receiver<Frame>) -> Self {
+    pub fn new(storage_sender: StorageSender, frame_receiver: Receiver<Frame>) -> Self {
         Self {
             frame_deserializer: FrameDeserializer::new(),
             serial_buf: vec![0; 128],
@@ -57,7 +127,7 @@ impl TimedTask for PibAdapter {
             if new_port.is_ok() {
                 self.serial = Option::from(new_port.unwrap());
             } else {
-                println!("PIB port not connected!");
+                warn!("PIB port not connected!");
                 thread::sleep(Duration::from_secs(10));
                 return;
             }
@@ -81,23 +151,48 @@ impl TimedTask for PibAdapter {
         let frame_result = self.frame_receiver.recv_timeout(Duration::from_millis(0));
         if frame_result.is_ok() {
             let frame = frame_result.unwrap();
-            self.send_frame(frame);
+            if let Err(err) = self.send_frame(frame) {
+                warn!("Failed to send frame to PIB: {}", err);
+            }
+        }
+    }
+
+    fn shutdown(&mut self) -> Result<(), String> {
+        // Flush out commands queued during shutdown, such as stowing the payload
+        let mut result = Ok(());
+        while let Ok(frame) = self.frame_receiver.try_recv() {
+            if let Err(err) = self.send_frame(frame) {
+                result = Err(format!("Failed to send frame to PIB during shutdown: {}", err));
+            }
         }
+
+        if let Some(serial) = self.serial.as_mut() {
+            if let Err(err) = serial.flush() {
+                result = Err(format!("Failed to flush PIB serial port: {}", err));
+            }
+        }
+
+        // Closes the serial port
+        self.serial = None;
+
+        return result;
     }
 }
 
 impl PibAdapter {
-    fn send_frame(&mut self, frame: Frame) {
+    fn send_frame(&mut self, frame: Frame) -> Result<(), String> {
         let frame_serializer = FrameSerializer::new(frame, true);
 
         let serialized_frame =  &frame_serializer.collect::<Vec<u8>>();
 
-        let serial = self.serial.as_mut().unwrap();
+        let serial = self.serial.as_mut().ok_or("PIB port not connected".to_string())?;
+
+        serial.write_all(serialized_frame).map_err(|err| err.to_string())?;
 
-        serial.write(serialized_frame).expect("Write failed!");
+        return Ok(());
     }
 
-    fn handle_in_frame(mut frame: Frame, storage_sender: SyncSender<IncomingData>) {
+    pub fn handle_in_frame(mut frame: Frame, storage_sender: StorageSender) {
         match frame.get_service() {
             POWER_TELEMETRY_SERVICE => {
                 if frame.get_payload_length() != PACKET_IN_POWER_TELEMETRY_LENGTH {
@@ -109,16 +204,16 @@ impl PibAdapter {
                 let av_cur = BigEndian::read_f32(&frame.get_payload()[4..8]);
                 let av_pow = BigEndian::read_f32(&frame.get_payload()[8..12]);
 
-                let payload = object!{
+                let payload = Power {
                     average_voltage: av_volt,
                     average_current: av_cur,
                     average_power: av_pow
                 };
 
-                let data_payload = IncomingData::new(DataSource::Power, Option::from(payload), None);
-                storage_sender.send(data_payload)
-                    .expect(&*format!("Failed to send data into write queue: {}",
-                                      get_data_source_string(&DataSource::Power)));
+                let data_payload = IncomingData::new(POWER.source, Option::from(Telemetry::Power(payload)), None);
+                if let Err(err) = storage_sender.send(data_payload) {
+                    warn!("Failed to queue {} data for storage: {}", POWER.source, err);
+                }
             },
             TEMPERATURE_TELEMETRY_SERVICE => {
                 if frame.get_payload_length() != PACKET_IN_TEMPERATURE_TELEMETRY_LENGTH {
@@ -132,7 +227,7 @@ impl PibAdapter {
                 let esc_3_temp = BigEndian::read_f32(&frame.get_payload()[12..16]);
                 let esc_4_temp = BigEndian::read_f32(&frame.get_payload()[16..20]);
 
-                let payload = object!{
+                let payload = Temperature {
                     power_converter_temperature: pow_converter_temp,
                     esc_1_temperature: esc_1_temp,
                     esc_2_temperature: esc_2_temp,
@@ -140,10 +235,10 @@ impl PibAdapter {
                     esc_4_temperature: esc_4_temp
                 };
 
-                let data_payload = IncomingData::new(DataSource::Temperature, Option::from(payload), None);
-                storage_sender.send(data_payload)
-                    .expect(&*format!("Failed to send data into write queue: {}",
-                                      get_data_source_string(&DataSource::Temperature)));
+                let data_payload = IncomingData::new(TEMPERATURE.source, Option::from(Telemetry::Temperature(payload)), None);
+                if let Err(err) = storage_sender.send(data_payload) {
+                    warn!("Failed to queue {} data for storage: {}", TEMPERATURE.source, err);
+                }
             },
             ENVIRONMENTAL_SENSOR_SERVICE => {
                 if frame.get_payload_length() != PACKET_IN_ENVIRONMENTAL_SENSOR_LENGTH {
@@ -154,15 +249,15 @@ impl PibAdapter {
                 let temp = BigEndian::read_f32(&frame.get_payload()[0..4]);
                 let hum = BigEndian::read_f32(&frame.get_payload()[4..8]);
 
-                let payload = object!{
+                let payload = Environmental {
                     temperature: temp,
                     humidity: hum
                 };
 
-                let data_payload = IncomingData::new(DataSource::Environmental, Option::from(payload), None);
-                storage_sender.send(data_payload)
-                    .expect(&*format!("Failed to send data into write queue: {}",
-                                      get_data_source_string(&DataSource::Environmental)));
+                let data_payload = IncomingData::new(ENVIRONMENTAL.source, Option::from(Telemetry::Environmental(payload)), None);
+                if let Err(err) = storage_sender.send(data_payload) {
+                    warn!("Failed to queue {} data for storage: {}", ENVIRONMENTAL.source, err);
+                }
             },
             _ => {
                 // TODO: log invalid in-service num
@@ -206,16 +301,23 @@ const LIGHT_MODE_SHIFT: u8 = 5;
 const LIGHT_BRIGHTNESS_SHIFT: u8 = 0;
 
 pub struct PibCommander {
-    frame_sender: SyncSender<Frame>
+    frame_sender: SyncSender<Frame>,
+    // Last servo value sent, images are tagged with it
+    servo_value: Mutex<Option<i8>>
 }
 
 impl PibCommander {
     pub fn new(frame_sender: SyncSender<Frame>) -> Self {
         Self{
-            frame_sender
+            frame_sender,
+            servo_value: Mutex::new(None)
         }
     }
 
+    pub fn get_servo_value(&self) -> Option<i8> {
+        return *self.servo_value.lock().unwrap();
+    }
+
     pub fn put_power_set_rate(&self, rate: u8) {
 
     }
@@ -244,12 +346,18 @@ impl PibCommander {
 
     }
 
-    pub fn put_servo_set(&self, pos: i8) {
+    pub fn put_servo_set(&self, pos: i8) -> Result<(), String> {
         let payload: u8 = pos as u8;
 
         let frame = Frame::new(ACTUATOR_CONTROL_SERVICE, vec![payload]);
 
-        self.frame_sender.send(frame.clone()).expect(&*format!("Failed to send frame: {}", frame));
+        // Never blocks, so a stalled adapter cannot hold up the orientator or its stow on shutdown
+        self.frame_sender.try_send(frame.clone()).map_err(|err| match err {
+            TrySendError::Full(_) => format!("PIB frame queue full, dropped frame: {}", frame),
+            TrySendError::Disconnected(_) => format!("PIB adapter stopped, dropped frame: {}", frame)
+        })?;
+        *self.servo_value.lock().unwrap() = Some(pos);
+        return Ok(());
     }
 
     pub fn put_indicator_light_set(&mut self, mode: LightMode, brightness: u8) {
@@ -1,17 +1,71 @@
-use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, mpsc};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
 use std::thread;
 use std::time::{Duration, Instant};
+use log::info;
+use crate::application::shutdown::ShutdownHandle;
+use crate::application::task_registry::{Dependency, TaskRegistry, TaskState};
+
+const TRIGGER_POLL_PERIOD: Duration = Duration::from_millis(25);
 
 // Runs at every time interval
 pub trait TimedTask {
     fn execute(&mut self) -> ();
+
+    // Called once after the task is killed to release its resources
+    fn shutdown(&mut self) -> Result<(), String> {
+        return Ok(());
+    }
+}
+
+enum Trigger {
+    Interval(Duration),
+    OnData(DataTrigger)
+}
+
+// Fires once a new sample has arrived, never more often than the minimum interval
+struct DataTrigger {
+    data_events: Receiver<()>,
+    min_interval: Duration,
+    pending: bool
+}
+
+impl DataTrigger {
+    fn take_due(&mut self, current_time: Instant, last_update: Instant) -> bool {
+        if !self.pending {
+            return false;
+        }
+
+        let since_update = current_time.duration_since(last_update);
+        if since_update.ge(&self.min_interval) {
+            self.pending = false;
+            return true;
+        }
+
+        return false;
+    }
+
+    fn wait(&mut self) {
+        match self.data_events.recv_timeout(TRIGGER_POLL_PERIOD) {
+            Ok(_) => {
+                // Coalesce everything that arrived meanwhile into a single execution
+                while self.data_events.try_recv().is_ok() {}
+                self.pending = true;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                thread::sleep(TRIGGER_POLL_PERIOD);
+            }
+        }
+    }
 }
 
 pub struct Timer {
     identification: String,
-    interval: Duration,
-    last_update: Instant
+    trigger: Trigger,
+    last_update: Instant,
+    dependencies: Vec<Dependency>,
+    stall_timeout: Option<Duration>
 }
 
 impl Timer {
@@ -19,35 +73,144 @@ impl Timer {
         Self {
             identification,
             last_update: Instant::now(),
-            interval
+            trigger: Trigger::Interval(interval),
+            dependencies: Vec::new(),
+            stall_timeout: None
+        }
+    }
+
+    // Executes whenever a new sample is published on the given data events subscription
+    pub fn on_data(identification: String, data_events: Receiver<()>) -> Self {
+        Self {
+            identification,
+            last_update: Instant::now(),
+            trigger: Trigger::OnData(DataTrigger {
+                data_events,
+                min_interval: Duration::ZERO,
+                pending: false
+            }),
+            dependencies: Vec::new(),
+            stall_timeout: None
+        }
+    }
+
+    // Only applies to data triggered timers
+    pub fn rate_limit(mut self, min_interval: Duration) -> Self {
+        if let Trigger::OnData(data_trigger) = &mut self.trigger {
+            data_trigger.min_interval = min_interval;
+        }
+        return self;
+    }
+
+    // System is reported unhealthy to the watchdog if the task stalls for longer than the timeout
+    pub fn critical(mut self, stall_timeout: Duration) -> Self {
+        self.stall_timeout = Option::from(stall_timeout);
+        return self;
+    }
+
+    // Task will only execute while all of its dependencies are satisfied
+    pub fn depends_on(mut self, dependency: Dependency) -> Self {
+        self.dependencies.push(dependency);
+        return self;
+    }
+
+    fn is_due(&mut self, current_time: Instant) -> bool {
+        match &mut self.trigger {
+            Trigger::Interval(interval) => {
+                return current_time.duration_since(self.last_update).ge(interval);
+            }
+            Trigger::OnData(data_trigger) => {
+                return data_trigger.take_due(current_time, self.last_update);
+            }
+        }
+    }
+
+    // Blocks until the next trigger may be due, returns true once a kill signal is received
+    fn wait_for_trigger(&mut self, kill_recv: &Receiver<bool>) -> bool {
+        match &mut self.trigger {
+            Trigger::Interval(_) => {
+                let kill_signal = kill_recv.recv_timeout(TRIGGER_POLL_PERIOD);
+                return kill_signal.is_ok() && kill_signal.unwrap() == true;
+            }
+            Trigger::OnData(data_trigger) => {
+                data_trigger.wait();
+                return kill_recv.try_recv() == Ok(true);
+            }
         }
     }
 }
 
-pub fn spawn_timer(timer: Timer, task: Box<dyn TimedTask + Send>) ->  Sender<bool> {
+pub fn spawn_timer(timer: Timer, task: Box<dyn TimedTask + Send>, task_registry: Arc<TaskRegistry>) -> ShutdownHandle {
     let (kill_sender, kill_recv) = mpsc::channel();
 
-    thread::spawn(|| {
-        timer_loop(timer, task, kill_recv);
+    task_registry.register(&timer.identification, timer.stall_timeout);
+
+    let identification = timer.identification.clone();
+    let join_handle = thread::spawn(|| {
+        return timer_loop(timer, task, kill_recv, task_registry);
     });
 
-    return kill_sender;
+    return ShutdownHandle::new(identification, kill_sender, join_handle);
 }
 
-fn timer_loop(mut timer: Timer, mut task: Box<dyn TimedTask>, kill_recv: Receiver<bool>) -> () {
+fn timer_loop(mut timer: Timer, mut task: Box<dyn TimedTask>, kill_recv: Receiver<bool>, task_registry: Arc<TaskRegistry>) -> Result<(), String> {
     loop {
         let current_time = Instant::now();
-        let duration = current_time.duration_since(timer.last_update);
-        if duration.ge(&timer.interval) {
-            task.execute();
+        if timer.is_due(current_time) {
+            if task_registry.check_dependencies(&timer.identification, &timer.dependencies) {
+                task.execute();
+            }
             timer.last_update = current_time;
         }
 
+        task_registry.report_progress(&timer.identification);
+
         // Halt task upon receiving kill signal
-        let kill_signal = kill_recv.recv_timeout(Duration::from_millis(25));
-        if kill_signal.is_ok() && kill_signal.unwrap() == true {
-            println!("Task {} was killed by signal", timer.identification);
-            break;
+        if timer.wait_for_trigger(&kill_recv) {
+            info!("Task {} was killed by signal", timer.identification);
+            let shutdown_result = task.shutdown();
+            task_registry.set_state(&timer.identification, TaskState::Stopped);
+            return shutdown_result;
         }
     }
 }
+
+#[cfg(test)]
+mod timer_tests {
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+    use crate::application::timer::Timer;
+
+    #[test]
+    fn data_trigger_waits_for_sample() {
+        let (event_sender, event_recv) = mpsc::channel();
+        let (_kill_sender, kill_recv) = mpsc::channel();
+        let mut timer = Timer::on_data("Test".to_string(), event_recv);
+
+        timer.wait_for_trigger(&kill_recv);
+        assert!(!timer.is_due(Instant::now()));
+
+        event_sender.send(()).unwrap();
+        event_sender.send(()).unwrap();
+        timer.wait_for_trigger(&kill_recv);
+        assert!(timer.is_due(Instant::now()));
+        // Both samples are coalesced into one execution
+        assert!(!timer.is_due(Instant::now()));
+    }
+
+    #[test]
+    fn data_trigger_rate_limit() {
+        let (event_sender, event_recv) = mpsc::channel();
+        let (_kill_sender, kill_recv) = mpsc::channel();
+        let mut timer = Timer::on_data("Test".to_string(), event_recv)
+            .rate_limit(Duration::from_secs(1));
+
+        event_sender.send(()).unwrap();
+        timer.wait_for_trigger(&kill_recv);
+        let event_time = Instant::now();
+
+        assert!(!timer.is_due(event_time));
+        assert!(!timer.is_due(event_time + Duration::from_millis(500)));
+        assert!(timer.is_due(timer.last_update + Duration::from_secs(1)));
+    }
+}
@@ -0,0 +1,16 @@
+use std::env;
+use std::process;
+use flightcode::mission_data;
+
+// Exports the flight track and image capture points of a mission directory, saved within it the images stay linked
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.as_slice() {
+        [mission_dir, format] => mission_data::export_mission_track(mission_dir, format),
+        _ => {
+            eprintln!("Usage: flightcode-export <mission directory> <gpx|kml|geojson>");
+            process::exit(2);
+        }
+    }
+}
@@ -0,0 +1,18 @@
+use std::env;
+use std::process;
+use flightcode::mission_data;
+
+// Summarizes a mission directory copied off the vehicle, as Markdown or with --html as an HTML page
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let html = args.iter().any(|arg| arg == "--html");
+    let mission_dirs: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+
+    match (mission_dirs.as_slice(), args.iter().all(|arg| !arg.starts_with("--") || arg == "--html")) {
+        ([mission_dir], true) => mission_data::report_mission(mission_dir, html),
+        _ => {
+            eprintln!("Usage: flightcode-report <mission directory> [--html]");
+            process::exit(2);
+        }
+    }
+}
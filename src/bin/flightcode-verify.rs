@@ -0,0 +1,14 @@
+use std::env;
+use std::process;
+use flightcode::mission_data;
+
+// Checks a mission directory copied off the vehicle against the integrity manifest written by the data manager
+fn main() {
+    match env::args().nth(1) {
+        Some(storage_dir) => mission_data::verify_mission(&storage_dir),
+        None => {
+            eprintln!("Usage: flightcode-verify <mission or idle directory>");
+            process::exit(2);
+        }
+    }
+}
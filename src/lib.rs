@@ -0,0 +1,9 @@
+// FlightCode as it runs on the vehicle
+#[cfg(feature = "vehicle")]
+pub mod application;
+// Reading back mission directories, shared with the ground tools, which are built without the vehicle feature and so
+// without OpenCV or any of the hardware and web server crates
+pub mod mission_data;
+// Fixtures shared by the unit tests of both modules
+#[cfg(test)]
+mod test_support;
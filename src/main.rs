@@ -1,9 +1,24 @@
-mod application;
+use std::env;
+use std::process;
+use flightcode::application;
 
-extern crate ll_protocol;
+fn main() {
+    if env::args().any(|arg| arg == "--print-config") {
+        application::print_config();
+        return;
+    }
 
-use std::{thread, time};
+    // Followed by a mission or idle directory
+    if let Some(position) = env::args().position(|arg| arg == "--convert-legacy") {
+        match env::args().nth(position + 1) {
+            Some(storage_dir) => application::convert_legacy(&storage_dir),
+            None => {
+                eprintln!("--convert-legacy requires a mission or idle directory");
+                process::exit(2);
+            }
+        }
+        return;
+    }
 
-fn main() {
-    application::start_application();
+    application::start_application(env::args().any(|arg| arg == "--sim"));
 }
@@ -0,0 +1,367 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub const INTEGRITY_FILE: &str = "integrity.json";
+
+// SHA-256 and size of a file, the size tells truncated files apart from otherwise altered ones
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct FileDigest {
+    pub sha256: String,
+    pub bytes: u64
+}
+
+// Digest of a file as it is being written
+#[derive(Clone, Default)]
+pub struct FileHasher {
+    hasher: Sha256,
+    bytes: u64
+}
+
+impl FileHasher {
+    pub fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+        self.bytes += data.len() as u64;
+    }
+
+    pub fn get_digest(&self) -> FileDigest {
+        return FileDigest { sha256: format!("{:x}", self.hasher.clone().finalize()), bytes: self.bytes };
+    }
+}
+
+pub fn get_digest(data: &[u8]) -> FileDigest {
+    let mut hasher = FileHasher::default();
+    hasher.update(data);
+    return hasher.get_digest();
+}
+
+fn read_digest(path: &Path) -> io::Result<FileDigest> {
+    let mut file = File::open(path)?;
+    let mut hasher = FileHasher::default();
+    let mut buffer = vec![0u8; 64 * 1024];
+
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(hasher.get_digest());
+        }
+        hasher.update(&buffer[..read]);
+    }
+}
+
+// Stored as integrity.json at the top of a mission or idle directory. Covers every file within the source directories,
+// the mission manifest and logs next to it are rewritten by other parts of FlightCode and left out
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IntegrityManifest {
+    // Set once the data manager has moved on from the directory and every file in it has been written
+    pub finalized: bool,
+    // Set when the manifest was rebuilt at startup after the directory was left in use by a power loss or crash
+    #[serde(default)]
+    pub recovered: bool,
+    pub algorithm: String,
+    // By path relative to the directory
+    pub files: BTreeMap<String, FileDigest>
+}
+
+#[derive(Default)]
+struct DirectoryDigests {
+    files: BTreeMap<String, FileDigest>,
+    pending_writes: usize,
+    closed: bool,
+    finalized: bool
+}
+
+// Digests of the files the data manager and image writer have written to each storage directory, written out as a
+// running manifest while the directory is in use and finalized once it is closed and its last queued image is written
+#[derive(Default)]
+pub struct IntegrityDigests {
+    directories: Mutex<HashMap<String, DirectoryDigests>>
+}
+
+impl IntegrityDigests {
+    pub fn new() -> Self {
+        return Self { directories: Mutex::new(HashMap::new()) };
+    }
+
+    // Files outside the storage directory are ignored
+    pub fn record(&self, storage_dir: &str, file: &Path, digest: FileDigest) {
+        let relative = match file.strip_prefix(storage_dir) {
+            Ok(relative) => relative.to_string_lossy().to_string(),
+            Err(_) => {
+                return;
+            }
+        };
+
+        self.directories.lock().unwrap().entry(storage_dir.to_string()).or_default().files.insert(relative, digest);
+    }
+
+    // Directories are reopened when data goes to them again, such as the idle area after a mission
+    pub fn open(&self, storage_dir: &str) {
+        let mut directories = self.directories.lock().unwrap();
+        let directory = directories.entry(storage_dir.to_string()).or_default();
+        directory.closed = false;
+        directory.finalized = false;
+    }
+
+    // A file queued for writing that the manifest waits for before being finalized
+    pub fn begin_write(&self, storage_dir: &str) {
+        self.directories.lock().unwrap().entry(storage_dir.to_string()).or_default().pending_writes += 1;
+    }
+
+    pub fn end_write(&self, storage_dir: &str) {
+        let mut directories = self.directories.lock().unwrap();
+        let directory = directories.entry(storage_dir.to_string()).or_default();
+        directory.pending_writes = directory.pending_writes.saturating_sub(1);
+        finalize_if_complete(storage_dir, directory);
+    }
+
+    pub fn close(&self, storage_dir: &str) {
+        let mut directories = self.directories.lock().unwrap();
+        let directory = directories.entry(storage_dir.to_string()).or_default();
+        directory.closed = true;
+        finalize_if_complete(storage_dir, directory);
+    }
+
+    // Running manifest of a directory still in use
+    pub fn write_snapshot(&self, storage_dir: &str) {
+        let directories = self.directories.lock().unwrap();
+        if let Some(directory) = directories.get(storage_dir).filter(|directory| !directory.finalized) {
+            if let Err(err) = write_integrity_manifest(storage_dir, &directory.files, false, false) {
+                warn!("{}", err);
+            }
+        }
+    }
+}
+
+fn finalize_if_complete(storage_dir: &str, directory: &mut DirectoryDigests) {
+    if !directory.closed || directory.pending_writes > 0 || directory.finalized {
+        return;
+    }
+
+    match write_integrity_manifest(storage_dir, &directory.files, true, false) {
+        Ok(()) => directory.finalized = true,
+        Err(err) => warn!("{}", err)
+    }
+}
+
+// Written next to the file and renamed over it, so a power loss leaves either the old or the new manifest
+fn write_integrity_manifest(storage_dir: &str, files: &BTreeMap<String, FileDigest>, finalized: bool, recovered: bool) -> Result<(), String> {
+    let path = format!("{}/{}", storage_dir, INTEGRITY_FILE);
+    let temp_path = format!("{}.tmp", path);
+
+    let manifest = IntegrityManifest { finalized, recovered, algorithm: "sha256".to_string(), files: files.clone() };
+    let contents = serde_json::to_string_pretty(&manifest)
+        .map_err(|err| format!("Failed to serialize integrity manifest {}: {}", path, err))?;
+
+    fs::write(&temp_path, contents)
+        .and_then(|_| File::open(&temp_path)?.sync_all())
+        .and_then(|_| fs::rename(&temp_path, &path))
+        .map_err(|err| format!("Failed to write integrity manifest {}: {}", path, err))?;
+
+    return Ok(());
+}
+
+#[derive(Debug)]
+pub struct AlteredFile {
+    pub file: String,
+    pub expected: FileDigest,
+    pub actual: FileDigest
+}
+
+// Outcome of checking a directory against its integrity manifest
+#[derive(Debug)]
+pub struct Verification {
+    pub finalized: bool,
+    pub recovered: bool,
+    pub verified: usize,
+    pub missing: Vec<String>,
+    pub extra: Vec<String>,
+    pub altered: Vec<AlteredFile>
+}
+
+impl Verification {
+    pub fn is_intact(&self) -> bool {
+        return self.missing.is_empty() && self.extra.is_empty() && self.altered.is_empty();
+    }
+}
+
+fn read_manifest(manifest_path: &Path) -> Result<IntegrityManifest, String> {
+    return fs::read_to_string(manifest_path)
+        .map_err(|err| format!("Failed to read {}: {}", manifest_path.display(), err))
+        .and_then(|contents| serde_json::from_str(&contents)
+            .map_err(|err| format!("Failed to parse {}: {}", manifest_path.display(), err)));
+}
+
+// Every file within the source directories by its path relative to the storage directory
+fn find_files(storage_dir: &Path) -> Result<BTreeMap<String, String>, String> {
+    let mut found = BTreeMap::new();
+    for source_dir in fs::read_dir(storage_dir).into_iter().flatten().flatten().filter(|entry| entry.path().is_dir()) {
+        collect_files(storage_dir, &source_dir.path(), &mut found)
+            .map_err(|err| format!("Failed to read {}: {}", source_dir.path().display(), err))?;
+    }
+    return Ok(found);
+}
+
+pub fn verify_directory(storage_dir: &Path) -> Result<Verification, String> {
+    let manifest = read_manifest(&storage_dir.join(INTEGRITY_FILE))?;
+    let mut verification = Verification { finalized: manifest.finalized, recovered: manifest.recovered, verified: 0,
+                                          missing: Vec::new(), extra: Vec::new(), altered: Vec::new() };
+
+    let mut found = find_files(storage_dir)?;
+    for (file, expected) in manifest.files.iter() {
+        let path = match found.remove(file) {
+            Some(path) => path,
+            None => {
+                verification.missing.push(file.clone());
+                continue;
+            }
+        };
+
+        let actual = read_digest(Path::new(&path)).map_err(|err| format!("Failed to read {}: {}", path, err))?;
+        if actual == *expected {
+            verification.verified += 1;
+        } else {
+            verification.altered.push(AlteredFile { file: file.clone(), expected: expected.clone(), actual });
+        }
+    }
+    verification.extra = found.into_keys().collect();
+
+    return Ok(verification);
+}
+
+// Manifests left unfinalized below the directory are from directories that were in use when FlightCode lost power or
+// crashed. Their last running snapshot misses recent files and the digests of segments recovery has since truncated, so
+// they are rebuilt from the files as recovered and flagged as such. Returns the rebuilt directories with their file count
+pub fn recover_manifests(dir: &Path) -> Vec<(PathBuf, Result<usize, String>)> {
+    let manifest_path = dir.join(INTEGRITY_FILE);
+    if manifest_path.is_file() {
+        return match read_manifest(&manifest_path) {
+            Ok(manifest) if manifest.finalized => Vec::new(),
+            Ok(_) => vec![(dir.to_path_buf(), rebuild_manifest(dir))],
+            // Left in place, a manifest is replaced atomically so an unreadable one is worth a look
+            Err(err) => vec![(dir.to_path_buf(), Err(err))]
+        };
+    }
+
+    return fs::read_dir(dir).into_iter().flatten().flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .flat_map(|path| recover_manifests(&path))
+        .collect();
+}
+
+fn rebuild_manifest(storage_dir: &Path) -> Result<usize, String> {
+    let mut files = BTreeMap::new();
+    for (file, path) in find_files(storage_dir)? {
+        let digest = read_digest(Path::new(&path)).map_err(|err| format!("Failed to read {}: {}", path, err))?;
+        files.insert(file, digest);
+    }
+
+    write_integrity_manifest(&storage_dir.to_string_lossy(), &files, true, true)?;
+    return Ok(files.len());
+}
+
+// Every file below the directory by its path relative to the storage directory
+fn collect_files(storage_dir: &Path, dir: &Path, found: &mut BTreeMap<String, String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(storage_dir, &path, found)?;
+        } else if let Ok(relative) = path.strip_prefix(storage_dir) {
+            found.insert(relative.to_string_lossy().to_string(), path.to_string_lossy().to_string());
+        }
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod integrity_tests {
+    use std::fs;
+    use std::path::Path;
+    use crate::mission_data::integrity::{get_digest, IntegrityDigests, IntegrityManifest, recover_manifests, verify_directory, INTEGRITY_FILE};
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn finalized_once_writes_complete_and_verified() {
+        let storage_dir = TempDir::new("integrity");
+        fs::create_dir_all(storage_dir.join("power")).unwrap();
+        fs::create_dir_all(storage_dir.join("thermal_img")).unwrap();
+        let dir = storage_dir.to_string_lossy().to_string();
+
+        let digests = IntegrityDigests::new();
+        digests.open(&dir);
+        for (file, contents) in [("power/power-00000.jsonl", "{}\n"), ("thermal_img/1.png", "png"), ("thermal_img/2.png", "png")] {
+            fs::write(storage_dir.join(file), contents).unwrap();
+            digests.record(&dir, &storage_dir.join(file), get_digest(contents.as_bytes()));
+        }
+        // Files outside of the directory are not recorded
+        digests.record(&dir, Path::new("/tmp/elsewhere.png"), get_digest(b""));
+
+        // Finalized only after the image still being written
+        digests.begin_write(&dir);
+        digests.close(&dir);
+        let read_manifest = || serde_json::from_str::<IntegrityManifest>(&fs::read_to_string(storage_dir.join(INTEGRITY_FILE)).unwrap()).unwrap();
+        assert!(!storage_dir.join(INTEGRITY_FILE).exists());
+        digests.end_write(&dir);
+        let manifest = read_manifest();
+        assert!(manifest.finalized);
+        assert_eq!(manifest.files.len(), 3);
+
+        // Top level files such as the mission manifest are not covered
+        fs::write(storage_dir.join("manifest.json"), "{}").unwrap();
+        let verification = verify_directory(&storage_dir).unwrap();
+        assert!(verification.is_intact() && verification.verified == 3);
+
+        fs::write(storage_dir.join("power/power-00000.jsonl"), "{").unwrap();
+        fs::remove_file(storage_dir.join("thermal_img/2.png")).unwrap();
+        fs::write(storage_dir.join("thermal_img/3.png"), "png").unwrap();
+        let verification = verify_directory(&storage_dir).unwrap();
+        assert_eq!(verification.verified, 1);
+        assert_eq!(verification.missing, ["thermal_img/2.png"]);
+        assert_eq!(verification.extra, ["thermal_img/3.png"]);
+        assert_eq!(verification.altered[0].file, "power/power-00000.jsonl");
+        assert_eq!((verification.altered[0].expected.bytes, verification.altered[0].actual.bytes), (3, 1));
+    }
+
+    #[test]
+    fn unfinalized_manifests_rebuilt_on_recovery() {
+        let root = TempDir::new("integrity-recovery");
+        let (crashed_dir, closed_dir) = (root.join("missions/0001-crashed"), root.join("missions/0002-closed"));
+
+        // Running snapshot taken before the last image was written and the segment was torn by the power loss
+        for (storage_dir, finalized) in [(&crashed_dir, false), (&closed_dir, true)] {
+            fs::create_dir_all(storage_dir.join("power")).unwrap();
+            fs::create_dir_all(storage_dir.join("thermal_img")).unwrap();
+            let dir = storage_dir.to_string_lossy().to_string();
+            let digests = IntegrityDigests::new();
+            fs::write(storage_dir.join("power/power-00000.jsonl"), "{}\n{").unwrap();
+            digests.record(&dir, &storage_dir.join("power/power-00000.jsonl"), get_digest(b"{}\n{"));
+            if finalized {
+                digests.close(&dir);
+            } else {
+                digests.write_snapshot(&dir);
+            }
+        }
+        fs::write(crashed_dir.join("power/power-00000.jsonl"), "{}\n").unwrap();
+        fs::write(crashed_dir.join("thermal_img/1.png"), "png").unwrap();
+
+        let recovered = recover_manifests(&root);
+        assert_eq!(recovered.len(), 1);
+        assert_eq!((recovered[0].0.as_path(), recovered[0].1.clone()), (crashed_dir.as_path(), Ok(2)));
+
+        let verification = verify_directory(&crashed_dir).unwrap();
+        assert!(verification.is_intact() && verification.finalized && verification.recovered);
+        assert_eq!(verification.verified, 2);
+        assert!(!verify_directory(&closed_dir).unwrap().recovered);
+
+        // Rebuilt manifests are finalized and left alone on the next startup
+        assert!(recover_manifests(&root).is_empty());
+    }
+}
@@ -0,0 +1,154 @@
+pub mod integrity;
+pub mod report;
+pub mod track_export;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use json::JsonValue;
+use crate::mission_data::integrity::verify_directory;
+use crate::mission_data::report::build_report;
+use crate::mission_data::track_export::{read_track, TrackFormat};
+
+pub const SEGMENT_EXTENSION: &str = "jsonl";
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SourceKind {
+    // Records in JSON Lines segments
+    Telemetry,
+    // A file per sample, such as images
+    Files
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct StoredSource {
+    pub name: String,
+    pub kind: SourceKind
+}
+
+// Segments of a source sorted by index
+pub fn get_segments(source_dir: &Path, source_name: &str) -> Vec<(u32, PathBuf)> {
+    let prefix = format!("{}-", source_name);
+    let suffix = format!(".{}", SEGMENT_EXTENSION);
+
+    let mut segments: Vec<(u32, PathBuf)> = fs::read_dir(source_dir).into_iter().flatten().flatten()
+        .filter_map(|entry| entry.file_name().to_str()
+            .and_then(|name| name.strip_prefix(&prefix))
+            .and_then(|name| name.strip_suffix(&suffix))
+            .and_then(|index| index.parse::<u32>().ok())
+            .map(|index| (index, entry.path())))
+        .collect();
+
+    segments.sort_by_key(|(index, _)| *index);
+    return segments;
+}
+
+// Mission manifest, Null for idle directories and manifests that cannot be read
+pub fn read_mission_manifest(mission_dir: &Path) -> JsonValue {
+    return fs::read_to_string(mission_dir.join(MANIFEST_FILE)).ok()
+        .and_then(|manifest| json::parse(&manifest).ok())
+        .unwrap_or(JsonValue::Null);
+}
+
+// Sources that stored data in the directory, told apart by what is in their directory rather than by the sources of this
+// build, as copied missions may come from another version. Ordered as in the mission manifest, then by name
+pub fn get_stored_sources(mission_dir: &Path) -> Vec<StoredSource> {
+    let mut sources: Vec<StoredSource> = fs::read_dir(mission_dir).into_iter().flatten().flatten()
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().to_str().map(|name| name.to_string()))
+        .filter_map(|name| {
+            let source_dir = mission_dir.join(&name);
+            if !get_segments(&source_dir, &name).is_empty() {
+                return Some(StoredSource { name, kind: SourceKind::Telemetry });
+            }
+
+            let has_files = fs::read_dir(&source_dir).into_iter().flatten().flatten().any(|entry| entry.path().is_file());
+            return has_files.then_some(StoredSource { name, kind: SourceKind::Files });
+        })
+        .collect();
+
+    let manifest = read_mission_manifest(mission_dir);
+    let order: Vec<&str> = manifest["sources"].entries().map(|(name, _)| name).collect();
+    sources.sort_by_key(|source| (order.iter().position(|name| *name == source.name).unwrap_or(order.len()), source.name.clone()));
+    return sources;
+}
+
+// Name of a mission as its id and name, or the directory name for idle directories
+pub fn get_mission_title(mission_dir: &Path) -> String {
+    let manifest = read_mission_manifest(mission_dir);
+    return match (manifest["id"].as_u32(), manifest["name"].as_str()) {
+        (Some(id), Some(name)) => format!("Mission {} {}", id, name),
+        _ => mission_dir.file_name().unwrap_or_default().to_string_lossy().to_string()
+    };
+}
+
+// Checks every file of a mission or idle directory against its integrity manifest, exiting with 1 when any is missing,
+// unexpected or altered and with 2 when the directory cannot be checked
+pub fn verify_mission(storage_dir: &str) {
+    let verification = match verify_directory(Path::new(storage_dir)) {
+        Ok(verification) => verification,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(2);
+        }
+    };
+
+    for file in verification.missing.iter() {
+        println!("MISSING {}", file);
+    }
+    for file in verification.extra.iter() {
+        println!("EXTRA {}", file);
+    }
+    for altered in verification.altered.iter() {
+        println!("ALTERED {} ({} bytes expected, {} found)", altered.file, altered.expected.bytes, altered.actual.bytes);
+    }
+
+    println!("{} files verified, {} missing, {} extra, {} altered", verification.verified, verification.missing.len(),
+             verification.extra.len(), verification.altered.len());
+    if verification.recovered {
+        println!("Manifest was rebuilt after FlightCode lost power or crashed, files are verified as they were recovered");
+    } else if !verification.finalized {
+        println!("Manifest was not finalized, the directory was still in use or FlightCode did not stop cleanly");
+    }
+
+    if !verification.is_intact() {
+        process::exit(1);
+    }
+}
+
+// Prints the post-flight summary of a mission directory as Markdown or HTML
+pub fn report_mission(mission_dir: &str, html: bool) {
+    let report = match build_report(Path::new(mission_dir)) {
+        Ok(report) => report,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(2);
+        }
+    };
+
+    if html {
+        print!("{}", report.to_html());
+    } else {
+        print!("{}", report.to_markdown());
+    }
+}
+
+// Prints the flight track and image locations of a mission directory as GPX, KML or GeoJSON
+pub fn export_mission_track(mission_dir: &str, format_name: &str) {
+    let format = match TrackFormat::from_name(format_name) {
+        Some(format) => format,
+        None => {
+            eprintln!("Unknown track format {}, expected gpx, kml or geojson", format_name);
+            process::exit(2);
+        }
+    };
+
+    match read_track(Path::new(mission_dir)) {
+        Ok(track) => print!("{}", track.export(format)),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(2);
+        }
+    }
+}
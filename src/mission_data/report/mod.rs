@@ -0,0 +1,381 @@
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use chrono::{DateTime, FixedOffset};
+use json::JsonValue;
+use serde_json::Value;
+use crate::mission_data::{get_mission_title, get_segments, get_stored_sources, read_mission_manifest, SourceKind, StoredSource};
+
+// Sources of the PIB and OBC telemetry the report is drawn from
+const POWER: &str = "power";
+const TEMPERATURE: &str = "temperature";
+const ENVIRONMENTAL: &str = "environmental";
+const OBC_TELEMETRY: &str = "obc_telemetry";
+
+// Intervals this many times longer than the usual interval of a source count as a gap
+const GAP_FACTOR: u64 = 5;
+// Shorter intervals never count as gaps, so the jitter of fast sources is not reported
+const GAP_MIN_MS: u64 = 2000;
+
+const NO_DATA: &str = "no data";
+
+struct Record {
+    time: DateTime<FixedOffset>,
+    // Missing from records stored before timestamps were taken on the monotonic clock
+    monotonic_ms: Option<u64>,
+    data: Value
+}
+
+impl Record {
+    // Monotonic time where available, so stepping the wall clock to GPS time does not show up as a gap
+    fn get_time_ms(&self) -> i64 {
+        return self.monotonic_ms.map_or(self.time.timestamp_millis(), |monotonic_ms| monotonic_ms as i64);
+    }
+}
+
+pub struct ReportSection {
+    pub title: &'static str,
+    pub header: Vec<&'static str>,
+    pub rows: Vec<Vec<String>>
+}
+
+// Summary of the data stored in a mission directory, rendered as Markdown or HTML
+pub struct MissionReport {
+    pub title: String,
+    pub sections: Vec<ReportSection>
+}
+
+impl MissionReport {
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = format!("# {}\n", self.title);
+
+        for section in self.sections.iter() {
+            let _ = write!(markdown, "\n## {}\n\n| {} |\n|{}\n", section.title, section.header.join(" | "), "---|".repeat(section.header.len()));
+            for row in section.rows.iter() {
+                let _ = writeln!(markdown, "| {} |", row.join(" | "));
+            }
+        }
+
+        return markdown;
+    }
+
+    pub fn to_html(&self) -> String {
+        let mut html = format!("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n<h1>{}</h1>\n",
+                               escape_html(&self.title), REPORT_STYLE, escape_html(&self.title));
+
+        for section in self.sections.iter() {
+            let _ = write!(html, "<h2>{}</h2>\n<table>\n<tr>", escape_html(section.title));
+            for column in section.header.iter() {
+                let _ = write!(html, "<th>{}</th>", escape_html(column));
+            }
+            html.push_str("</tr>\n");
+
+            for row in section.rows.iter() {
+                html.push_str("<tr>");
+                for cell in row.iter() {
+                    let _ = write!(html, "<td>{}</td>", escape_html(cell));
+                }
+                html.push_str("</tr>\n");
+            }
+            html.push_str("</table>\n");
+        }
+
+        html.push_str("</body>\n</html>\n");
+        return html;
+    }
+}
+
+const REPORT_STYLE: &str = "body { font-family: sans-serif; margin: 2em; } table { border-collapse: collapse; margin-bottom: 1em; } \
+                            th, td { border: 1px solid #ccc; padding: 4px 10px; text-align: left; }";
+
+fn escape_html(text: &str) -> String {
+    return text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;");
+}
+
+pub fn build_report(mission_dir: &Path) -> Result<MissionReport, String> {
+    if !mission_dir.is_dir() {
+        return Err(format!("{} is not a mission directory", mission_dir.display()));
+    }
+
+    let manifest = read_mission_manifest(mission_dir);
+    let sources = get_stored_sources(mission_dir);
+    let records: Vec<(&str, Vec<Record>)> = sources.iter()
+        .filter(|source| source.kind == SourceKind::Telemetry)
+        .map(|source| (source.name.as_str(), read_records(mission_dir, &source.name)))
+        .collect();
+    let get_records = |name: &str| -> &[Record] {
+        return records.iter().find(|(source, _)| *source == name).map_or(&[], |(_, records)| records.as_slice());
+    };
+
+    let sections = vec![
+        get_mission_section(&manifest, &records),
+        get_power_section(get_records(POWER)),
+        get_temperature_section(get_records(TEMPERATURE)),
+        get_environment_section(get_records(ENVIRONMENTAL)),
+        get_image_section(mission_dir, &sources),
+        get_gap_section(mission_dir, &sources, &records),
+        get_storage_section(mission_dir, get_records(OBC_TELEMETRY))
+    ];
+
+    return Ok(MissionReport { title: get_mission_title(mission_dir), sections });
+}
+
+// Torn or otherwise unreadable lines are skipped
+fn read_records(mission_dir: &Path, source_name: &str) -> Vec<Record> {
+    return get_segments(&mission_dir.join(source_name), source_name).iter()
+        .filter_map(|(_, segment)| fs::read_to_string(segment).ok())
+        .flat_map(|segment| segment.lines().filter_map(|line| serde_json::from_str::<Value>(line).ok()).collect::<Vec<Value>>())
+        .filter_map(|record| {
+            let time = DateTime::parse_from_rfc3339(record["timestamp"].as_str()?).ok()?;
+            return Some(Record { time, monotonic_ms: record["monotonic_ms"].as_u64(), data: record["data"].clone() });
+        })
+        .collect();
+}
+
+struct FieldSummary {
+    min: f64,
+    max: f64,
+    mean: f64
+}
+
+fn summarize(records: &[Record], field: &str) -> Option<FieldSummary> {
+    let values: Vec<f64> = records.iter().filter_map(|record| record.data[field].as_f64()).collect();
+    if values.is_empty() {
+        return None;
+    }
+
+    return Some(FieldSummary {
+        min: values.iter().cloned().fold(f64::INFINITY, f64::min),
+        max: values.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+        mean: values.iter().sum::<f64>() / values.len() as f64
+    });
+}
+
+fn format_value(value: Option<f64>, unit: &str) -> String {
+    return value.map_or(NO_DATA.to_string(), |value| format!("{:.2} {}", value, unit));
+}
+
+fn format_duration(ms: i64) -> String {
+    let seconds = ms.max(0) / 1000;
+    return match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, seconds) => format!("{} s", seconds),
+        (0, minutes, seconds) => format!("{} min {} s", minutes, seconds),
+        (hours, minutes, seconds) => format!("{} h {} min {} s", hours, minutes, seconds)
+    };
+}
+
+fn format_bytes(bytes: u64) -> String {
+    return match bytes {
+        bytes if bytes >= 1024 * 1024 * 1024 => format!("{:.2} GiB", bytes as f64 / (1024.0 * 1024.0 * 1024.0)),
+        bytes if bytes >= 1024 * 1024 => format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0)),
+        bytes if bytes >= 1024 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        bytes => format!("{} B", bytes)
+    };
+}
+
+// Missions that were not stopped cleanly have no end time, the last stored record is taken instead
+fn get_mission_section(manifest: &JsonValue, records: &[(&str, Vec<Record>)]) -> ReportSection {
+    let parse_time = |time: &JsonValue| time.as_str().and_then(|time| DateTime::parse_from_rfc3339(time).ok());
+    let all_records = || records.iter().flat_map(|(_, records)| records.iter());
+
+    let start_time = parse_time(&manifest["start_time"]).or(all_records().map(|record| record.time).min());
+    let (end_time, end_note) = match parse_time(&manifest["end_time"]) {
+        Some(end_time) => (Some(end_time), ""),
+        None => (all_records().map(|record| record.time).max(), " (last record, mission was not stopped)")
+    };
+
+    let text = |value: &JsonValue| value.as_str().map_or(NO_DATA.to_string(), |value| value.to_string());
+    let format_time = |time: Option<DateTime<FixedOffset>>| time.map_or(NO_DATA.to_string(), |time| time.to_rfc3339());
+
+    let duration = match (start_time, end_time) {
+        (Some(start_time), Some(end_time)) => format!("{}{}", format_duration((end_time - start_time).num_milliseconds()), end_note),
+        _ => NO_DATA.to_string()
+    };
+
+    return ReportSection {
+        title: "Mission",
+        header: vec!["Field", "Value"],
+        rows: vec![
+            vec!["Vehicle".to_string(), text(&manifest["vehicle_id"])],
+            vec!["Started by".to_string(), text(&manifest["started_by"])],
+            vec!["Start".to_string(), format_time(start_time)],
+            vec!["End".to_string(), format!("{}{}", format_time(end_time), end_note)],
+            vec!["Duration".to_string(), duration],
+            vec!["Software version".to_string(), text(&manifest["software_version"])]
+        ]
+    };
+}
+
+fn get_power_section(records: &[Record]) -> ReportSection {
+    let voltage = summarize(records, "average_voltage");
+    let current = summarize(records, "average_current");
+    let power = summarize(records, "average_power");
+
+    return ReportSection {
+        title: "Power",
+        header: vec!["Measure", "Value"],
+        rows: vec![
+            vec!["Minimum voltage".to_string(), format_value(voltage.as_ref().map(|voltage| voltage.min), "V")],
+            vec!["Average voltage".to_string(), format_value(voltage.as_ref().map(|voltage| voltage.mean), "V")],
+            vec!["Peak current".to_string(), format_value(current.map(|current| current.max), "A")],
+            vec!["Peak power".to_string(), format_value(power.map(|power| power.max), "W")]
+        ]
+    };
+}
+
+fn get_temperature_section(records: &[Record]) -> ReportSection {
+    let sensors = [("Power converter", "power_converter_temperature"), ("ESC 1", "esc_1_temperature"), ("ESC 2", "esc_2_temperature"),
+                   ("ESC 3", "esc_3_temperature"), ("ESC 4", "esc_4_temperature")];
+
+    return ReportSection {
+        title: "Temperatures",
+        header: vec!["Sensor", "Maximum"],
+        rows: sensors.iter()
+            .map(|(sensor, field)| vec![sensor.to_string(), format_value(summarize(records, field).map(|summary| summary.max), "degC")])
+            .collect()
+    };
+}
+
+fn get_environment_section(records: &[Record]) -> ReportSection {
+    return ReportSection {
+        title: "Environment",
+        header: vec!["Measure", "Minimum", "Maximum"],
+        rows: [("Temperature", "temperature", "degC"), ("Humidity", "humidity", "%")].iter()
+            .map(|(measure, field, unit)| {
+                let summary = summarize(records, field);
+                vec![measure.to_string(), format_value(summary.as_ref().map(|summary| summary.min), unit),
+                     format_value(summary.as_ref().map(|summary| summary.max), unit)]
+            })
+            .collect()
+    };
+}
+
+// Files of a source other than geotag sidecars, by their timestamp in milliseconds where it can be read from the name
+fn get_files(mission_dir: &Path, source_name: &str) -> Vec<Option<i64>> {
+    return fs::read_dir(mission_dir.join(source_name)).into_iter().flatten().flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_none_or(|extension| extension != "json"))
+        .map(|path| path.file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| DateTime::parse_from_rfc3339(stem.split('_').next().unwrap_or(stem)).ok())
+            .map(|time| time.timestamp_millis()))
+        .collect();
+}
+
+// Cameras that stored no image at all have an empty directory and are left out
+fn get_image_section(mission_dir: &Path, sources: &[StoredSource]) -> ReportSection {
+    return ReportSection {
+        title: "Images",
+        header: vec!["Source", "Images"],
+        rows: sources.iter()
+            .filter(|source| source.kind == SourceKind::Files)
+            .map(|source| vec![source.name.clone(), get_files(mission_dir, &source.name).len().to_string()])
+            .collect()
+    };
+}
+
+// Intervals well beyond the usual interval of the source, taken as the median
+fn get_gap_row(name: &str, mut times: Vec<i64>) -> Vec<String> {
+    times.sort();
+    let mut intervals: Vec<u64> = times.windows(2).map(|pair| (pair[1] - pair[0]).max(0) as u64).collect();
+    intervals.sort();
+
+    let usual = match intervals.get(intervals.len() / 2) {
+        Some(usual) => *usual,
+        None => {
+            return vec![name.to_string(), times.len().to_string(), NO_DATA.to_string(), "0".to_string(), "".to_string(), "".to_string()];
+        }
+    };
+
+    let gaps: Vec<u64> = intervals.into_iter().filter(|interval| *interval > (usual * GAP_FACTOR).max(GAP_MIN_MS)).collect();
+    let (longest, total) = match gaps.last() {
+        Some(longest) => (format_duration(*longest as i64), format_duration(gaps.iter().sum::<u64>() as i64)),
+        None => ("".to_string(), "".to_string())
+    };
+
+    return vec![name.to_string(), times.len().to_string(), format!("{} ms", usual), gaps.len().to_string(), longest, total];
+}
+
+fn get_gap_section(mission_dir: &Path, sources: &[StoredSource], records: &[(&str, Vec<Record>)]) -> ReportSection {
+    let rows = sources.iter()
+        .map(|source| match records.iter().find(|(name, _)| *name == source.name) {
+            Some((_, records)) => get_gap_row(&source.name, records.iter().map(|record| record.get_time_ms()).collect()),
+            None => get_gap_row(&source.name, get_files(mission_dir, &source.name).into_iter().flatten().collect())
+        })
+        .filter(|row| row[1] != "0")
+        .collect();
+
+    return ReportSection {
+        title: "Data Gaps",
+        header: vec!["Source", "Samples", "Usual interval", "Gaps", "Longest gap", "Total gap time"],
+        rows
+    };
+}
+
+fn get_directory_size(dir: &Path) -> u64 {
+    return fs::read_dir(dir).into_iter().flatten().flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => get_directory_size(&entry.path()),
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0
+        })
+        .sum();
+}
+
+fn get_storage_section(mission_dir: &Path, records: &[Record]) -> ReportSection {
+    let storage_space = |record: Option<&Record>| record.and_then(|record| record.data["storage_space"].as_str())
+        .map_or(NO_DATA.to_string(), |storage_space| storage_space.to_string());
+
+    return ReportSection {
+        title: "Storage",
+        header: vec!["Measure", "Value"],
+        rows: vec![
+            vec!["Mission data".to_string(), format_bytes(get_directory_size(mission_dir))],
+            vec!["OBC storage used at start".to_string(), storage_space(records.first())],
+            vec!["OBC storage used at end".to_string(), storage_space(records.last())]
+        ]
+    };
+}
+
+#[cfg(test)]
+mod report_tests {
+    use std::fs;
+    use crate::mission_data::report::build_report;
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn summarizes_mission_directory() {
+        let mission_dir = TempDir::new("report");
+        for source in ["power", "temperature", "thermal_img"] {
+            fs::create_dir_all(mission_dir.join(source)).unwrap();
+        }
+
+        fs::write(mission_dir.join("manifest.json"), r#"{"id": 3, "name": "north-field", "vehicle_id": "firedrone",
+            "start_time": "2024-05-01T10:00:00+00:00", "end_time": "2024-05-01T10:12:30+00:00"}"#).unwrap();
+
+        // One sample a second with the power board silent from 10 to 40 s
+        let power: Vec<String> = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 40, 41, 42].iter().enumerate()
+            .map(|(index, second)| format!(r#"{{"timestamp":"2024-05-01T10:00:{:02}+00:00","monotonic_ms":{},"data":{{"average_voltage":{},"average_current":{},"average_power":10.0}}}}"#,
+                                           second, second * 1000, 24.0 - index as f64 * 0.1, if index == 5 {12.5} else {2.0}))
+            .collect();
+        fs::write(mission_dir.join("power/power-00000.jsonl"), power.join("\n") + "\n{\"timestamp\":").unwrap();
+        fs::write(mission_dir.join("temperature/temperature-00000.jsonl"),
+                  r#"{"timestamp":"2024-05-01T10:00:00+00:00","data":{"power_converter_temperature":41.5,"esc_1_temperature":55.0}}"#).unwrap();
+        for file in ["2024-05-01T10:00:00+00:00.png", "2024-05-01T10:00:01+00:00.png", "2024-05-01T10:00:01+00:00.json"] {
+            fs::write(mission_dir.join("thermal_img").join(file), "png").unwrap();
+        }
+
+        let markdown = build_report(&mission_dir).unwrap().to_markdown();
+        assert!(markdown.starts_with("# Mission 3 north-field\n"));
+        assert!(markdown.contains("| Duration | 12 min 30 s |"));
+        assert!(markdown.contains("| Minimum voltage | 22.70 V |"));
+        assert!(markdown.contains("| Peak current | 12.50 A |"));
+        assert!(markdown.contains("| ESC 1 | 55.00 degC |"));
+        assert!(markdown.contains("| ESC 2 | no data |"));
+        assert!(markdown.contains("| thermal_img | 2 |"));
+        assert!(markdown.contains("| power | 14 | 1000 ms | 1 | 30 s | 30 s |"));
+
+        let html = build_report(&mission_dir).unwrap().to_html();
+        assert!(html.contains("<td>Peak power</td><td>10.00 W</td>"));
+    }
+}
@@ -0,0 +1,359 @@
+use std::fs;
+use std::path::Path;
+use chrono::{DateTime, SecondsFormat, Utc};
+use json::{array, JsonValue, object};
+use serde_json::Value;
+use crate::mission_data::{get_mission_title, get_segments, get_stored_sources, SourceKind};
+
+// GLOBAL_POSITION_INT messages of the autopilot
+const POSITION_SOURCE: &str = "global_position";
+
+// Images without a geotag sidecar are placed at the closest position within this time of their capture
+const IMAGE_POSITION_MAX_MS: i64 = 2000;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TrackFormat {
+    Gpx,
+    Kml,
+    GeoJson
+}
+
+impl TrackFormat {
+    pub fn from_name(name: &str) -> Option<TrackFormat> {
+        return match name.to_lowercase().as_str() {
+            "gpx" => {Some(TrackFormat::Gpx)}
+            "kml" => {Some(TrackFormat::Kml)}
+            "geojson" => {Some(TrackFormat::GeoJson)}
+            _ => {None}
+        };
+    }
+
+    pub fn extension(&self) -> &'static str {
+        return match self {
+            TrackFormat::Gpx => {"gpx"}
+            TrackFormat::Kml => {"kml"}
+            TrackFormat::GeoJson => {"geojson"}
+        };
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        return match self {
+            TrackFormat::Gpx => {"application/gpx+xml"}
+            TrackFormat::Kml => {"application/vnd.google-earth.kml+xml"}
+            TrackFormat::GeoJson => {"application/geo+json"}
+        };
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrackPoint {
+    pub time: DateTime<Utc>,
+    pub latitude: f64,
+    pub longitude: f64,
+    // Above mean sea level
+    pub altitude_m: Option<f64>,
+    // Above the home position
+    pub relative_altitude_m: Option<f64>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImagePoint {
+    pub source: String,
+    // Relative to the mission directory, so exports saved in it link to the image
+    pub file: String,
+    pub position: TrackPoint
+}
+
+// Flight track and image capture points of a mission, exported as GPX, KML or GeoJSON
+pub struct MissionTrack {
+    pub name: String,
+    pub points: Vec<TrackPoint>,
+    pub images: Vec<ImagePoint>
+}
+
+impl MissionTrack {
+    pub fn export(&self, format: TrackFormat) -> String {
+        return match format {
+            TrackFormat::Gpx => {self.to_gpx()}
+            TrackFormat::Kml => {self.to_kml()}
+            TrackFormat::GeoJson => {self.to_geojson().pretty(2)}
+        };
+    }
+
+    // Images as waypoints linked to their files, the flight as one track segment
+    pub fn to_gpx(&self) -> String {
+        let mut gpx = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <gpx version=\"1.1\" creator=\"FlightCode\" xmlns=\"http://www.topografix.com/GPX/1/1\">\n");
+        gpx.push_str(&format!("<metadata><name>{}</name></metadata>\n", escape_xml(&self.name)));
+
+        for image in self.images.iter() {
+            gpx.push_str(&format!("<wpt lat=\"{}\" lon=\"{}\">{}<time>{}</time><name>{}</name><link href=\"{}\"><text>{}</text></link><type>{}</type></wpt>\n",
+                                  image.position.latitude, image.position.longitude, get_gpx_elevation(&image.position),
+                                  format_time(&image.position.time), escape_xml(&image.file), escape_xml(&image.file),
+                                  escape_xml(&image.file), escape_xml(&image.source)));
+        }
+
+        gpx.push_str(&format!("<trk><name>{}</name><trkseg>\n", escape_xml(&self.name)));
+        for point in self.points.iter() {
+            gpx.push_str(&format!("<trkpt lat=\"{}\" lon=\"{}\">{}<time>{}</time></trkpt>\n",
+                                  point.latitude, point.longitude, get_gpx_elevation(point), format_time(&point.time)));
+        }
+        gpx.push_str("</trkseg></trk>\n</gpx>\n");
+
+        return gpx;
+    }
+
+    // The flight as a gx:Track, which keeps the time of every position, and images as placemarks linked to their files
+    pub fn to_kml(&self) -> String {
+        let mut kml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <kml xmlns=\"http://www.opengis.net/kml/2.2\" xmlns:gx=\"http://www.google.com/kml/ext/2.2\">\n<Document>\n");
+        kml.push_str(&format!("<name>{}</name>\n", escape_xml(&self.name)));
+
+        kml.push_str("<Placemark><name>Flight track</name><gx:Track><altitudeMode>absolute</altitudeMode>\n");
+        for point in self.points.iter() {
+            kml.push_str(&format!("<when>{}</when>\n", format_time(&point.time)));
+        }
+        for point in self.points.iter() {
+            kml.push_str(&format!("<gx:coord>{} {} {}</gx:coord>\n", point.longitude, point.latitude, point.altitude_m.unwrap_or(0.0)));
+        }
+        kml.push_str("</gx:Track></Placemark>\n");
+
+        for image in self.images.iter() {
+            let position = &image.position;
+            kml.push_str(&format!("<Placemark><name>{}</name><description><![CDATA[<a href=\"{}\">{}</a>]]></description>\
+                                   <TimeStamp><when>{}</when></TimeStamp><ExtendedData>{}</ExtendedData>\
+                                   <Point><altitudeMode>absolute</altitudeMode><coordinates>{},{},{}</coordinates></Point></Placemark>\n",
+                                  escape_xml(&image.file), image.file, image.file, format_time(&position.time),
+                                  get_kml_data(image), position.longitude, position.latitude, position.altitude_m.unwrap_or(0.0)));
+        }
+
+        kml.push_str("</Document>\n</kml>\n");
+        return kml;
+    }
+
+    // The flight as a LineString with the time of each coordinate in coordTimes, images as Point features
+    pub fn to_geojson(&self) -> JsonValue {
+        let mut features = array![object!{
+            type: "Feature",
+            geometry: object!{
+                type: "LineString",
+                coordinates: self.points.iter().map(get_geojson_coordinates).collect::<Vec<JsonValue>>()
+            },
+            properties: object!{
+                name: self.name.clone(),
+                coordTimes: self.points.iter().map(|point| format_time(&point.time)).collect::<Vec<String>>(),
+                relative_altitudes: self.points.iter().map(|point| point.relative_altitude_m).collect::<Vec<Option<f64>>>()
+            }
+        }];
+
+        for image in self.images.iter() {
+            let _ = features.push(object!{
+                type: "Feature",
+                geometry: object!{
+                    type: "Point",
+                    coordinates: get_geojson_coordinates(&image.position)
+                },
+                properties: object!{
+                    source: image.source.clone(),
+                    file: image.file.clone(),
+                    time: format_time(&image.position.time),
+                    altitude: image.position.altitude_m,
+                    relative_altitude: image.position.relative_altitude_m
+                }
+            });
+        }
+
+        return object!{
+            type: "FeatureCollection",
+            features: features
+        };
+    }
+}
+
+fn format_time(time: &DateTime<Utc>) -> String {
+    return time.to_rfc3339_opts(SecondsFormat::Millis, true);
+}
+
+fn escape_xml(text: &str) -> String {
+    return text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;");
+}
+
+fn get_gpx_elevation(point: &TrackPoint) -> String {
+    return point.altitude_m.map_or(String::new(), |altitude| format!("<ele>{}</ele>", altitude));
+}
+
+fn get_kml_data(image: &ImagePoint) -> String {
+    let mut data = format!("<Data name=\"source\"><value>{}</value></Data><Data name=\"file\"><value>{}</value></Data>",
+                           escape_xml(&image.source), escape_xml(&image.file));
+    if let Some(relative_altitude) = image.position.relative_altitude_m {
+        data.push_str(&format!("<Data name=\"relative_altitude\"><value>{}</value></Data>", relative_altitude));
+    }
+    return data;
+}
+
+// Longitude first, as GeoJSON orders them
+fn get_geojson_coordinates(point: &TrackPoint) -> JsonValue {
+    return match point.altitude_m {
+        Some(altitude) => array![point.longitude, point.latitude, altitude],
+        None => array![point.longitude, point.latitude]
+    };
+}
+
+pub fn read_track(mission_dir: &Path) -> Result<MissionTrack, String> {
+    if !mission_dir.is_dir() {
+        return Err(format!("{} is not a mission directory", mission_dir.display()));
+    }
+
+    let points = read_positions(mission_dir);
+    let images = get_stored_sources(mission_dir).into_iter()
+        .filter(|source| source.kind == SourceKind::Files)
+        .flat_map(|source| read_image_points(mission_dir, &source.name, &points))
+        .collect();
+
+    return Ok(MissionTrack { name: get_mission_title(mission_dir), points, images });
+}
+
+// Positions in time order. The autopilot reports 0, 0 until it has a fix, those are left out
+fn read_positions(mission_dir: &Path) -> Vec<TrackPoint> {
+    let mut points: Vec<TrackPoint> = get_segments(&mission_dir.join(POSITION_SOURCE), POSITION_SOURCE).iter()
+        .filter_map(|(_, segment)| fs::read_to_string(segment).ok())
+        .flat_map(|segment| segment.lines().filter_map(|line| serde_json::from_str::<Value>(line).ok()).collect::<Vec<Value>>())
+        .filter_map(|record| {
+            let time = DateTime::parse_from_rfc3339(record["timestamp"].as_str()?).ok()?.with_timezone(&Utc);
+            let data = &record["data"];
+            let (lat, lon) = (data["lat"].as_i64()?, data["lon"].as_i64()?);
+            if lat == 0 && lon == 0 {
+                return None;
+            }
+
+            return Some(TrackPoint {
+                time,
+                latitude: lat as f64 / 1e7,
+                longitude: lon as f64 / 1e7,
+                altitude_m: data["alt"].as_i64().map(|alt| alt as f64 / 1000.0),
+                relative_altitude_m: data["relative_alt"].as_i64().map(|relative_alt| relative_alt as f64 / 1000.0)
+            });
+        })
+        .collect();
+
+    points.sort_by_key(|point| point.time);
+    return points;
+}
+
+// Placed by the geotag sidecar the image writer stores next to them as <stem>.json. JPEGs and TIFFs carry their geotag
+// embedded, they and images tagged without a position fix are placed from the track
+fn read_image_points(mission_dir: &Path, source_name: &str, points: &[TrackPoint]) -> Vec<ImagePoint> {
+    let source_dir = mission_dir.join(source_name);
+    let mut files: Vec<String> = fs::read_dir(&source_dir).into_iter().flatten().flatten()
+        .filter_map(|entry| entry.file_name().to_str().map(|file| file.to_string()))
+        .filter(|file| !file.ends_with(".json") && !file.ends_with(".tmp"))
+        .collect();
+    files.sort();
+
+    return files.into_iter()
+        .filter_map(|file| {
+            let stem = file.rsplit_once('.').map_or(file.as_str(), |(stem, _)| stem);
+            let time = DateTime::parse_from_rfc3339(stem.split('_').next().unwrap_or(stem)).ok()?.with_timezone(&Utc);
+
+            let geotag = fs::read_to_string(source_dir.join(format!("{}.json", stem))).ok()
+                .and_then(|sidecar| serde_json::from_str::<Value>(&sidecar).ok())
+                .unwrap_or(Value::Null);
+            let position = match (geotag["latitude"].as_f64(), geotag["longitude"].as_f64()) {
+                (Some(latitude), Some(longitude)) => TrackPoint {
+                    time,
+                    latitude,
+                    longitude,
+                    altitude_m: geotag["altitude"].as_f64(),
+                    relative_altitude_m: geotag["relative_altitude"].as_f64()
+                },
+                _ => TrackPoint { time, ..get_closest_position(points, time)?.clone() }
+            };
+
+            return Some(ImagePoint { source: source_name.to_string(), file: format!("{}/{}", source_name, file), position });
+        })
+        .collect();
+}
+
+fn get_closest_position(points: &[TrackPoint], time: DateTime<Utc>) -> Option<&TrackPoint> {
+    return points.iter()
+        .min_by_key(|point| (point.time - time).num_milliseconds().abs())
+        .filter(|point| (point.time - time).num_milliseconds().abs() <= IMAGE_POSITION_MAX_MS);
+}
+
+// Images are written through the image writer of the vehicle, as their sidecars are stored on it
+#[cfg(all(test, feature = "vehicle"))]
+mod track_export_tests {
+    use std::fs;
+    use std::path::Path;
+    use std::sync::Arc;
+    use bytes::Bytes;
+    use crate::application::data_manage::{ContentType, DataSource, IncomingData};
+    use crate::application::data_manage::image_writer::{ImageWrite, ImageWriter, ImageWriterConfig};
+    use crate::mission_data::integrity::IntegrityDigests;
+    use crate::application::geotag::GeoTag;
+    use crate::mission_data::track_export::{read_track, TrackFormat};
+    use crate::test_support::TempDir;
+
+    fn image_write(mission_dir: &Path, file: &str, content_type: ContentType, geotag: Option<GeoTag>) -> ImageWrite {
+        let mut incoming_data = IncomingData::new(DataSource::new("thermal_img"), None, Some(Bytes::from_static(b"image")));
+        if let Some(geotag) = geotag {
+            incoming_data = incoming_data.with_geotag(geotag);
+        }
+
+        return ImageWrite {
+            storage_dir: mission_dir.display().to_string(),
+            file: format!("{}/thermal_img/{}", mission_dir.display(), file),
+            extension: content_type.extension().to_string(),
+            content_type,
+            incoming_data: Arc::new(incoming_data)
+        };
+    }
+
+    #[test]
+    fn exports_track_and_image_points() {
+        let mission_dir = TempDir::new("track");
+        for source in ["global_position", "thermal_img"] {
+            fs::create_dir_all(mission_dir.join(source)).unwrap();
+        }
+        fs::write(mission_dir.join("manifest.json"), r#"{"id": 3, "name": "north-field"}"#).unwrap();
+
+        // The first position is before the autopilot has a fix
+        let positions = [(0, 0, 0), (1, 498075000, -971366000), (2, 498076000, -971367000), (3, 498077000, -971368000)].iter()
+            .map(|(second, lat, lon)| format!(r#"{{"timestamp":"2024-05-01T10:00:0{}+00:00","data":{{"lat":{},"lon":{},"alt":{},"relative_alt":{}}}}}"#,
+                                             second, lat, lon, 232000 + second * 10000, second * 10000))
+            .collect::<Vec<String>>();
+        fs::write(mission_dir.join("global_position/global_position-00000.jsonl"), positions.join("\n")).unwrap();
+
+        // Sidecars as the image writer stores them. The geotag of the second image has no fix and is placed from the
+        // track like the TIFF, which has its geotag embedded. The last has no position within reach
+        let image_writer = ImageWriter::spawn(&ImageWriterConfig { queue_capacity: 8, stats_interval_ms: 0 }, Arc::new(IntegrityDigests::new()));
+        let geotag = GeoTag { latitude: Some(49.81), longitude: Some(-97.14), altitude_m: Some(250.5), relative_altitude_m: Some(18.5), ..Default::default() };
+        image_writer.queue(image_write(&mission_dir, "2024-05-01T10:00:02.250+00:00", ContentType::Png, Some(geotag)));
+        image_writer.queue(image_write(&mission_dir, "2024-05-01T10:00:02.750+00:00", ContentType::Png, Some(GeoTag::default())));
+        image_writer.queue(image_write(&mission_dir, "2024-05-01T10:00:03+00:00", ContentType::Tiff, None));
+        image_writer.queue(image_write(&mission_dir, "2024-05-01T10:00:09+00:00", ContentType::Png, None));
+        image_writer.stop().unwrap();
+        assert!(mission_dir.join("thermal_img/2024-05-01T10:00:02.250+00:00.json").exists());
+
+        let track = read_track(&mission_dir).unwrap();
+        assert_eq!(track.points.len(), 3);
+        let positions: Vec<(f64, Option<f64>)> = track.images.iter()
+            .map(|image| (image.position.latitude, image.position.relative_altitude_m))
+            .collect();
+        assert_eq!(positions, [(49.81, Some(18.5)), (49.8077, Some(30.0)), (49.8077, Some(30.0))]);
+
+        let gpx = track.export(TrackFormat::Gpx);
+        assert!(gpx.contains("<trkpt lat=\"49.8075\" lon=\"-97.1366\"><ele>242</ele><time>2024-05-01T10:00:01.000Z</time></trkpt>"));
+        assert!(gpx.contains("<wpt lat=\"49.81\" lon=\"-97.14\"><ele>250.5</ele><time>2024-05-01T10:00:02.250Z</time>"));
+        assert!(gpx.contains("<link href=\"thermal_img/2024-05-01T10:00:02.250+00:00.png\">"));
+
+        let kml = track.export(TrackFormat::Kml);
+        assert!(kml.contains("<when>2024-05-01T10:00:03.000Z</when>\n<gx:coord>"));
+        assert!(kml.contains("<coordinates>-97.1368,49.8077,262</coordinates>"));
+
+        let geojson = json::parse(&track.export(TrackFormat::GeoJson)).unwrap();
+        assert_eq!(geojson["features"][0]["geometry"]["coordinates"][2].dump(), "[-97.1368,49.8077,262]");
+        assert_eq!(geojson["features"][0]["properties"]["coordTimes"][0], "2024-05-01T10:00:01.000Z");
+        assert_eq!(geojson["features"][1]["properties"]["file"], "thermal_img/2024-05-01T10:00:02.250+00:00.png");
+    }
+}
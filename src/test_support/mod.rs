@@ -0,0 +1,41 @@
+use std::env;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process;
+
+// Directory of a test below the system temp directory, removed again when dropped so it is cleaned up even when an
+// assertion fails
+pub struct TempDir {
+    path: PathBuf
+}
+
+impl TempDir {
+    // Unique to the test process, whatever an earlier run left behind is removed first
+    pub fn new(name: &str) -> Self {
+        let path = env::temp_dir().join(format!("flightcode-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        return Self { path };
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        return &self.path;
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        return &self.path;
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}